@@ -13,12 +13,13 @@
 // You should have received a copy of the GNU Lesser General Public License along with Viola Ex Machina.
 // If not, see <https://www.gnu.org/licenses/>.
 
-use crate::{ViolaExMachinaParams, InstrumentType, Articulation};
-use synth::director::Message;
+use crate::{ViolaExMachinaParams, InstrumentType, Articulation, Body};
+use synth::director::{Message, arco_attack_time, release_time};
 use nih_plug::prelude::*;
 use nih_plug_egui::{create_egui_editor, egui};
 use egui_commonmark::{CommonMarkCache, CommonMarkViewer};
 use std::sync::{Arc, Mutex, mpsc};
+use std::sync::atomic::{AtomicU32, Ordering};
 
 #[derive(PartialEq)]
 enum Panel {
@@ -28,18 +29,21 @@ enum Panel {
 }
 
 pub struct UIState {
-    current_panel: Panel
+    current_panel: Panel,
+    test_tone: bool
 }
 
 impl UIState {
     pub fn new() -> Self {
         Self {
-            current_panel: Panel::Controls
+            current_panel: Panel::Controls,
+            test_tone: false
         }
     }
 }
 
-pub fn draw_editor(params: Arc<ViolaExMachinaParams>, sender: Arc<Mutex<mpsc::Sender<Message>>>, state: Arc<Mutex<UIState>>) -> Option<Box<dyn Editor>> {
+pub fn draw_editor(params: Arc<ViolaExMachinaParams>, sender: Arc<Mutex<mpsc::Sender<Message>>>, state: Arc<Mutex<UIState>>,
+                   correlation: Arc<AtomicU32>) -> Option<Box<dyn Editor>> {
     create_egui_editor(
         params.editor_state.clone(),
         (),
@@ -73,11 +77,12 @@ pub fn draw_editor(params: Arc<ViolaExMachinaParams>, sender: Arc<Mutex<mpsc::Se
                     });
                 });
                 egui::CentralPanel::default().show_inside(ui, |ui| {
-                    let state = state.lock().unwrap();
+                    let mut state = state.lock().unwrap();
+                    let state = &mut *state;
                     match state.current_panel {
-                        Panel::Controls => draw_controls_panel(ui, &params, &sender, setter),
+                        Panel::Controls => draw_controls_panel(ui, &params, &sender, &correlation, setter),
                         Panel::Help => draw_help_panel(ui),
-                        Panel::About => draw_about_panel(ui)
+                        Panel::About => draw_about_panel(ui, &sender, &mut state.test_tone)
                     }
                 });
             });
@@ -85,7 +90,8 @@ pub fn draw_editor(params: Arc<ViolaExMachinaParams>, sender: Arc<Mutex<mpsc::Se
     )
 }
 
-fn draw_controls_panel(ui: &mut egui::Ui, params: &Arc<ViolaExMachinaParams>, sender: &Arc<Mutex<mpsc::Sender<Message>>>, setter: &ParamSetter) {
+fn draw_controls_panel(ui: &mut egui::Ui, params: &Arc<ViolaExMachinaParams>, sender: &Arc<Mutex<mpsc::Sender<Message>>>,
+                       correlation: &Arc<AtomicU32>, setter: &ParamSetter) {
     let mut new_instrument_type = params.instrument_type.value();
     let mut new_instrument_count = params.instrument_count.value();
     let mut new_articulation = params.articulation.value();
@@ -102,7 +108,30 @@ fn draw_controls_panel(ui: &mut egui::Ui, params: &Arc<ViolaExMachinaParams>, se
         ui.add_space(10.0);
         ui.label("Number");
         ui.add(egui::Slider::new(&mut new_instrument_count, 1..=8).handle_shape(egui::style::HandleShape::Circle));
+        ui.add_space(10.0);
+        ui.label("Body");
+        let mut new_body = params.body.value();
+        egui::ComboBox::from_id_salt("Body").selected_text(new_body.label()).show_ui(ui, |ui| {
+            for body in [Body::Default, Body::Violin, Body::Viola, Body::Cello, Body::Bass] {
+                ui.selectable_value(&mut new_body, body, body.label());
+            }
+        });
+        if params.body.value() != new_body {
+            setter.begin_set_parameter(&params.body);
+            setter.set_parameter(&params.body, new_body);
+            setter.end_set_parameter(&params.body);
+        }
+        ui.add_space(10.0);
+        let mut adopt_type_defaults = params.adopt_type_defaults.value();
+        if ui.checkbox(&mut adopt_type_defaults, "Adopt Type Defaults").changed() {
+            setter.begin_set_parameter(&params.adopt_type_defaults);
+            setter.set_parameter(&params.adopt_type_defaults, adopt_type_defaults);
+            setter.end_set_parameter(&params.adopt_type_defaults);
+        }
     });
+    if params.instrument_type.value() != new_instrument_type && params.adopt_type_defaults.value() {
+        apply_type_defaults(params, setter, new_instrument_type);
+    }
     if params.instrument_type.value() != new_instrument_type || params.instrument_count.value() != new_instrument_count {
         setter.begin_set_parameter(&params.instrument_type);
         setter.set_parameter(&params.instrument_type, new_instrument_type);
@@ -154,21 +183,73 @@ fn draw_controls_panel(ui: &mut egui::Ui, params: &Arc<ViolaExMachinaParams>, se
 
         ui.spacing_mut().slider_width = 200.0;
         draw_param_slider(ui, &params.dynamics, setter);
+        draw_param_slider(ui, &params.ensemble_tightness, setter);
         draw_param_slider(ui, &params.vibrato, setter);
         draw_param_slider(ui, &params.bow_position, setter);
         draw_param_slider(ui, &params.bow_noise, setter);
+        draw_param_slider(ui, &params.air, setter);
+        ui.with_layout(egui::Layout::right_to_left(egui::Align::TOP), |ui| {
+            ui.label(params.tilt_eq.name());
+        });
+        let mut tilt_eq = params.tilt_eq.value();
+        if ui.add(egui::Slider::new(&mut tilt_eq, -1.0..=1.0).handle_shape(egui::style::HandleShape::Circle).max_decimals(3)).changed() {
+            setter.begin_set_parameter(&params.tilt_eq);
+            setter.set_parameter(&params.tilt_eq, tilt_eq);
+            setter.end_set_parameter(&params.tilt_eq);
+        }
+        ui.end_row();
         draw_param_slider(ui, &params.release_rate, setter);
+        for param in [&params.attack_scale, &params.release_scale] {
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::TOP), |ui| {
+                ui.label(param.name());
+            });
+            let mut scale = param.value();
+            if ui.add(egui::Slider::new(&mut scale, 0.25..=20.0).logarithmic(true).handle_shape(egui::style::HandleShape::Circle).max_decimals(2)).changed() {
+                setter.begin_set_parameter(param);
+                setter.set_parameter(param, scale);
+                setter.end_set_parameter(param);
+            }
+            ui.end_row();
+        }
+        ui.with_layout(egui::Layout::right_to_left(egui::Align::TOP), |ui| {
+            ui.label(params.velocity_curve.name());
+        });
+        let mut velocity_curve = params.velocity_curve.value();
+        if ui.add(egui::Slider::new(&mut velocity_curve, 0.25..=4.0).logarithmic(true).handle_shape(egui::style::HandleShape::Circle).max_decimals(2)).changed() {
+            setter.begin_set_parameter(&params.velocity_curve);
+            setter.set_parameter(&params.velocity_curve, velocity_curve);
+            setter.end_set_parameter(&params.velocity_curve);
+        }
+        ui.end_row();
         draw_param_slider(ui, &params.stereo_width, setter);
         ui.with_layout(egui::Layout::right_to_left(egui::Align::TOP), |ui| {
-            ui.label("Time Spread (ms)");
+            ui.label(params.pan_center.name());
         });
-        let mut spread = params.time_spread.value();
-        if ui.add(egui::Slider::new(&mut spread, 0..=100).handle_shape(egui::style::HandleShape::Circle)).changed() {
-            setter.begin_set_parameter(&params.time_spread);
-            setter.set_parameter(&params.time_spread, spread);
-            setter.end_set_parameter(&params.time_spread);
+        let mut pan_center = params.pan_center.value();
+        if ui.add(egui::Slider::new(&mut pan_center, -1.0..=1.0).handle_shape(egui::style::HandleShape::Circle).max_decimals(3)).changed() {
+            setter.begin_set_parameter(&params.pan_center);
+            setter.set_parameter(&params.pan_center, pan_center);
+            setter.end_set_parameter(&params.pan_center);
         }
         ui.end_row();
+        ui.with_layout(egui::Layout::right_to_left(egui::Align::TOP), |ui| {
+            ui.label("Time Spread (ms)");
+        });
+        ui.horizontal(|ui| {
+            let mut spread = params.time_spread.value();
+            if ui.add(egui::Slider::new(&mut spread, 0..=100).handle_shape(egui::style::HandleShape::Circle)).changed() {
+                setter.begin_set_parameter(&params.time_spread);
+                setter.set_parameter(&params.time_spread, spread);
+                setter.end_set_parameter(&params.time_spread);
+            }
+            let mut tight = params.tight.value();
+            if ui.checkbox(&mut tight, "Tight").changed() {
+                setter.begin_set_parameter(&params.tight);
+                setter.set_parameter(&params.tight, tight);
+                setter.end_set_parameter(&params.tight);
+            }
+        });
+        ui.end_row();
         let mut harmonics = params.harmonics.value();
         if ui.checkbox(&mut harmonics, "Harmonics").changed() {
             setter.begin_set_parameter(&params.harmonics);
@@ -190,6 +271,105 @@ fn draw_controls_panel(ui: &mut egui::Ui, params: &Arc<ViolaExMachinaParams>, se
                 setter.end_set_parameter(&params.polyphonic);
             }
         });
+        ui.end_row();
+        let mut invert_polarity = params.invert_polarity.value();
+        if ui.checkbox(&mut invert_polarity, "Invert Polarity").changed() {
+            setter.begin_set_parameter(&params.invert_polarity);
+            setter.set_parameter(&params.invert_polarity, invert_polarity);
+            setter.end_set_parameter(&params.invert_polarity);
+        }
+        ui.with_layout(egui::Layout::left_to_right(egui::Align::TOP), |ui| {
+            let mut compressor = params.compressor.value();
+            if ui.checkbox(&mut compressor, "Compressor").changed() {
+                setter.begin_set_parameter(&params.compressor);
+                setter.set_parameter(&params.compressor, compressor);
+                setter.end_set_parameter(&params.compressor);
+            }
+            ui.add_space(25.0);
+            let mut synth_bypass = params.synth_bypass.value();
+            if ui.checkbox(&mut synth_bypass, "Synth Bypass").changed() {
+                setter.begin_set_parameter(&params.synth_bypass);
+                setter.set_parameter(&params.synth_bypass, synth_bypass);
+                setter.end_set_parameter(&params.synth_bypass);
+            }
+        });
+        ui.end_row();
+        let mut resonator_mode = params.resonator_mode.value();
+        if ui.checkbox(&mut resonator_mode, "Resonator Mode").changed() {
+            setter.begin_set_parameter(&params.resonator_mode);
+            setter.set_parameter(&params.resonator_mode, resonator_mode);
+            setter.end_set_parameter(&params.resonator_mode);
+        }
+        let mut separate_outputs = params.separate_outputs.value();
+        if ui.checkbox(&mut separate_outputs, "Separate Outputs").changed() {
+            setter.begin_set_parameter(&params.separate_outputs);
+            setter.set_parameter(&params.separate_outputs, separate_outputs);
+            setter.end_set_parameter(&params.separate_outputs);
+        }
+    });
+    ui.add_space(10.0);
+    draw_envelope_preview(ui, params);
+    ui.add_space(10.0);
+    draw_correlation_meter(ui, correlation);
+}
+
+/// Draw a meter showing the correlation between the left and right channels.  A value of 1 means
+/// the output is mono.  Lower values mean the ensemble is spread more widely.
+fn draw_correlation_meter(ui: &mut egui::Ui, correlation: &Arc<AtomicU32>) {
+    let correlation = f32::from_bits(correlation.load(Ordering::Relaxed)).clamp(-1.0, 1.0);
+    ui.horizontal(|ui| {
+        ui.label("Correlation");
+        let (response, painter) = ui.allocate_painter(egui::vec2(200.0, 12.0), egui::Sense::hover());
+        let rect = response.rect;
+        let center = rect.center().x;
+        let x = center + 0.5*rect.width()*correlation;
+        let bar = egui::Rect::from_x_y_ranges(center.min(x)..=center.max(x), rect.y_range());
+        painter.rect_filled(bar, 0.0, ui.visuals().widgets.active.fg_stroke.color);
+        painter.line_segment([egui::pos2(center, rect.top()), egui::pos2(center, rect.bottom())], ui.visuals().widgets.noninteractive.bg_stroke);
+        painter.rect_stroke(rect, 0.0, ui.visuals().widgets.noninteractive.bg_stroke, egui::StrokeKind::Inside);
+        ui.label(format!("{:+.2}", correlation));
+    });
+}
+
+/// Set the parameters that depend on the instrument type to the suggested values for a type.
+fn apply_type_defaults(params: &Arc<ViolaExMachinaParams>, setter: &ParamSetter, instrument_type: InstrumentType) {
+    let defaults = instrument_type.defaults();
+    for (param, value) in [(&params.vibrato, defaults.vibrato), (&params.bow_position, defaults.bow_position),
+                           (&params.bow_noise, defaults.bow_noise), (&params.release_rate, defaults.release_rate)] {
+        setter.begin_set_parameter(param);
+        setter.set_parameter(param, value);
+        setter.end_set_parameter(param);
+    }
+}
+
+/// Draw the envelope of an arco note played at medium velocity, so the effect of the release rate
+/// and the attack and release scales can be seen while adjusting them.
+fn draw_envelope_preview(ui: &mut egui::Ui, params: &Arc<ViolaExMachinaParams>) {
+    let attack = arco_attack_time(0.5) as f32*params.attack_scale.value();
+    let release = release_time(params.release_rate.value()) as f32*params.release_scale.value();
+    let hold = 10000.0;
+    let total = attack+hold+release;
+    ui.horizontal(|ui| {
+        let (response, painter) = ui.allocate_painter(egui::vec2(300.0, 40.0), egui::Sense::hover());
+        let rect = response.rect;
+        painter.rect_stroke(rect, 0.0, ui.visuals().widgets.noninteractive.bg_stroke, egui::StrokeKind::Inside);
+        let mut points = Vec::with_capacity(101);
+        for i in 0..=100 {
+            let t = total*i as f32/100.0;
+            let envelope = if t < attack {
+                0.5-0.5*(std::f32::consts::PI*t/attack).cos()
+            }
+            else if t < attack+hold {
+                1.0
+            }
+            else {
+                0.5+0.5*(std::f32::consts::PI*(t-attack-hold)/release).cos()
+            };
+            points.push(egui::pos2(rect.left()+rect.width()*i as f32/100.0, rect.bottom()-envelope*rect.height()));
+        }
+        painter.add(egui::Shape::line(points, ui.visuals().widgets.active.fg_stroke));
+        ui.add_space(10.0);
+        ui.label(format!("Release: {} ms", 1000*release as i32/synth::SAMPLE_RATE));
     });
 }
 
@@ -214,7 +394,7 @@ fn draw_help_panel(ui: &mut egui::Ui) {
     });
 }
 
-fn draw_about_panel(ui: &mut egui::Ui) {
+fn draw_about_panel(ui: &mut egui::Ui, sender: &Arc<Mutex<mpsc::Sender<Message>>>, test_tone: &mut bool) {
     ui.vertical_centered(|ui| {
         ui.add_space(30.0);
         ui.label(egui::RichText::new("Viola Ex Machina").size(36.0).italics());
@@ -222,5 +402,13 @@ fn draw_about_panel(ui: &mut egui::Ui) {
         ui.label(egui::RichText::new("Copyright 2026 by Peter Eastman").size(14.0));
         ui.add_space(12.0);
         ui.hyperlink("https://github.com/peastman/ViolaExMachina");
+
+        // The test tone replaces the output with a sine wave, which is useful for checking routing
+        // and levels without needing MIDI.
+
+        ui.add_space(30.0);
+        if ui.checkbox(test_tone, "Test Tone (440 Hz)").changed() {
+            let _ = sender.lock().unwrap().send(Message::SetTestTone {enabled: *test_tone, frequency: 440.0});
+        }
     });
 }
@@ -0,0 +1,120 @@
+// Copyright 2026 by Peter Eastman
+//
+// This file is part of Viola Ex Machina.
+//
+// Viola Ex Machina is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Lesser General Public License as published by the Free Software Foundation, either
+// version 2.1 of the License, or (at your option) any later version.
+//
+// Viola Ex Machina is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License along with Viola Ex Machina.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use synth::director::Message;
+use nih_plug::prelude::*;
+
+/// The MIDI control change number for the sostenuto pedal.
+const SOSTENUTO_CC: u8 = 66;
+
+/// This converts the NoteEvents received from the host into Messages for the Director.  It is kept
+/// separate from the plugin so the logic can be tested without a host.
+///
+/// To support MPE controllers, which send each note on its own channel, pitch bend is only applied
+/// from the channel of the most recent note.  The last bend received on every channel is recorded,
+/// so a note starts with the bend its channel was set to before the note began.  Channel pressure
+/// is not translated, since the Director has no control for it.
+///
+/// All the events at a single sample are resolved together, so the result does not depend on the
+/// order the host delivers them in.  NoteOffs are sent after all NoteOns, and a NoteOff for a note
+/// that is also started at the same sample is dropped.  When one key is released at the same moment
+/// another is pressed, the Director therefore always sees the new note first and plays it legato.
+/// In monophonic mode it then ignores the NoteOff, since that note is no longer the current one.
+///
+/// Note velocities are shaped by a curve, which raises them to a power (gamma).  Values below 1
+/// make soft playing louder, and values above 1 make it softer.
+pub struct EventTranslator {
+    new_notes: Vec<u8>,
+    note_offs: Vec<(u8, f32)>,
+    velocity_curve: f32,
+    active_channel: Option<u8>,
+    channel_bend: [f32; 16],
+    applied_bend: f32
+}
+
+impl EventTranslator {
+    pub fn new() -> Self {
+        Self {
+            new_notes: Vec::with_capacity(16),
+            note_offs: Vec::with_capacity(16),
+            velocity_curve: 1.0,
+            active_channel: None,
+            channel_bend: [0.5; 16],
+            applied_bend: 0.5
+        }
+    }
+
+    /// Set the exponent applied to note velocities.  The default of 1 leaves them unchanged.
+    pub fn set_velocity_curve(&mut self, gamma: f32) {
+        self.velocity_curve = gamma;
+    }
+
+    /// Send a pitch bend to the Director, given as a value between 0 and 1 like MIDI.
+    fn apply_bend(&mut self, value: f32, messages: &mut Vec<Message>) {
+        messages.push(Message::SetPitchBend {semitones: 4.0*(value-0.5)});
+        self.applied_bend = value;
+    }
+
+    /// Translate all the events that occur at a single sample.  The resulting Messages are
+    /// appended to messages.
+    pub fn translate_events(&mut self, events: &[NoteEvent<()>], messages: &mut Vec<Message>) {
+        self.new_notes.clear();
+        self.note_offs.clear();
+        for event in events {
+            match event {
+                NoteEvent::NoteOn { note, velocity, channel, .. } => {
+                    let bend = self.channel_bend[(*channel & 0x0f) as usize];
+                    self.active_channel = Some(*channel);
+                    if bend != self.applied_bend {
+                        self.apply_bend(bend, messages);
+                    }
+                    let velocity = if self.velocity_curve == 1.0 {*velocity} else {velocity.powf(self.velocity_curve)};
+                    messages.push(Message::NoteOn {
+                        note_index: *note as i32,
+                        velocity: velocity});
+                    self.new_notes.push(*note);
+                }
+                NoteEvent::NoteOff { note, velocity, .. } => {
+                    self.note_offs.push((*note, *velocity));
+                }
+                NoteEvent::MidiCC { cc: SOSTENUTO_CC, value, .. } => {
+                    messages.push(Message::SetSostenuto {engaged: *value >= 0.5});
+                }
+                NoteEvent::MidiPitchBend { value, channel, .. } => {
+                    self.channel_bend[(*channel & 0x0f) as usize] = *value;
+                    if self.active_channel.is_none() || self.active_channel == Some(*channel) {
+                        self.apply_bend(*value, messages);
+                    }
+                }
+                NoteEvent::Choke { .. } => {
+                    messages.push(Message::AllNotesOff);
+                }
+                NoteEvent::VoiceTerminated { .. } => {
+                    messages.push(Message::AllNotesOff);
+                }
+                _ => ()
+            }
+        }
+
+        // If we get both a NoteOn and a NoteOff for the same note at the same time, skip the NoteOff
+        // to allow legato playing.
+
+        for (note, velocity) in &self.note_offs {
+            if !self.new_notes.contains(note) {
+                messages.push(Message::NoteOff {note_index: *note as i32, velocity: *velocity});
+            }
+        }
+    }
+}
@@ -14,35 +14,105 @@
 // If not, see <https://www.gnu.org/licenses/>.
 
 mod editor;
+pub mod events;
 
 use synth;
 use synth::director::{Director, Message};
-use synth::resampler::Resampler;
+use synth::resampler::{Resampler, SincResampler};
+use synth::dither::Ditherer;
 use nih_plug::prelude::*;
 use nih_plug_egui::EguiState;
+use std::collections::VecDeque;
 use std::sync::{Arc, Mutex, mpsc};
-use std::collections::HashSet;
+use std::sync::atomic::{AtomicU32, Ordering};
+use events::EventTranslator;
+
+/// The bit depth dither is designed for.  The plugin outputs floating point and has no way of
+/// knowing how the host will eventually quantize it, so this assumes 24 bit audio.  When rendering
+/// to 16 bits, the host's own dither should be used instead.
+const DITHER_BITS: u32 = 24;
+
+/// Suggested values for parameters whose best setting depends on the instrument type.  When
+/// Adopt Type Defaults is enabled, these are applied whenever the instrument type is changed.
+pub struct TypeDefaults {
+    pub vibrato: f32,
+    pub bow_position: f32,
+    pub bow_noise: f32,
+    pub release_rate: f32
+}
+
+const VIOLIN_DEFAULTS: TypeDefaults = TypeDefaults {vibrato: 0.4, bow_position: 0.5, bow_noise: 0.5, release_rate: 0.5};
+const VIOLA_DEFAULTS: TypeDefaults = TypeDefaults {vibrato: 0.4, bow_position: 0.5, bow_noise: 0.5, release_rate: 0.45};
+const CELLO_DEFAULTS: TypeDefaults = TypeDefaults {vibrato: 0.35, bow_position: 0.55, bow_noise: 0.45, release_rate: 0.4};
+const BASS_DEFAULTS: TypeDefaults = TypeDefaults {vibrato: 0.25, bow_position: 0.6, bow_noise: 0.4, release_rate: 0.3};
+
+/// The settings used by the output compressor (threshold in dBFS and ratio).
+const COMPRESSOR_THRESHOLD: f32 = -18.0;
+const COMPRESSOR_RATIO: f32 = 3.0;
+
+/// The time constant in seconds over which the correlation meter averages.
+const CORRELATION_TIME: f32 = 0.3;
+
+/// In resonator mode, resampled input waits in a queue until the Director needs it.  The number of
+/// samples produced and consumed can differ slightly from moment to moment, so a little extra is
+/// allowed to build up, but never more than this.
+const MAX_INPUT_QUEUE: usize = 16;
+
+/// The number of separate instrument outputs.  This matches the maximum number of instruments.
+const MAX_AUX_OUTPUTS: usize = 8;
 
 pub struct ViolaExMachina {
     params: Arc<ViolaExMachinaParams>,
     director: Arc<Mutex<Director>>,
     sender: Arc<Mutex<mpsc::Sender<Message>>>,
     editor_state: Arc<Mutex<editor::UIState>>,
+    correlation: Arc<AtomicU32>,
+    correlation_sums: (f32, f32, f32),
+    correlation_decay: f32,
+    sample_rate: f32,
     need_resample: bool,
+    hq_resample: bool,
+    reported_latency: u32,
     resample_left: Resampler,
     resample_right: Resampler,
-    last_note: u8,
+    sinc_resample_left: SincResampler,
+    sinc_resample_right: SincResampler,
+    input_resample_left: Resampler,
+    input_resample_right: Resampler,
+    input_queue: VecDeque<(f32, f32)>,
+    aux_resample: Vec<Resampler>,
+    aux_sinc_resample: Vec<SincResampler>,
+    aux_samples: Vec<f32>,
+    dither_left: Ditherer,
+    dither_right: Ditherer,
+    aux_dither: Vec<Ditherer>,
+    translator: EventTranslator,
+    sample_events: Vec<NoteEvent<()>>,
+    messages: Vec<Message>,
     last_articulation: Articulation,
+    last_body: Body,
     last_dynamics: f32,
+    last_ensemble_tightness: f32,
     last_vibrato: f32,
     last_bow_position: f32,
     last_bow_noise: f32,
+    last_air: f32,
+    last_tilt_eq: f32,
     last_release_rate: f32,
+    last_attack_scale: f32,
+    last_release_scale: f32,
     last_stereo_width: f32,
+    last_pan_center: f32,
     last_time_spread: i32,
+    last_tight: bool,
     last_harmonics: bool,
     last_mute: bool,
-    last_polyphonic: bool
+    last_polyphonic: bool,
+    last_invert_polarity: bool,
+    last_compressor: bool,
+    last_synth_bypass: bool,
+    last_resonator_mode: bool,
+    last_separate_outputs: bool
 }
 
 #[derive(Params)]
@@ -55,26 +125,60 @@ struct ViolaExMachinaParams {
     pub instrument_count: IntParam,
     #[id = "articulation"]
     pub articulation: EnumParam<Articulation>,
+    #[id = "body"]
+    pub body: EnumParam<Body>,
     #[id = "dynamics"]
     pub dynamics: FloatParam,
+    #[id = "ensemble_tightness"]
+    pub ensemble_tightness: FloatParam,
     #[id = "vibrato"]
     pub vibrato: FloatParam,
     #[id = "bow_position"]
     pub bow_position: FloatParam,
     #[id = "bow_noise"]
     pub bow_noise: FloatParam,
+    #[id = "air"]
+    pub air: FloatParam,
+    #[id = "tilt_eq"]
+    pub tilt_eq: FloatParam,
     #[id = "release_rate"]
     pub release_rate: FloatParam,
+    #[id = "attack_scale"]
+    pub attack_scale: FloatParam,
+    #[id = "release_scale"]
+    pub release_scale: FloatParam,
+    #[id = "velocity_curve"]
+    pub velocity_curve: FloatParam,
     #[id = "stereo_width"]
     pub stereo_width: FloatParam,
+    #[id = "pan_center"]
+    pub pan_center: FloatParam,
     #[id = "time_spread"]
     pub time_spread: IntParam,
+    #[id = "tight"]
+    pub tight: BoolParam,
     #[id = "harmonics"]
     pub harmonics: BoolParam,
     #[id = "mute"]
     pub mute: BoolParam,
     #[id = "polyphonic"]
-    pub polyphonic: BoolParam
+    pub polyphonic: BoolParam,
+    #[id = "hq_resample"]
+    pub hq_resample: BoolParam,
+    #[id = "dither"]
+    pub dither: BoolParam,
+    #[id = "invert_polarity"]
+    pub invert_polarity: BoolParam,
+    #[id = "compressor"]
+    pub compressor: BoolParam,
+    #[id = "synth_bypass"]
+    pub synth_bypass: BoolParam,
+    #[id = "resonator_mode"]
+    pub resonator_mode: BoolParam,
+    #[id = "separate_outputs"]
+    pub separate_outputs: BoolParam,
+    #[id = "adopt_type_defaults"]
+    pub adopt_type_defaults: BoolParam
 }
 
 #[derive(Copy, Clone, Enum, Debug, PartialEq)]
@@ -89,6 +193,18 @@ pub enum InstrumentType {
     Bass
 }
 
+impl InstrumentType {
+    /// Get the suggested parameter values for this instrument type.
+    pub fn defaults(&self) -> &'static TypeDefaults {
+        match self {
+            InstrumentType::Violin => &VIOLIN_DEFAULTS,
+            InstrumentType::Viola => &VIOLA_DEFAULTS,
+            InstrumentType::Cello => &CELLO_DEFAULTS,
+            InstrumentType::Bass => &BASS_DEFAULTS
+        }
+    }
+}
+
 #[derive(Copy, Clone, Enum, Debug, PartialEq)]
 pub enum Articulation {
     #[id = "arco"]
@@ -107,6 +223,32 @@ pub enum Articulation {
     Tremolo
 }
 
+#[derive(Copy, Clone, Enum, Debug, PartialEq)]
+pub enum Body {
+    #[id = "default"]
+    Default,
+    #[id = "violin"]
+    Violin,
+    #[id = "viola"]
+    Viola,
+    #[id = "cello"]
+    Cello,
+    #[id = "bass"]
+    Bass
+}
+
+impl Body {
+    pub fn label(&self) -> String {
+        match self {
+            Body::Default => String::from("Match Type"),
+            Body::Violin => String::from("Violin"),
+            Body::Viola => String::from("Viola"),
+            Body::Cello => String::from("Cello"),
+            Body::Bass => String::from("Bass")
+        }
+    }
+}
+
 impl Articulation {
     pub fn label(&self) -> String {
         match self {
@@ -129,21 +271,53 @@ impl Default for ViolaExMachina {
             director: Arc::new(Mutex::new(Director::new(synth::InstrumentType::Violin, 1, receiver))),
             sender: Arc::new(Mutex::new(sender)),
             editor_state: Arc::new(Mutex::new(editor::UIState::new())),
+            correlation: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            correlation_sums: (0.0, 0.0, 0.0),
+            correlation_decay: 1.0-1.0/(CORRELATION_TIME*synth::SAMPLE_RATE as f32),
+            sample_rate: synth::SAMPLE_RATE as f32,
             need_resample: false,
+            hq_resample: false,
+            reported_latency: 0,
             resample_left: Resampler::new(synth::SAMPLE_RATE as f32),
             resample_right: Resampler::new(synth::SAMPLE_RATE as f32),
-            last_note: 255,
+            sinc_resample_left: SincResampler::new(synth::SAMPLE_RATE as f32),
+            sinc_resample_right: SincResampler::new(synth::SAMPLE_RATE as f32),
+            input_resample_left: Resampler::from_rate(synth::SAMPLE_RATE as f32),
+            input_resample_right: Resampler::from_rate(synth::SAMPLE_RATE as f32),
+            input_queue: VecDeque::with_capacity(MAX_INPUT_QUEUE+4),
+            aux_resample: vec![],
+            aux_sinc_resample: vec![],
+            aux_samples: vec![],
+            dither_left: Ditherer::new(synth::SAMPLE_RATE as f32, DITHER_BITS),
+            dither_right: Ditherer::new(synth::SAMPLE_RATE as f32, DITHER_BITS),
+            aux_dither: vec![],
+            translator: EventTranslator::new(),
+            sample_events: Vec::with_capacity(16),
+            messages: Vec::with_capacity(16),
             last_articulation: Articulation::Arco,
+            last_body: Body::Default,
             last_dynamics: -1.0,
+            last_ensemble_tightness: -1.0,
             last_vibrato: -1.0,
             last_bow_position: -1.0,
             last_bow_noise: -1.0,
+            last_air: -1.0,
+            last_tilt_eq: -2.0,
             last_release_rate: -1.0,
+            last_attack_scale: -1.0,
+            last_release_scale: -1.0,
             last_stereo_width: -1.0,
+            last_pan_center: -2.0,
             last_time_spread: -1,
+            last_tight: false,
             last_harmonics: false,
             last_mute: false,
-            last_polyphonic: false
+            last_polyphonic: false,
+            last_invert_polarity: false,
+            last_compressor: false,
+            last_synth_bypass: false,
+            last_resonator_mode: false,
+            last_separate_outputs: false
         }
     }
 }
@@ -151,20 +325,37 @@ impl Default for ViolaExMachina {
 impl Default for ViolaExMachinaParams {
     fn default() -> Self {
         let result = Self {
-            editor_state: EguiState::from_size(600, 400),
+            editor_state: EguiState::from_size(600, 705),
             instrument_type: EnumParam::new("Instrument Type", InstrumentType::Violin).non_automatable(),
             instrument_count: IntParam::new("Instruments", 1, IntRange::Linear {min: 1, max: 8}).non_automatable(),
             articulation: EnumParam::new("Articulation", Articulation::Arco),
+            body: EnumParam::new("Body", Body::Default).non_automatable(),
             dynamics: FloatParam::new("Dynamics", 1.0, FloatRange::Linear {min: 0.0, max: 1.0}),
+            ensemble_tightness: FloatParam::new("Ensemble Tightness", 0.5, FloatRange::Linear {min: 0.0, max: 1.0}),
             vibrato: FloatParam::new("Vibrato", 0.4, FloatRange::Linear {min: 0.0, max: 1.0}),
             bow_position: FloatParam::new("Bow Position", 0.5, FloatRange::Linear {min: 0.0, max: 1.0}),
             bow_noise: FloatParam::new("Bow Noise", 0.5, FloatRange::Linear {min: 0.0, max: 1.0}),
+            air: FloatParam::new("Air", 0.0, FloatRange::Linear {min: 0.0, max: 1.0}),
+            tilt_eq: FloatParam::new("Tilt EQ", 0.0, FloatRange::Linear {min: -1.0, max: 1.0}),
             release_rate: FloatParam::new("Release Rate", 0.5, FloatRange::Linear {min: 0.0, max: 1.0}),
+            attack_scale: FloatParam::new("Attack Scale", 1.0, FloatRange::Skewed {min: 0.25, max: 20.0, factor: FloatRange::skew_factor(-2.0)}),
+            release_scale: FloatParam::new("Release Scale", 1.0, FloatRange::Skewed {min: 0.25, max: 20.0, factor: FloatRange::skew_factor(-2.0)}),
+            velocity_curve: FloatParam::new("Velocity Curve", 1.0, FloatRange::Skewed {min: 0.25, max: 4.0, factor: FloatRange::skew_factor(-1.0)}),
             stereo_width: FloatParam::new("Stereo Width", 0.7, FloatRange::Linear {min: 0.0, max: 1.0}),
+            pan_center: FloatParam::new("Pan Center", 0.0, FloatRange::Linear {min: -1.0, max: 1.0}),
             time_spread: IntParam::new("Time Spread", 50, IntRange::Linear {min: 0, max: 100}),
+            tight: BoolParam::new("Tight", false),
             harmonics: BoolParam::new("Harmonics", false),
             mute: BoolParam::new("Con Sordino", false),
-            polyphonic: BoolParam::new("Polyphonic", false)
+            polyphonic: BoolParam::new("Polyphonic", false),
+            hq_resample: BoolParam::new("High Quality Resampling", false).non_automatable(),
+            dither: BoolParam::new("Dither", false).non_automatable(),
+            invert_polarity: BoolParam::new("Invert Polarity", false).non_automatable(),
+            compressor: BoolParam::new("Compressor", false).non_automatable(),
+            synth_bypass: BoolParam::new("Synth Bypass", false).non_automatable(),
+            resonator_mode: BoolParam::new("Resonator Mode", false).non_automatable(),
+            separate_outputs: BoolParam::new("Separate Outputs", false).non_automatable(),
+            adopt_type_defaults: BoolParam::new("Adopt Type Defaults", false).non_automatable()
         };
         result
     }
@@ -177,12 +368,29 @@ impl Plugin for ViolaExMachina {
     const EMAIL: &'static str = "peter.eastman@gmail.com";
     const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
+    // The second layout adds a mono output for each instrument, which is used when Separate Outputs
+    // is enabled.
+
     const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[AudioIOLayout {
         main_input_channels: None,
         main_output_channels: NonZeroU32::new(2),
-        aux_input_ports: &[],
+        aux_input_ports: &[new_nonzero_u32(2)],
         aux_output_ports: &[],
-        names: PortNames::const_default(),
+        names: PortNames {
+            aux_inputs: &["Resonator Input"],
+            ..PortNames::const_default()
+        },
+    }, AudioIOLayout {
+        main_input_channels: None,
+        main_output_channels: NonZeroU32::new(2),
+        aux_input_ports: &[new_nonzero_u32(2)],
+        aux_output_ports: &[new_nonzero_u32(1); MAX_AUX_OUTPUTS],
+        names: PortNames {
+            aux_inputs: &["Resonator Input"],
+            aux_outputs: &["Instrument 1", "Instrument 2", "Instrument 3", "Instrument 4",
+                           "Instrument 5", "Instrument 6", "Instrument 7", "Instrument 8"],
+            ..PortNames::const_default()
+        },
     }];
 
     const MIDI_INPUT: MidiConfig = MidiConfig::MidiCCs;
@@ -196,10 +404,22 @@ impl Plugin for ViolaExMachina {
         self.params.clone()
     }
 
-    fn initialize(&mut self, _audio_io_layout: &AudioIOLayout, buffer_config: &BufferConfig, _context: &mut impl InitContext<Self>) -> bool {
+    fn initialize(&mut self, audio_io_layout: &AudioIOLayout, buffer_config: &BufferConfig, context: &mut impl InitContext<Self>) -> bool {
+        self.sample_rate = buffer_config.sample_rate;
         self.need_resample = buffer_config.sample_rate != synth::SAMPLE_RATE as f32;
-        self.resample_left = Resampler::new(buffer_config.sample_rate);
-        self.resample_right = Resampler::new(buffer_config.sample_rate);
+        self.correlation_decay = 1.0-1.0/(CORRELATION_TIME*self.sample_rate);
+        self.sinc_resample_left = SincResampler::new(self.sample_rate);
+        self.sinc_resample_right = SincResampler::new(self.sample_rate);
+        let aux_outputs = audio_io_layout.aux_output_ports.len();
+        self.aux_resample = (0..aux_outputs).map(|_| Resampler::new(self.sample_rate)).collect();
+        self.aux_sinc_resample = (0..aux_outputs).map(|_| SincResampler::new(self.sample_rate)).collect();
+        self.dither_left = Ditherer::new(self.sample_rate, DITHER_BITS);
+        self.dither_right = Ditherer::new(self.sample_rate, DITHER_BITS);
+        self.aux_dither = (0..aux_outputs).map(|_| Ditherer::new(self.sample_rate, DITHER_BITS)).collect();
+        self.aux_samples = vec![0.0; aux_outputs];
+        self.reset();
+        self.reported_latency = self.latency();
+        context.set_latency_samples(self.reported_latency);
         let instrument_type = match self.params.instrument_type.value() {
             InstrumentType::Violin => synth::InstrumentType::Violin,
             InstrumentType::Viola => synth::InstrumentType::Viola,
@@ -208,16 +428,56 @@ impl Plugin for ViolaExMachina {
         };
         let instrument_count = self.params.instrument_count.value() as usize;
         let _ = self.sender.lock().unwrap().send(Message::Reinitialize {instrument_type: instrument_type, instrument_count: instrument_count});
+        if synth::director::asset_decode_failed() {
+            nih_log!("Failed to decode bundled audio data, using a substitute");
+        }
+        if synth::instrument::fft_failed() {
+            nih_log!("An inverse FFT failed, and a block of output was replaced with silence");
+        }
         true
     }
 
     fn reset(&mut self) {
+        // Changes to the resampling mode take effect here, since switching in the middle of
+        // processing would cause a glitch.
+
+        self.hq_resample = self.params.hq_resample.value();
+        self.resample_left = Resampler::new(self.sample_rate);
+        self.resample_right = Resampler::new(self.sample_rate);
+        self.sinc_resample_left.reset();
+        self.sinc_resample_right.reset();
+        for resampler in self.aux_resample.iter_mut() {
+            *resampler = Resampler::new(self.sample_rate);
+        }
+        for resampler in self.aux_sinc_resample.iter_mut() {
+            resampler.reset();
+        }
+        self.dither_left.reset();
+        self.dither_right.reset();
+        for ditherer in self.aux_dither.iter_mut() {
+            ditherer.reset();
+        }
+        self.reset_input();
     }
 
-    fn process(&mut self, buffer: &mut Buffer, _aux: &mut AuxiliaryBuffers, context: &mut impl ProcessContext<Self>) -> ProcessStatus {
+    fn process(&mut self, buffer: &mut Buffer, aux: &mut AuxiliaryBuffers, context: &mut impl ProcessContext<Self>) -> ProcessStatus {
+        let resonator_mode = self.params.resonator_mode.value();
+        if self.last_resonator_mode != resonator_mode {
+            self.last_resonator_mode = resonator_mode;
+            self.reset_input();
+        }
+        let aux_input = aux.inputs.first().map(|input| input.as_slice_immutable());
+
+        // The resampling mode can change in reset(), so the host is told about the new latency here.
+
+        if self.reported_latency != self.latency() {
+            self.reported_latency = self.latency();
+            context.set_latency_samples(self.reported_latency);
+        }
         let mut director = self.director.lock().unwrap();
         let sender = self.sender.lock().unwrap();
         let mut next_event = context.next_event();
+        let apply_dither = self.params.dither.value();
         if self.last_articulation != self.params.articulation.value() {
             self.last_articulation = self.params.articulation.value();
             let articulation = match &self.last_articulation {
@@ -231,6 +491,17 @@ impl Plugin for ViolaExMachina {
             };
             let _ = sender.send(Message::SetArticulation {articulation: articulation});
         }
+        if self.last_body != self.params.body.value() {
+            self.last_body = self.params.body.value();
+            let which = match &self.last_body {
+                Body::Default => None,
+                Body::Violin => Some(synth::BundledIR::Violin),
+                Body::Viola => Some(synth::BundledIR::Viola),
+                Body::Cello => Some(synth::BundledIR::Cello),
+                Body::Bass => Some(synth::BundledIR::Bass)
+            };
+            let _ = sender.send(Message::SetImpulseResponse {which: which});
+        }
         if self.last_dynamics != self.params.dynamics.value() {
             self.last_dynamics = self.params.dynamics.value();
             let _ = sender.send(Message::SetVolume {volume: self.last_dynamics});
@@ -247,17 +518,49 @@ impl Plugin for ViolaExMachina {
             self.last_bow_noise = self.params.bow_noise.value();
             let _ = sender.send(Message::SetBowNoise {bow_noise: self.last_bow_noise});
         }
+        if self.last_air != self.params.air.value() {
+            self.last_air = self.params.air.value();
+            let _ = sender.send(Message::SetAir {amount: self.last_air});
+        }
+        if self.last_tilt_eq != self.params.tilt_eq.value() {
+            self.last_tilt_eq = self.params.tilt_eq.value();
+            let _ = sender.send(Message::SetTiltEq {tilt: self.last_tilt_eq});
+        }
         if self.last_release_rate != self.params.release_rate.value() {
             self.last_release_rate = self.params.release_rate.value();
             let _ = sender.send(Message::SetReleaseRate {release: self.last_release_rate});
         }
+        if self.last_attack_scale != self.params.attack_scale.value() {
+            self.last_attack_scale = self.params.attack_scale.value();
+            let _ = sender.send(Message::SetAttackScale {scale: self.last_attack_scale});
+        }
+        if self.last_release_scale != self.params.release_scale.value() {
+            self.last_release_scale = self.params.release_scale.value();
+            let _ = sender.send(Message::SetReleaseScale {scale: self.last_release_scale});
+        }
         if self.last_stereo_width != self.params.stereo_width.value() {
             self.last_stereo_width = self.params.stereo_width.value();
             let _ = sender.send(Message::SetStereoWidth {width: self.last_stereo_width});
         }
-        if self.last_time_spread != self.params.time_spread.value() {
+        if self.last_pan_center != self.params.pan_center.value() {
+            self.last_pan_center = self.params.pan_center.value();
+            let _ = sender.send(Message::SetPanCenter {position: self.last_pan_center});
+        }
+        if self.last_ensemble_tightness != self.params.ensemble_tightness.value() {
+            // This is checked before Time Spread, so when both are sent together the individual
+            // setting takes precedence.
+
+            self.last_ensemble_tightness = self.params.ensemble_tightness.value();
+            let _ = sender.send(Message::SetEnsembleTightness {value: self.last_ensemble_tightness});
+        }
+        if self.last_time_spread != self.params.time_spread.value() || self.last_tight != self.params.tight.value() {
+            // When Tight is enabled, all instruments play together.  The time spread is still remembered,
+            // so it can be restored when Tight is disabled.
+
             self.last_time_spread = self.params.time_spread.value();
-            let _ = sender.send(Message::SetMaxInstrumentDelay {max_delay: (self.last_time_spread*synth::SAMPLE_RATE/1000) as i64});
+            self.last_tight = self.params.tight.value();
+            let spread = if self.last_tight {0} else {self.last_time_spread};
+            let _ = sender.send(Message::SetMaxInstrumentDelay {max_delay: (spread*synth::SAMPLE_RATE/1000) as i64});
         }
         if self.last_harmonics != self.params.harmonics.value() {
             self.last_harmonics = self.params.harmonics.value();
@@ -271,56 +574,125 @@ impl Plugin for ViolaExMachina {
             self.last_polyphonic = self.params.polyphonic.value();
             let _ = sender.send(Message::SetPolyphonic {polyphonic: self.last_polyphonic});
         }
+        if self.last_invert_polarity != self.params.invert_polarity.value() {
+            self.last_invert_polarity = self.params.invert_polarity.value();
+            let _ = sender.send(Message::SetPolarityInvert {enabled: self.last_invert_polarity});
+        }
+        if self.last_compressor != self.params.compressor.value() {
+            self.last_compressor = self.params.compressor.value();
+            let _ = sender.send(Message::SetCompressor {enabled: self.last_compressor, threshold: COMPRESSOR_THRESHOLD, ratio: COMPRESSOR_RATIO});
+        }
+        if self.last_synth_bypass != self.params.synth_bypass.value() {
+            self.last_synth_bypass = self.params.synth_bypass.value();
+            let _ = sender.send(Message::SetSynthBypass {enabled: self.last_synth_bypass});
+        }
+        if self.last_separate_outputs != self.params.separate_outputs.value() {
+            self.last_separate_outputs = self.params.separate_outputs.value();
+            let _ = sender.send(Message::SetSeparateOutputs {enabled: self.last_separate_outputs});
+        }
         for (sample_id, channel_samples) in buffer.iter_samples().enumerate() {
-            let mut new_notes = HashSet::new();
+            // Collect all the events for this sample and translate them to Messages.
+
+            self.sample_events.clear();
             while let Some(event) = next_event {
                 if event.timing() != sample_id as u32 {
                     break;
                 }
-                match event {
-                    NoteEvent::NoteOn { note, velocity, .. } => {
-                        let _ = sender.send(Message::NoteOn {
-                            note_index: note as i32,
-                            velocity: velocity});
-                        self.last_note = note;
+                self.sample_events.push(event);
+                next_event = context.next_event();
+            }
+            if self.sample_events.len() > 0 {
+                self.translator.set_velocity_curve(self.params.velocity_curve.value());
+                self.translator.translate_events(&self.sample_events, &mut self.messages);
+                for message in self.messages.drain(..) {
+                    let _ = sender.send(message);
+                }
+            }
 
-                        // If we get both a NoteOn and a NoteOff for the same note at the same time, skip the NoteOff
-                        // to allow legato playing.
+            // In resonator mode, queue up the input at the Director's sample rate.
 
-                        new_notes.insert(note);
+            if resonator_mode {
+                let mut input = (0.0, 0.0);
+                if let Some(channels) = &aux_input {
+                    if !channels.is_empty() {
+                        input.0 = channels[0][sample_id];
+                        input.1 = if channels.len() > 1 {channels[1][sample_id]} else {input.0};
                     }
-                    NoteEvent::NoteOff { note, .. } => {
-                        if !new_notes.contains(&note) {
-                            let _ = sender.send(Message::NoteOff {note_index: note as i32} );
-                        }
-                    }
-                    NoteEvent::MidiPitchBend { value, .. } => {
-                        let _ = sender.send(Message::SetPitchBend {semitones: 4.0*(value-0.5)});
-                    }
-                    NoteEvent::Choke { .. } => {
-                        let _ = sender.send(Message::AllNotesOff);
+                }
+                if self.need_resample {
+                    self.input_resample_left.add_input(input.0);
+                    self.input_resample_right.add_input(input.1);
+                    while self.input_resample_left.has_output() {
+                        self.input_queue.push_back((self.input_resample_left.get_output(), self.input_resample_right.get_output()));
                     }
-                    NoteEvent::VoiceTerminated { .. } => {
-                        let _ = sender.send(Message::AllNotesOff);
+                }
+                else {
+                    self.input_queue.push_back(input);
+                }
+                while self.input_queue.len() > MAX_INPUT_QUEUE {
+                    self.input_queue.pop_front();
+                }
+            }
+            let mut left;
+            let mut right;
+            if self.need_resample && self.hq_resample {
+                while !self.sinc_resample_left.has_output() {
+                    let (left2, right2) = next_sample(&mut director, resonator_mode, &mut self.input_queue);
+                    self.sinc_resample_left.add_input(left2);
+                    self.sinc_resample_right.add_input(right2);
+                    for (i, resampler) in self.aux_sinc_resample.iter_mut().enumerate() {
+                        resampler.add_input(director.get_instrument_output(i));
                     }
-                    _ => ()
                 }
-                next_event = context.next_event();
+                left = self.sinc_resample_left.get_output();
+                right = self.sinc_resample_right.get_output();
+                for (sample, resampler) in self.aux_samples.iter_mut().zip(self.aux_sinc_resample.iter_mut()) {
+                    *sample = resampler.get_output();
+                }
             }
-            let left;
-            let right;
-            if self.need_resample {
+            else if self.need_resample {
                 while !self.resample_left.has_output() {
-                    let (left2, right2) = director.generate();
+                    let (left2, right2) = next_sample(&mut director, resonator_mode, &mut self.input_queue);
                     self.resample_left.add_input(left2);
                     self.resample_right.add_input(right2);
+                    for (i, resampler) in self.aux_resample.iter_mut().enumerate() {
+                        resampler.add_input(director.get_instrument_output(i));
+                    }
                 }
                 left = self.resample_left.get_output();
                 right = self.resample_right.get_output();
+                for (sample, resampler) in self.aux_samples.iter_mut().zip(self.aux_resample.iter_mut()) {
+                    *sample = resampler.get_output();
+                }
             }
             else {
-                (left, right) = director.generate();
+                (left, right) = next_sample(&mut director, resonator_mode, &mut self.input_queue);
+                for (i, sample) in self.aux_samples.iter_mut().enumerate() {
+                    *sample = director.get_instrument_output(i);
+                }
             }
+            if apply_dither {
+                left = self.dither_left.process(left);
+                right = self.dither_right.process(right);
+                for (sample, ditherer) in self.aux_samples.iter_mut().zip(self.aux_dither.iter_mut()) {
+                    *sample = ditherer.process(*sample);
+                }
+            }
+
+            // Write each instrument to its own output, if the host selected the layout that has them.
+
+            for (output, &value) in aux.outputs.iter_mut().zip(&self.aux_samples) {
+                for channel in output.as_slice() {
+                    channel[sample_id] = value;
+                }
+            }
+            let (lr, ll, rr) = self.correlation_sums;
+            let decay = self.correlation_decay;
+            self.correlation_sums = (decay*lr + left*right, decay*ll + left*left, decay*rr + right*right);
+
+            // Some hosts provide more channels than we asked for.  Write the stereo pair to the
+            // first two and silence the rest so they don't contain garbage.
+
             let mut i = 0;
             for sample in channel_samples {
                 if i == 0 {
@@ -329,9 +701,19 @@ impl Plugin for ViolaExMachina {
                 else if i == 1 {
                     *sample = right;
                 }
+                else {
+                    *sample = 0.0;
+                }
                 i += 1;
             }
         }
+
+        // Update the correlation shown by the meter.  It is left unchanged while the output is silent.
+
+        let (lr, ll, rr) = self.correlation_sums;
+        if ll*rr > 1e-20 {
+            self.correlation.store((lr/(ll*rr).sqrt()).to_bits(), Ordering::Relaxed);
+        }
         ProcessStatus::KeepAlive
     }
 
@@ -339,7 +721,41 @@ impl Plugin for ViolaExMachina {
         let params = Arc::clone(&self.params);
         let sender = Arc::clone(&self.sender);
         let state = Arc::clone(&self.editor_state);
-        editor::draw_editor(params, sender, state)
+        let correlation = Arc::clone(&self.correlation);
+        editor::draw_editor(params, sender, state, correlation)
+    }
+}
+
+impl ViolaExMachina {
+    /// Get the latency (in samples at the host's sample rate) introduced by the current resampling mode.
+    fn latency(&self) -> u32 {
+        if self.need_resample && self.hq_resample {self.sinc_resample_left.latency()} else {0}
+    }
+
+    /// Discard any queued input for resonator mode and restart the input resamplers.
+    fn reset_input(&mut self) {
+        self.input_queue.clear();
+        self.input_resample_left = Resampler::from_rate(self.sample_rate);
+        self.input_resample_right = Resampler::from_rate(self.sample_rate);
+
+        // A new Resampler has output ready at time 0.  Retrieving it leaves one sample in the
+        // queue, so small variations in timing don't cause it to run dry.
+
+        while self.input_resample_left.has_output() {
+            self.input_queue.push_back((self.input_resample_left.get_output(), self.input_resample_right.get_output()));
+        }
+    }
+}
+
+/// Get the next sample from the Director.  In resonator mode, this processes the next sample of
+/// queued input instead of generating audio from the instruments.
+fn next_sample(director: &mut Director, resonator_mode: bool, input_queue: &mut VecDeque<(f32, f32)>) -> (f32, f32) {
+    if resonator_mode {
+        let (left, right) = input_queue.pop_front().unwrap_or((0.0, 0.0));
+        director.process_input(left, right)
+    }
+    else {
+        director.generate()
     }
 }
 
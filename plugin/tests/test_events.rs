@@ -0,0 +1,133 @@
+// Copyright 2026 by Peter Eastman
+//
+// This file is part of Viola Ex Machina.
+//
+// Viola Ex Machina is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Lesser General Public License as published by the Free Software Foundation, either
+// version 2.1 of the License, or (at your option) any later version.
+//
+// Viola Ex Machina is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License along with Viola Ex Machina.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use viola_ex_machina::events::EventTranslator;
+use synth::director::Message;
+use nih_plug::prelude::NoteEvent;
+
+fn note_on(note: u8) -> NoteEvent<()> {
+    NoteEvent::NoteOn {timing: 0, voice_id: None, channel: 0, note: note, velocity: 0.8}
+}
+
+fn note_off(note: u8) -> NoteEvent<()> {
+    NoteEvent::NoteOff {timing: 0, voice_id: None, channel: 0, note: note, velocity: 0.0}
+}
+
+fn translate(translator: &mut EventTranslator, events: &[NoteEvent<()>]) -> Vec<Message> {
+    let mut messages = vec![];
+    translator.translate_events(events, &mut messages);
+    messages
+}
+
+#[test]
+fn test_legato_same_note() {
+    // A NoteOff that follows a NoteOn for the same note at the same sample should be skipped.
+
+    let mut translator = EventTranslator::new();
+    let expected = vec![Message::NoteOn {note_index: 60, velocity: 0.8}];
+    assert_eq!(expected, translate(&mut translator, &[note_on(60), note_off(60)]));
+}
+
+#[test]
+fn test_note_off_other_note() {
+    // A NoteOff for a different note is passed through unchanged.  The Director ignores it
+    // unless it matches the note that is currently playing.
+
+    let mut translator = EventTranslator::new();
+    let messages = translate(&mut translator, &[note_on(62), note_off(60)]);
+    assert_eq!(vec![Message::NoteOn {note_index: 62, velocity: 0.8}, Message::NoteOff {note_index: 60, velocity: 0.0}], messages);
+}
+
+#[test]
+fn test_same_sample_orderings() {
+    // Releasing one key and pressing another at the same sample should give the same Messages
+    // whatever order the events arrive in: the NoteOns come first, so the new note is played
+    // legato, followed by the NoteOffs for notes that were not restarted.
+
+    let mut translator = EventTranslator::new();
+    let expected = vec![Message::NoteOn {note_index: 62, velocity: 0.8}, Message::NoteOff {note_index: 60, velocity: 0.0}];
+    assert_eq!(expected, translate(&mut translator, &[note_off(60), note_on(62)]));
+    assert_eq!(expected, translate(&mut translator, &[note_on(62), note_off(60)]));
+
+    // With several notes, the NoteOns keep their order, as do the NoteOffs.
+
+    let expected = vec![Message::NoteOn {note_index: 64, velocity: 0.8}, Message::NoteOn {note_index: 67, velocity: 0.8},
+                        Message::NoteOff {note_index: 60, velocity: 0.0}, Message::NoteOff {note_index: 62, velocity: 0.0}];
+    for events in [[note_off(60), note_off(62), note_on(64), note_on(67)],
+                   [note_on(64), note_off(60), note_on(67), note_off(62)],
+                   [note_off(60), note_on(64), note_off(62), note_on(67)]] {
+        assert_eq!(expected, translate(&mut translator, &events));
+    }
+
+    // A note that is restarted is not released, even when the NoteOff arrives first or other
+    // notes are released.
+
+    let expected = vec![Message::NoteOn {note_index: 60, velocity: 0.8}];
+    assert_eq!(expected, translate(&mut translator, &[note_off(60), note_on(60)]));
+    let expected = vec![Message::NoteOn {note_index: 60, velocity: 0.8}, Message::NoteOff {note_index: 62, velocity: 0.0}];
+    for events in [[note_off(60), note_off(62), note_on(60)], [note_off(62), note_on(60), note_off(60)]] {
+        assert_eq!(expected, translate(&mut translator, &events));
+    }
+}
+
+#[test]
+fn test_separate_samples() {
+    // Suppression only applies to events at the same sample.
+
+    let mut translator = EventTranslator::new();
+    assert_eq!(vec![Message::NoteOn {note_index: 60, velocity: 0.8}], translate(&mut translator, &[note_on(60)]));
+    assert_eq!(vec![Message::NoteOff {note_index: 60, velocity: 0.0}], translate(&mut translator, &[note_off(60)]));
+}
+
+#[test]
+fn test_sostenuto_pedal() {
+    // CC 66 engages and releases the sostenuto pedal.  Other controllers are ignored.
+
+    let mut translator = EventTranslator::new();
+    let pedal = |cc: u8, value: f32| NoteEvent::MidiCC {timing: 0, channel: 0, cc: cc, value: value};
+    assert_eq!(vec![Message::SetSostenuto {engaged: true}], translate(&mut translator, &[pedal(66, 1.0)]));
+    assert_eq!(vec![Message::SetSostenuto {engaged: false}], translate(&mut translator, &[pedal(66, 0.0)]));
+    assert_eq!(Vec::<Message>::new(), translate(&mut translator, &[pedal(64, 1.0)]));
+}
+
+#[test]
+fn test_mpe_pitch_bend() {
+    // Pitch bend should only be applied from the channel of the most recent note.  When a note
+    // starts on a new channel, the bend already sent on that channel is applied.
+
+    let mut translator = EventTranslator::new();
+    let note_on = |note: u8, channel: u8| NoteEvent::NoteOn {timing: 0, voice_id: None, channel: channel, note: note, velocity: 0.8};
+    let bend = |channel: u8, value: f32| NoteEvent::MidiPitchBend {timing: 0, channel: channel, value: value};
+    assert_eq!(vec![Message::SetPitchBend {semitones: 1.0}], translate(&mut translator, &[bend(0, 0.75)]));
+    assert_eq!(vec![Message::NoteOn {note_index: 60, velocity: 0.8}], translate(&mut translator, &[note_on(60, 0)]));
+    assert_eq!(Vec::<Message>::new(), translate(&mut translator, &[bend(1, 0.25)]));
+    assert_eq!(vec![Message::SetPitchBend {semitones: -1.0}, Message::NoteOn {note_index: 62, velocity: 0.8}],
+               translate(&mut translator, &[note_on(62, 1)]));
+    assert_eq!(Vec::<Message>::new(), translate(&mut translator, &[bend(0, 0.5)]));
+    assert_eq!(vec![Message::SetPitchBend {semitones: 0.0}], translate(&mut translator, &[bend(1, 0.5)]));
+}
+
+#[test]
+fn test_velocity_curve() {
+    // The velocity curve raises velocities to a power.  By default they are unchanged.
+
+    let mut translator = EventTranslator::new();
+    let event = |velocity: f32| NoteEvent::NoteOn {timing: 0, voice_id: None, channel: 0, note: 60, velocity: velocity};
+    assert_eq!(vec![Message::NoteOn {note_index: 60, velocity: 0.5}], translate(&mut translator, &[event(0.5)]));
+    translator.set_velocity_curve(2.0);
+    assert_eq!(vec![Message::NoteOn {note_index: 60, velocity: 0.25}], translate(&mut translator, &[event(0.5)]));
+    translator.set_velocity_curve(0.5);
+    assert_eq!(vec![Message::NoteOn {note_index: 60, velocity: 0.5}], translate(&mut translator, &[event(0.25)]));
+}
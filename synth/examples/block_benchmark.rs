@@ -0,0 +1,77 @@
+// Copyright 2026 by Peter Eastman
+//
+// This file is part of Viola Ex Machina.
+//
+// Viola Ex Machina is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Lesser General Public License as published by the Free Software Foundation, either
+// version 2.1 of the License, or (at your option) any later version.
+//
+// Viola Ex Machina is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License along with Viola Ex Machina.
+// If not, see <https://www.gnu.org/licenses/>.
+
+//! Compare the speed of generating audio one sample at a time with generate() to generating
+//! it in blocks with generate_block().  Run it with
+//!
+//! cargo run --release --example block_benchmark
+
+use synth::director::{Director, Message};
+use synth::{InstrumentType, SAMPLE_RATE};
+use std::sync::mpsc;
+use std::time::Instant;
+
+const SECONDS: i32 = 4;
+const REPEATS: usize = 5;
+
+/// Create a Director that is playing a chord.
+fn create_director(instrument_count: usize) -> Director {
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Violin, instrument_count, receiver, 0);
+    let _ = sender.send(Message::SetPolyphonic {polyphonic: true});
+    for note in [60, 64, 67] {
+        let _ = sender.send(Message::NoteOn {note_index: note, velocity: 0.8});
+    }
+    director.generate();
+    director
+}
+
+/// Generate audio in blocks of the specified size (or one sample at a time if it is 1) and
+/// return the time in seconds per second of audio.  Each measurement is repeated several times
+/// and the fastest is reported, since that is least affected by other activity on the computer.
+fn time_generation(instrument_count: usize, block_size: usize) -> f32 {
+    let mut best = f32::MAX;
+    for _ in 0..REPEATS {
+        let mut director = create_director(instrument_count);
+        let mut left = vec![0.0; block_size];
+        let mut right = vec![0.0; block_size];
+        let start = Instant::now();
+        let mut sum = 0.0;
+        for _ in 0..SECONDS*SAMPLE_RATE/block_size as i32 {
+            if block_size == 1 {
+                (left[0], right[0]) = director.generate();
+            }
+            else {
+                director.generate_block(&mut left, &mut right);
+            }
+            sum += left.iter().sum::<f32>() + right.iter().sum::<f32>();
+        }
+        assert!(sum.is_finite());
+        best = best.min(start.elapsed().as_secs_f32()/SECONDS as f32);
+    }
+    best
+}
+
+fn main() {
+    println!("instruments  block size  seconds per second of audio  speedup");
+    for instrument_count in [1, 4, 16] {
+        let per_sample = time_generation(instrument_count, 1);
+        println!("{:>11}  {:>10}  {:>27.5}", instrument_count, 1, per_sample);
+        for block_size in [64, 256, 1024] {
+            let elapsed = time_generation(instrument_count, block_size);
+            println!("{:>11}  {:>10}  {:>27.5}  {:>7.2}", instrument_count, block_size, elapsed, per_sample/elapsed);
+        }
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright 2026 by Peter Eastman
+//
+// This file is part of Viola Ex Machina.
+//
+// Viola Ex Machina is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Lesser General Public License as published by the Free Software Foundation, either
+// version 2.1 of the License, or (at your option) any later version.
+//
+// Viola Ex Machina is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License along with Viola Ex Machina.
+// If not, see <https://www.gnu.org/licenses/>.
+
+//! Play a Standard MIDI File through the synthesizer and write the result to a WAV file.  Run it with
+//!
+//! cargo run --release --example play_midi -- input.mid output.wav [instrument] [count] [channel]
+//!
+//! The instrument is violin, viola, cello, or bass (default violin), count is the number of
+//! instruments in the section (default 1), and channel selects a single MIDI channel from 1 to 16
+//! (default all channels).  Notes, pitch bend (with a range of 2 semitones), the modulation wheel
+//! (CC 1, used for dynamics), the sostenuto pedal (CC 66), and all notes off (CC 123) are
+//! translated into Messages.  Other events are ignored.
+
+use synth::director::{Director, Message};
+use synth::{InstrumentType, SAMPLE_RATE};
+use midly::{Smf, Timing, TrackEventKind, MetaMessage, MidiMessage};
+use std::sync::mpsc;
+
+/// After the last event, keep generating until the output is silent or this many seconds pass.
+const MAX_TAIL_SECONDS: usize = 10;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 {
+        eprintln!("Usage: play_midi input.mid output.wav [violin|viola|cello|bass] [count] [channel]");
+        std::process::exit(1);
+    }
+    let instrument_type = match args.get(3).map(|s| s.to_lowercase()).as_deref() {
+        None | Some("violin") => InstrumentType::Violin,
+        Some("viola") => InstrumentType::Viola,
+        Some("cello") => InstrumentType::Cello,
+        Some("bass") => InstrumentType::Bass,
+        Some(other) => {
+            eprintln!("Unknown instrument: {}", other);
+            std::process::exit(1);
+        }
+    };
+    let count: usize = args.get(4).map(|s| s.parse().expect("Invalid instrument count")).unwrap_or(1);
+    let channel: Option<u8> = args.get(5).map(|s| s.parse::<u8>().expect("Invalid channel")-1);
+
+    // Parse the file.
+
+    let data = std::fs::read(&args[1]).expect("Failed to read MIDI file");
+    let smf = Smf::parse(&data).expect("Failed to parse MIDI file");
+
+    // Merge the tracks into a single list ordered by time.  Tempo changes can be in any track and
+    // apply to all of them.
+
+    let mut track_events = vec![];
+    for track in &smf.tracks {
+        let mut tick: u64 = 0;
+        for event in track {
+            tick += event.delta.as_int() as u64;
+            track_events.push((tick, event.kind));
+        }
+    }
+    track_events.sort_by_key(|e| e.0);
+
+    // Convert them to Messages with times in samples.
+
+    let mut events = vec![];
+    let mut held_notes = vec![];
+    let mut note_count = 0;
+    let mut out_of_range = 0;
+    let mut seconds_per_tick = match smf.header.timing {
+        Timing::Metrical(ticks_per_beat) => 0.5/ticks_per_beat.as_int() as f64,
+        Timing::Timecode(fps, subframes) => 1.0/(fps.as_f32() as f64*subframes as f64)
+    };
+    let mut last_tick = 0;
+    let mut time = 0.0;
+    for (tick, kind) in track_events {
+        time += (tick-last_tick) as f64*seconds_per_tick;
+        last_tick = tick;
+        let sample = (time*SAMPLE_RATE as f64).round() as i64;
+        match kind {
+            TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => {
+                if let Timing::Metrical(ticks_per_beat) = smf.header.timing {
+                    seconds_per_tick = tempo.as_int() as f64*1e-6/ticks_per_beat.as_int() as f64;
+                }
+            }
+            TrackEventKind::Midi {channel: c, message} => {
+                if channel.is_some_and(|ch| ch != c.as_int()) {
+                    continue;
+                }
+                match message {
+                    MidiMessage::NoteOn {key, vel} if vel.as_int() > 0 => {
+                        let note_index = key.as_int() as i32;
+                        note_count += 1;
+                        if note_index < instrument_type.lowest_note() || note_index > instrument_type.highest_note() {
+                            out_of_range += 1;
+                        }
+                        held_notes.push(note_index);
+                        events.push((sample, Message::NoteOn {note_index, velocity: vel.as_int() as f32/127.0}));
+                    }
+                    MidiMessage::NoteOn {key, ..} | MidiMessage::NoteOff {key, ..} => {
+                        let note_index = key.as_int() as i32;
+                        let velocity = if let MidiMessage::NoteOff {vel, ..} = message {vel.as_int() as f32/127.0} else {0.0};
+                        held_notes.retain(|n| *n != note_index);
+                        events.push((sample, Message::NoteOff {note_index, velocity}));
+                    }
+                    MidiMessage::PitchBend {bend} => {
+                        events.push((sample, Message::SetPitchBend {semitones: 2.0*bend.as_f32()}));
+                    }
+                    MidiMessage::Controller {controller, value} => {
+                        match controller.as_int() {
+                            1 => events.push((sample, Message::SetVolume {volume: value.as_int() as f32/127.0})),
+                            66 => events.push((sample, Message::SetSostenuto {engaged: value.as_int() >= 64})),
+                            123 => {
+                                held_notes.clear();
+                                events.push((sample, Message::AllNotesOff));
+                            }
+                            _ => {}
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    let end = events.last().map(|e| e.0).unwrap_or(0).max(0) as usize;
+    println!("{} notes, {} outside the range of the instrument", note_count, out_of_range);
+    if !held_notes.is_empty() {
+        println!("Notes never released: {:?}", held_notes);
+        events.push((end as i64, Message::AllNotesOff));
+    }
+
+    // Generate the audio, then continue until the release and tails have finished.
+
+    let (_sender, receiver) = mpsc::channel();
+    let mut director = Director::new(instrument_type, count, receiver);
+    let (mut left, mut right) = director.generate_with_events(&events, end+1);
+    let block = SAMPLE_RATE as usize/10;
+    for _ in 0..MAX_TAIL_SECONDS*10 {
+        let (l, r) = director.generate_with_events(&[], block);
+        let silent = l.iter().chain(r.iter()).all(|x| x.abs() < 1e-5);
+        left.extend(l);
+        right.extend(r);
+        if silent && !director.is_note_active() {
+            break;
+        }
+    }
+
+    // Write the WAV file.
+
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: SAMPLE_RATE as u32,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float
+    };
+    let mut writer = hound::WavWriter::create(&args[2], spec).expect("Failed to create WAV file");
+    let mut peak: f32 = 0.0;
+    for (l, r) in left.iter().zip(right.iter()) {
+        writer.write_sample(*l).unwrap();
+        writer.write_sample(*r).unwrap();
+        peak = peak.max(l.abs()).max(r.abs());
+    }
+    writer.finalize().unwrap();
+    println!("Wrote {:.1} seconds, peak level {:.1} dBFS", left.len() as f32/SAMPLE_RATE as f32, 20.0*peak.log10());
+}
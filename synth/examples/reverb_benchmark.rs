@@ -0,0 +1,57 @@
+// Copyright 2026 by Peter Eastman
+//
+// This file is part of Viola Ex Machina.
+//
+// Viola Ex Machina is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Lesser General Public License as published by the Free Software Foundation, either
+// version 2.1 of the License, or (at your option) any later version.
+//
+// Viola Ex Machina is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License along with Viola Ex Machina.
+// If not, see <https://www.gnu.org/licenses/>.
+
+//! Measure the speed of the reverb with different partitionings of the IR.  The best choice
+//! depends on the CPU.  Run it with
+//!
+//! cargo run --release --example reverb_benchmark
+
+use synth::reverb::Reverb;
+use synth::random::Random;
+use synth::SAMPLE_RATE;
+use realfft::RealFftPlanner;
+use std::time::Instant;
+
+fn main() {
+    // Create an IR with about the same length as the bundled ones, and some random input.
+
+    let mut random = Random::with_seed(0);
+    let mut ir = vec![];
+    for i in 0..SAMPLE_RATE/2 {
+        ir.push(random.get_normal()*f32::exp(-10.0*i as f32/SAMPLE_RATE as f32));
+    }
+    let mut input = vec![];
+    for _ in 0..10*SAMPLE_RATE {
+        input.push(random.get_normal());
+    }
+
+    // Time each configuration.
+
+    let mut fft_planner = RealFftPlanner::<f32>::new();
+    println!("direct width  max block width  seconds per second of audio");
+    for direct_width in [8, 16, 32, 64, 128, 256] {
+        for max_block_width in [4096, 16384, usize::MAX] {
+            let mut reverb = Reverb::with_partitions(&ir, direct_width, max_block_width, &mut fft_planner);
+            let start = Instant::now();
+            let mut sum = 0.0;
+            for x in input.iter() {
+                sum += reverb.process(*x);
+            }
+            let elapsed = start.elapsed().as_secs_f32();
+            let max_label = if max_block_width == usize::MAX {"unlimited".to_string()} else {max_block_width.to_string()};
+            println!("{:>12}  {:>15}  {:.5}  ({})", direct_width, max_label, elapsed/10.0, if sum.is_finite() {"ok"} else {"overflow"});
+        }
+    }
+}
@@ -0,0 +1,82 @@
+// Copyright 2025 by Peter Eastman
+//
+// This file is part of Viola Ex Machina.
+//
+// Viola Ex Machina is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Lesser General Public License as published by the Free Software Foundation, either
+// version 2.1 of the License, or (at your option) any later version.
+//
+// Viola Ex Machina is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License along with Viola Ex Machina.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use crate::SAMPLE_RATE;
+
+/// The time for the compressor to respond to a rise in level.
+const ATTACK_SECONDS: f32 = 0.01;
+
+/// The time for the compressor to recover after the level falls.
+const RELEASE_SECONDS: f32 = 0.2;
+
+/// A gentle feed-forward compressor for a stereo signal.  The level is detected from the peak of
+/// both channels, so the same gain is applied to each and the stereo image is not shifted.  The
+/// gain reduction follows the level with separate attack and release times.
+#[derive(Copy, Clone)]
+pub struct Compressor {
+    threshold: f32,
+    ratio: f32,
+    attack: f32,
+    release: f32,
+    level: f32
+}
+
+impl Compressor {
+    /// Create a Compressor.  The threshold is in dB relative to full scale, and the ratio is the
+    /// amount by which the level above the threshold is reduced.
+    pub fn new(threshold: f32, ratio: f32) -> Self {
+        let mut result = Self {
+            threshold: 0.0,
+            ratio: 1.0,
+            attack: 1.0-(-1.0/(ATTACK_SECONDS*SAMPLE_RATE as f32)).exp(),
+            release: 1.0-(-1.0/(RELEASE_SECONDS*SAMPLE_RATE as f32)).exp(),
+            level: 0.0
+        };
+        result.set_threshold(threshold);
+        result.set_ratio(ratio);
+        result
+    }
+
+    pub fn reset(&mut self) {
+        self.level = 0.0;
+    }
+
+    /// Set the threshold (in dB relative to full scale) above which the signal is compressed.
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold.min(0.0);
+    }
+
+    /// Set the compression ratio.  This must be at least 1.
+    pub fn set_ratio(&mut self, ratio: f32) {
+        self.ratio = ratio.max(1.0);
+    }
+
+    /// Process one sample of each channel.
+    pub fn process(&mut self, left: f32, right: f32) -> (f32, f32) {
+        let peak = left.abs().max(right.abs());
+        let rate = if peak > self.level {self.attack} else {self.release};
+        self.level += rate*(peak-self.level);
+        if self.level < 1e-6 {
+            return (left, right);
+        }
+        let db = 20.0*self.level.log10();
+        if db <= self.threshold {
+            return (left, right);
+        }
+        let reduction = (db-self.threshold)*(1.0-1.0/self.ratio);
+        let gain = 10.0f32.powf(-reduction/20.0);
+        (gain*left, gain*right)
+    }
+}
@@ -16,24 +16,182 @@
 use crate::instrument::Instrument;
 use crate::random::Random;
 use crate::reverb::Reverb;
-use crate::{InstrumentType, Articulation, SAMPLE_RATE};
-use crate::filter::{Filter, LowpassFilter, ResonantFilter};
+use crate::compressor::Compressor;
+use crate::preset::Preset;
+use crate::{InstrumentType, Articulation, BundledIR, DebugTap, SynthError, VibratoShape, MIDI_NOTE_RANGE, SAMPLE_RATE};
+use crate::filter::{Filter, LowpassFilter, HighpassFilter, HighShelfFilter, PeakingFilter, ResonantFilter, TiltFilter};
 use std::f32::consts::PI;
 use std::sync::mpsc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::cell::RefCell;
 use realfft::RealFftPlanner;
 
+/// The envelope level the release goes to before the release tail begins.
+const RELEASE_TAIL_LEVEL: f32 = 0.03;
+
+/// The maximum length of the release tail.  Once the tail ends the envelope reaches 0, so idle
+/// instruments still stop generating.
+const MAX_RELEASE_TAIL_MS: i64 = 2000;
+
+/// The range of rates for the decay of note tails, relative to the default for the instrument type.
+const MIN_TAIL_DECAY: f32 = 0.25;
+const MAX_TAIL_DECAY: f32 = 4.0;
+
+/// The maximum depth (in cents) and duration of the scoop at the start of a note.
+const MAX_ENTRY_SCOOP_CENTS: f32 = 200.0;
+const MAX_ENTRY_SCOOP_MS: i64 = 500;
+
+/// The "air" control adds a gentle high shelf boost above this frequency to simulate the high frequency
+/// noise from the bow contacting the string.  At the maximum setting it doubles the amplitude (about 6 dB).
+const AIR_CUTOFF: f32 = 6000.0;
+const AIR_MAX_BOOST: f32 = 1.0;
+
+/// The tilt EQ pivots around this frequency (in Hz).  At the maximum tilt, frequencies far above
+/// it are boosted by this many dB and frequencies far below it are cut by the same amount, or the
+/// reverse.
+const TILT_PIVOT: f32 = 1000.0;
+const MAX_TILT_DB: f32 = 6.0;
+
+/// Subsonic energy is removed from the output with a highpass filter.  Its cutoff (in Hz) is just
+/// below the lowest open string of each instrument type: G3 for violin, C3 for viola, C2 for cello,
+/// and E1 for bass.  Notes below E1 on the bass are slightly attenuated.
+const VIOLIN_RUMBLE_CUTOFF: f32 = 180.0;
+const VIOLA_RUMBLE_CUTOFF: f32 = 120.0;
+const CELLO_RUMBLE_CUTOFF: f32 = 60.0;
+const BASS_RUMBLE_CUTOFF: f32 = 38.0;
+
+/// In mono compatible mode, the distance of each pan position from the center is scaled by this
+/// factor.  With a constant power pan law, this limits the loss when an instrument is summed to mono
+/// to cos²(π/8), or about 0.7 dB, while keeping its level in stereo unchanged.
+const MONO_COMPATIBLE_WIDTH: f32 = 0.5;
+
+/// The range of rates (in bow strokes per second) that can be selected for tremolo.
+const MIN_TREMOLO_RATE: f32 = 2.0;
+const MAX_TREMOLO_RATE: f32 = 30.0;
+
+/// With full bow alternation, up-bows are this much quieter than down-bows, and the bow is
+/// shifted this far along the string, giving a slightly different timbre.
+const UP_BOW_LEVEL_REDUCTION: f32 = 0.15;
+const UP_BOW_POSITION_SHIFT: f32 = 0.04;
+
+/// With stereo tails enabled, the tail of each previous note is shifted by a random amount up to
+/// this far toward one side.
+const STEREO_TAIL_SPREAD: f32 = 0.5;
+
+/// The maximum width of the body resonance's stereo image, relative to that of the dry signal.
+const MAX_REVERB_WIDTH: f32 = 3.0;
+
+/// Each instrument can sum up to this many slightly detuned voices, detuned by up to this many
+/// cents, to thicken the tone of a single player.
+const MAX_UNISON_VOICES: usize = 4;
+const MAX_UNISON_DETUNE: f32 = 25.0;
+
+/// The range of factors by which the lengths of arco attacks and of releases can be scaled.  At the
+/// maximum, a slow attack lasts about 13 seconds and a slow release about 4.6 seconds.
+const MIN_ENVELOPE_SCALE: f32 = 0.25;
+const MAX_ENVELOPE_SCALE: f32 = 20.0;
+
+/// With string crossing enabled, a pitch bend that carries a note onto a different string gives a
+/// burst of attack noise at this level, as if it were a note with this velocity.  To avoid repeated
+/// bursts when a bend hovers near an open string, the pitch must pass this many semitones beyond
+/// the open string before the crossing happens.
+const STRING_CROSSING_LEVEL: f32 = 0.5;
+const STRING_CROSSING_HYSTERESIS: f32 = 0.25;
+
+/// The maximum amount (in cents) by which SetNoteTuning can shift a note, and the time (in steps)
+/// over which a sounding note glides to its new tuning.
+const MAX_NOTE_TUNING: f32 = 1200.0;
+const NOTE_RETUNE_TIME: i64 = 2400;
+
+/// The settings that SetEnsembleTightness uses for the loosest ensemble.  At the default tightness
+/// of 0.5 they reproduce the default settings, and at 1 the instruments play in exact unison.
+const LOOSE_INSTRUMENT_DELAY: i64 = 4000;
+const LOOSE_PITCH_DRIFT: f32 = 1.0;
+const LOOSE_ATTACK_JITTER: f32 = 0.5;
+const LOOSE_VIBRATO_GRADIENT: f32 = 0.6;
+const LOOSE_UNISON_DETUNE: f32 = 15.0;
+
+/// With the noise gate enabled, bow noise is fully open when an instrument's volume changes by this
+/// much over 100 steps.  When the gate is closing, it moves this fraction of the way toward its
+/// target every 100 steps, so the noise fades out over about 10 ms rather than stopping abruptly.
+const NOISE_GATE_MOTION: f32 = 0.01;
+const NOISE_GATE_RELEASE: f32 = 0.2;
+
+/// The maximum number of body Reverbs kept in the cache.  This is enough for every instrument type,
+/// plus a few blended bodies.
+const MAX_CACHED_REVERBS: usize = 8;
+
+/// When the maximum instrument delay is changed while Transitions are in progress, each
+/// instrument's delay moves toward its new value by one step for every this many steps that pass.
+/// Since the delay changes more slowly than time advances, no instrument ever moves backward
+/// through a Transition it is following.
+const DELAY_SLEW_STEPS: i64 = 2;
+
+/// When the instrument type is switched while a note is playing, the body resonance crossfades
+/// from the old IR to the new one over this many steps.
+const REVERB_CROSSFADE_STEPS: f32 = 2400.0;
+
+/// Up to this many resonant body modes can be added to the output, to emphasize frequencies the
+/// body IR doesn't capture.  Each one has a center frequency (in Hz), a Q, and a gain (in dB).
+/// Modes with a gain of 0 have no effect, which is the default.
+const MAX_BODY_MODES: usize = 4;
+const DEFAULT_BODY_MODE: (f32, f32, f32) = (500.0, 10.0, 0.0);
+const MAX_BODY_MODE_Q: f32 = 50.0;
+const MAX_BODY_MODE_GAIN: f32 = 24.0;
+
+/// The output of the instruments is multiplied by this, divided by the square root of the number
+/// of instruments.
+const OUTPUT_LEVEL: f32 = 0.01;
+
+/// The amplitude of the test tone (about -12 dBFS).
+const TEST_TONE_LEVEL: f32 = 0.25;
+
+/// The fraction of the remaining distance the volume moves toward its target on each step.
+/// This gives a time constant of 10 ms.
+const VOLUME_SMOOTHING: f32 = 1.0/(0.01*SAMPLE_RATE as f32);
+
+/// When the number of instruments is reduced while they are playing, the ones that are removed
+/// fade out over this many steps (100 ms) instead of stopping abruptly.
+const RETIRE_FADE_STEPS: i32 = 4800;
+
+/// The default amount of noise at the start of bowed notes.
+const DEFAULT_ATTACK_NOISE: f32 = 0.3;
+
+/// The fraction of the remaining distance the pan gains move toward their targets on each step.
+/// This gives a time constant of 20 ms, so changes to the stereo image glide smoothly.
+const PAN_SMOOTHING: f32 = 1.0/(0.02*SAMPLE_RATE as f32);
+
+/// The fraction of the remaining distance a quantized pitch bend moves every 100 steps.
+const BEND_SMOOTHING: f32 = 0.1;
+
+/// Parameters for automatic gain control.  The output is measured over a window of about 0.3 seconds,
+/// and the gain follows changes with a time constant of about 0.1 seconds.
+const AUTO_GAIN_TARGET_RMS: f32 = 0.1;
+const AUTO_GAIN_MIN_RMS: f32 = 0.01;
+const AUTO_GAIN_MIN: f32 = 0.25;
+const AUTO_GAIN_MAX: f32 = 4.0;
+const AUTO_GAIN_POWER_DECAY: f32 = 1.0-1.0/(0.3*SAMPLE_RATE as f32);
+const AUTO_GAIN_SMOOTHING: f32 = 1.0/(0.1*SAMPLE_RATE as f32);
+
+/// The default settings for the output compressor: a threshold in dBFS and a ratio.
+const DEFAULT_COMPRESSOR_THRESHOLD: f32 = -18.0;
+const DEFAULT_COMPRESSOR_RATIO: f32 = 3.0;
+
 /// A message that can be sent to a Director.  Messages roughly correspond to MIDI events:
 /// note on, note off, and various control channels.
+#[derive(Clone, Debug, PartialEq)]
 pub enum Message {
     Reinitialize {instrument_type: InstrumentType, instrument_count: usize},
     NoteOn {note_index: i32, velocity: f32},
-    NoteOff {note_index: i32},
+    NoteOff {note_index: i32, velocity: f32},
     AllNotesOff,
     SetArticulation {articulation: Articulation},
     SetVolume {volume: f32},
     SetPitchBend {semitones: f32},
     SetVibrato {vibrato: f32},
+    SetVibratoShape {shape: VibratoShape},
+    SetVibratoPhaseReset {enabled: bool},
+    SetVibratoGradient {amount: f32},
     SetBowPosition {bow_position: f32},
     SetBowNoise {bow_noise: f32},
     SetReleaseRate {release: f32},
@@ -41,18 +199,172 @@ pub enum Message {
     SetMute {mute: bool},
     SetPolyphonic {polyphonic: bool},
     SetStereoWidth {width: f32},
-    SetMaxInstrumentDelay {max_delay: i64}
+    SetPanCenter {position: f32},
+    SetMaxInstrumentDelay {max_delay: i64},
+    SetAttackJitter {jitter: f32},
+    SetReleaseTail {ms: i64},
+    SetBendQuantize {enabled: bool},
+    SetAutoGain {enabled: bool},
+    SetCompressor {enabled: bool, threshold: f32, ratio: f32},
+    SetBodyBlend {other_type: InstrumentType, amount: f32},
+    SetPhaseDecorrelation {amount: f32},
+    SetExcitationCoherence {coherence: f32},
+    SetMaxHarmonic {ratio: f32},
+    SetAttackNoise {amount: f32},
+    SetEntryScoop {cents: f32, ms: i64},
+    SetTailDecay {rate: f32},
+    SetSustainLevel {level: f32},
+    SetLoudnessCompensation {enabled: bool},
+    SetIdleThreshold {samples: i64, level: f32},
+    SetTestTone {enabled: bool, frequency: f32},
+    SetMonoCompatible {enabled: bool},
+    SetImpulseResponse {which: Option<BundledIR>},
+    SetAir {amount: f32},
+    SetInstrumentGains {gains: Vec<f32>},
+    SetBowAlternation {amount: f32},
+    SetTremoloRate {hz: f32},
+    SetStereoTails {enabled: bool},
+    SetRetriggerSameNote {enabled: bool},
+    SetPolarityInvert {enabled: bool},
+    SetSynthBypass {enabled: bool},
+    SetVoiceBudget {max: usize},
+    SetDebugTap {tap: DebugTap},
+    SetSostenuto {engaged: bool},
+    SetMaxNoteDuration {ms: i64},
+    SetBodyMode {index: usize, freq: f32, q: f32, gain: f32},
+    SetReverbWidth {width: f32},
+    SetPitchDrift {amount: f32},
+    SetUnisonVoices {count: usize, detune: f32},
+    SetInharmonicity {amount: f32},
+    SetTiltEq {tilt: f32},
+    SetDivisi {enabled: bool},
+    SetStringCrossing {enabled: bool},
+    SetAttackScale {scale: f32},
+    SetReleaseScale {scale: f32},
+    SetActiveInstrument {instrument_type: InstrumentType},
+    SetNoteTuning {note_index: i32, cents: f32},
+    SetEnsembleTightness {value: f32},
+    SetNoiseGate {amount: f32},
+    SetExcitationVariation {amount: f32},
+    SetSeparateOutputs {enabled: bool},
+    SetSustainDamping {amount: f32}
+}
+
+/// A snapshot of the settings of a Director, as most recently set by Messages.  This allows a
+/// user interface that connects to a running Director to display its true current state.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DirectorState {
+    pub instrument_type: InstrumentType,
+    pub instrument_count: usize,
+    pub articulation: Articulation,
+    pub volume: f32,
+    pub pitch_bend: f32,
+    pub bend_quantize: bool,
+    pub vibrato: f32,
+    pub vibrato_shape: VibratoShape,
+    pub vibrato_phase_reset: bool,
+    pub vibrato_gradient: f32,
+    pub bow_position: f32,
+    pub bow_noise: f32,
+    pub release_rate: f32,
+    pub release_tail: i64,
+    pub harmonics: bool,
+    pub mute: bool,
+    pub polyphonic: bool,
+    pub stereo_width: f32,
+    pub pan_center: f32,
+    pub max_instrument_delay: i64,
+    pub attack_jitter: f32,
+    pub auto_gain: bool,
+    pub compressor_enabled: bool,
+    pub compressor_threshold: f32,
+    pub compressor_ratio: f32,
+    pub body_blend_type: InstrumentType,
+    pub body_blend: f32,
+    pub phase_decorrelation: f32,
+    pub excitation_coherence: f32,
+    pub max_harmonic: f32,
+    pub attack_noise: f32,
+    pub entry_scoop_cents: f32,
+    pub entry_scoop_ms: i64,
+    pub tail_decay: f32,
+    pub sustain_level: f32,
+    pub loudness_compensation: bool,
+    pub idle_samples: i64,
+    pub idle_level: f32,
+    pub test_tone: Option<f32>,
+    pub mono_compatible: bool,
+    pub impulse_response: Option<BundledIR>,
+    pub air: f32,
+    pub instrument_gains: Vec<f32>,
+    pub bow_alternation: f32,
+    pub tremolo_rate: f32,
+    pub stereo_tails: bool,
+    pub retrigger_same_note: bool,
+    pub polarity_invert: bool,
+    pub synth_bypass: bool,
+    pub voice_budget: usize,
+    pub debug_tap: DebugTap,
+    pub sostenuto: bool,
+    pub max_note_duration: i64,
+    pub body_modes: Vec<(f32, f32, f32)>,
+    pub reverb_width: f32,
+    pub pitch_drift: f32,
+    pub unison_voices: usize,
+    pub unison_detune: f32,
+    pub inharmonicity: f32,
+    pub tilt_eq: f32,
+    pub divisi: bool,
+    pub string_crossing: bool,
+    pub attack_scale: f32,
+    pub release_scale: f32,
+    pub note_tuning: Vec<f32>,
+    pub ensemble_tightness: f32,
+    pub noise_gate: f32,
+    pub excitation_variation: f32,
+    pub separate_outputs: bool,
+    pub sustain_damping: f32
 }
 
 /// A Transition describes some type of continuous change to the instruments.  It specifies the time
 /// interval (in step indices) over which the change takes place.  The details of what is
 /// changing are specified by the TransitionData.
+///
+/// Normally every instrument follows the same time interval (offset by its instrument delay).
+/// A staggered Transition instead has its timing scaled separately for each instrument, relative
+/// to the step at which it was created (the origin).  This lets each player in a section attack
+/// at a slightly different speed.
+///
+/// When a new envelope change replaces the existing ones, the old Transitions are not removed
+/// immediately.  Instead they are marked as superseded at the current step.  Delayed instruments
+/// continue to follow them until they reach that step in their own timeline.  The new Transition
+/// then starts from whatever envelope each instrument has reached, which is recorded in
+/// instrument_start.
 struct Transition {
     start: i64,
     end: i64,
+    origin: i64,
+    staggered: bool,
+    superseded: i64,
+    instrument_start: Vec<Option<f32>>,
     data: TransitionData
 }
 
+impl Transition {
+    /// Get the (start, end) interval of this Transition for an instrument with the specified
+    /// time scale factor.
+    fn get_interval(&self, scale: f32) -> (i64, i64) {
+        if self.staggered {
+            let start = self.origin + ((self.start-self.origin) as f32*scale) as i64;
+            let end = self.origin + ((self.end-self.origin) as f32*scale) as i64;
+            (start, end)
+        }
+        else {
+            (self.start, self.end)
+        }
+    }
+}
+
 /// A TransitionData is contained in a Transition.  It specifies what aspect of the instruments is
 /// changing, and what values it is changing between.
 enum TransitionData {
@@ -80,34 +392,134 @@ pub struct Director {
     apply_filter: bool,
     pub step: i64,
     steps_until_off: i32,
+    idle_samples: i32,
+    idle_level: f32,
+    test_tone: Option<f32>,
+    test_tone_phase: f32,
     pub max_instrument_delay: i64,
     pub volume: f32,
+    volume_target: f32,
     pub tremolo_length: i64,
     pub tremolo_space: i64,
+    tremolo_rate: f32,
     pub bend: f32,
+    bend_target: f32,
+    bend_semitones: f32,
+    bend_quantize: bool,
     pub vibrato: f32,
+    pub vibrato_shape: VibratoShape,
+    pub vibrato_phase_reset: bool,
+    pub vibrato_gradient: f32,
     pub bow_position: f32,
     pub release_rate: f32,
+    pub release_tail: i64,
     pub bow_noise: f32,
     pub bow_noise_scale: f32,
+    old_bow_noise_scale: f32,
     body_resonance: f32,
     pub harmonics: bool,
     pub mute: bool,
     polyphonic: bool,
+    divisi: bool,
+    divisi_notes: Vec<i32>,
+    string_crossing: bool,
+    attack_scale: f32,
+    release_scale: f32,
+    note_tuning: Vec<f32>,
+    ensemble_tightness: f32,
+    noise_gate: f32,
+    excitation_variation: f32,
+    separate_outputs: bool,
+    instrument_outputs: Vec<f32>,
+    sustain_damping: f32,
     message_receiver: mpsc::Receiver<Message>,
+    disconnected: bool,
+    last_error: Option<SynthError>,
     pub stereo_width: f32,
+    pub pan_center: f32,
+    mono_pan_gain: (f32, f32),
+    mono_pan_gain_target: (f32, f32),
+    pub attack_jitter: f32,
+    auto_gain: bool,
+    auto_gain_power: f32,
+    auto_gain_target: f32,
+    auto_gain_value: f32,
+    compressor: Compressor,
+    pub compressor_enabled: bool,
+    pub compressor_threshold: f32,
+    pub compressor_ratio: f32,
+    body_blend_type: InstrumentType,
+    body_blend: f32,
+    pub phase_decorrelation: f32,
+    pub excitation_coherence: f32,
+    pub max_harmonic: f32,
+    pub attack_noise: f32,
+    pub entry_scoop_cents: f32,
+    pub entry_scoop_time: i64,
+    pub tail_decay: f32,
+    pub sustain_level: f32,
+    pub loudness_compensation: bool,
+    pub mono_compatible: bool,
+    pub stereo_tails: bool,
+    pub retrigger_same_note: bool,
+    pub polarity_invert: bool,
+    pub synth_bypass: bool,
+    pub voice_budget: usize,
+    pub debug_tap: DebugTap,
+    sostenuto: bool,
+    max_note_duration: i64,
+    body_modes: Vec<(f32, f32, f32)>,
+    body_mode_filters: Vec<(PeakingFilter, PeakingFilter)>,
+    reverb_width: f32,
+    pitch_drift: f32,
+    unison_voices: usize,
+    unison_detune: f32,
+    inharmonicity: f32,
+    pub instrument_gains: Vec<f32>,
+    pub bow_alternation: f32,
+    next_bow_down: bool,
+    impulse_response: Option<BundledIR>,
+    air: f32,
+    left_air_filter: HighShelfFilter,
+    right_air_filter: HighShelfFilter,
+    tilt_eq: f32,
+    left_tilt_filter: TiltFilter,
+    right_tilt_filter: TiltFilter,
+    left_rumble_filter: HighpassFilter,
+    right_rumble_filter: HighpassFilter,
     reverb: Vec<Reverb>,
+    old_reverb: Vec<Reverb>,
+    old_body_resonance: f32,
+    reverb_crossfade: f32,
+    ir_cache: Vec<(BundledIR, Vec<f32>)>,
+    reverb_cache: Vec<CachedReverb>,
     pub noise_buffer: Vec<f32>
 }
 
+/// An unused Reverb for an instrument body, kept so that switching to that body does not require
+/// building a new one.  The blended IR is only relevant when `blend` is greater than 0.
+struct CachedReverb {
+    which: BundledIR,
+    blended: BundledIR,
+    blend: f32,
+    reverb: Reverb
+}
+
 pub struct Division {
     instruments: Vec<Instrument>,
+    retiring: Vec<Instrument>,
+    retiring_pan_gain: Vec<(f32, f32)>,
+    retire_steps: i32,
     random: Random,
+    seed: Option<u32>,
     steps_until_off: i32,
     current_note: i32,
     current_note_articulation: Articulation,
     transitions: Vec<Transition>,
     instrument_delays: Vec<i64>,
+    target_instrument_delays: Vec<i64>,
+    last_delay_step: i64,
+    attack_scale: Vec<f32>,
     envelope: Vec<f32>,
     frequency: Vec<f32>,
     tremolo_start: Vec<i64>,
@@ -117,61 +529,433 @@ pub struct Division {
     envelope_after_transitions: f32,
     frequency_after_transitions: f32,
     instrument_pan: Vec<f32>,
+    pan_gain: Vec<(f32, f32)>,
+    pan_gain_target: Vec<(f32, f32)>,
     bow_position_shift: Vec<f32>,
+    bow_direction_gain: f32,
+    bow_direction_shift: f32,
+    loudness_gain: f32,
+    old_loudness_gain: f32,
     noise_position: Vec<usize>,
-    noise_filter: Vec<ResonantFilter>
+    noise_filter: Vec<ResonantFilter>,
+    noise_gate_level: Vec<f32>,
+    noise_gate_gain: Vec<f32>,
+    outputs: Vec<f32>,
+    string_index: Vec<usize>,
+    sostenuto_held: bool,
+    divisi_gain: Vec<f32>,
+    sostenuto_release: Option<f32>,
+    note_start: i64,
+    block_signal: Vec<f32>,
+    block_side: Vec<f32>,
+    block_left: Vec<f32>,
+    block_right: Vec<f32>
 }
 
 impl Director {
     pub fn new(instrument_type: InstrumentType, instrument_count: usize, message_receiver: mpsc::Receiver<Message>) -> Self {
+        Director::create(instrument_type, instrument_count, message_receiver, None)
+    }
+
+    /// Create a Director whose random numbers are all derived from a specified seed.  Two Directors
+    /// created with the same seed and sent the same messages produce identical output.
+    pub fn new_seeded(instrument_type: InstrumentType, instrument_count: usize, message_receiver: mpsc::Receiver<Message>, seed: u32) -> Self {
+        Director::create(instrument_type, instrument_count, message_receiver, Some(seed))
+    }
+
+    fn create(instrument_type: InstrumentType, instrument_count: usize, message_receiver: mpsc::Receiver<Message>, seed: Option<u32>) -> Self {
         let mut result = Self {
             divisions: RefCell::new(vec![]),
             instrument_type: instrument_type.clone(),
             instrument_count: 0,
             articulation: Articulation::Arco,
-            random: Random::new(),
+            random: match seed {
+                Some(seed) => Random::with_seed(seed),
+                None => Random::new()
+            },
             fft_planner: RefCell::new(RealFftPlanner::<f32>::new()),
             left_mute_filter: LowpassFilter::new(1200.0),
             right_mute_filter: LowpassFilter::new(1200.0),
             apply_filter: true,
             step: 0,
             steps_until_off: 0,
+            idle_samples: 10000,
+            idle_level: 0.001,
+            test_tone: None,
+            test_tone_phase: 0.0,
             max_instrument_delay: 2000,
             volume: 1.0,
+            volume_target: 1.0,
             tremolo_length: 4300,
             tremolo_space: 200,
+            tremolo_rate: 0.0,
             bend: 1.0,
+            bend_target: 1.0,
+            bend_semitones: 0.0,
+            bend_quantize: false,
             vibrato: 0.4,
+            vibrato_shape: VibratoShape::Cubed,
+            vibrato_phase_reset: false,
+            vibrato_gradient: 0.0,
             bow_position: 0.5,
             release_rate: 0.5,
+            release_tail: 0,
             bow_noise: 0.5,
             bow_noise_scale: 1.0,
+            old_bow_noise_scale: 1.0,
             body_resonance: 0.1,
             harmonics: false,
             mute: false,
             polyphonic: false,
+            divisi: false,
+            divisi_notes: vec![],
+            string_crossing: false,
+            attack_scale: 1.0,
+            release_scale: 1.0,
+            note_tuning: vec![0.0; MIDI_NOTE_RANGE.count()],
+            ensemble_tightness: 0.5,
+            noise_gate: 0.0,
+            excitation_variation: 1.0,
+            separate_outputs: false,
+            instrument_outputs: vec![],
+            sustain_damping: 0.0,
             message_receiver: message_receiver,
+            disconnected: false,
+            last_error: None,
             stereo_width: 0.3,
+            pan_center: 0.0,
+            mono_pan_gain: (1.0, 1.0),
+            mono_pan_gain_target: (1.0, 1.0),
+            attack_jitter: 0.0,
+            auto_gain: false,
+            auto_gain_power: 0.0,
+            auto_gain_target: 1.0,
+            auto_gain_value: 1.0,
+            compressor: Compressor::new(DEFAULT_COMPRESSOR_THRESHOLD, DEFAULT_COMPRESSOR_RATIO),
+            compressor_enabled: false,
+            compressor_threshold: DEFAULT_COMPRESSOR_THRESHOLD,
+            compressor_ratio: DEFAULT_COMPRESSOR_RATIO,
+            body_blend_type: instrument_type,
+            body_blend: 0.0,
+            phase_decorrelation: 0.0,
+            excitation_coherence: 0.0,
+            max_harmonic: 1.0,
+            attack_noise: DEFAULT_ATTACK_NOISE,
+            entry_scoop_cents: 0.0,
+            entry_scoop_time: 0,
+            tail_decay: 1.0,
+            sustain_level: 0.0,
+            loudness_compensation: false,
+            mono_compatible: false,
+            stereo_tails: false,
+            retrigger_same_note: false,
+            polarity_invert: false,
+            synth_bypass: false,
+            voice_budget: 0,
+            debug_tap: DebugTap::Output,
+            sostenuto: false,
+            max_note_duration: 0,
+            body_modes: vec![DEFAULT_BODY_MODE; MAX_BODY_MODES],
+            body_mode_filters: vec![(PeakingFilter::new(DEFAULT_BODY_MODE.0, DEFAULT_BODY_MODE.1, 1.0), PeakingFilter::new(DEFAULT_BODY_MODE.0, DEFAULT_BODY_MODE.1, 1.0)); MAX_BODY_MODES],
+            reverb_width: 1.0,
+            pitch_drift: 0.0,
+            unison_voices: 1,
+            unison_detune: 0.0,
+            inharmonicity: 0.0,
+            instrument_gains: vec![],
+            bow_alternation: 0.0,
+            next_bow_down: true,
+            impulse_response: None,
+            air: 0.0,
+            left_air_filter: HighShelfFilter::new(AIR_CUTOFF, 1.0),
+            right_air_filter: HighShelfFilter::new(AIR_CUTOFF, 1.0),
+            tilt_eq: 0.0,
+            left_tilt_filter: TiltFilter::new(TILT_PIVOT, 1.0),
+            right_tilt_filter: TiltFilter::new(TILT_PIVOT, 1.0),
+            left_rumble_filter: HighpassFilter::new(20.0),
+            right_rumble_filter: HighpassFilter::new(20.0),
             reverb: vec![],
-            noise_buffer: parse_flac(include_bytes!("data/bow_noise.flac"))
+            old_reverb: vec![],
+            old_body_resonance: 0.0,
+            reverb_crossfade: 0.0,
+            ir_cache: vec![],
+            reverb_cache: vec![],
+            noise_buffer: load_bow_noise(include_bytes!("data/bow_noise.flac"))
         };
-        for _ in 0..4 {
-            result.divisions.borrow_mut().push(Division::new())
+        for i in 0..4 {
+            // Each division gets its own range of seeds, from which the seeds for its instruments are derived.
+
+            let division_seed = seed.map(|s| s.wrapping_add(((i+1) as u32) << 16));
+            result.divisions.borrow_mut().push(Division::new(division_seed))
         }
         result.initialize_instruments(instrument_type, instrument_count);
+
+        // Build the body Reverbs for every type now, so SetActiveInstrument can switch types
+        // without building them on the audio thread.
+
+        for other_type in [InstrumentType::Violin, InstrumentType::Viola, InstrumentType::Cello, InstrumentType::Bass] {
+            result.body_reverb(other_type);
+        }
+
+        // Plan the FFTs the instruments will need now, rather than when the first note is played.
+        // This is too slow to do on the audio thread, so the sizes for every type are planned, and
+        // later changes of instrument type find them already cached.
+
+        for instrument_type in [InstrumentType::Violin, InstrumentType::Viola, InstrumentType::Cello, InstrumentType::Bass] {
+            Instrument::prepare_fft_plans(instrument_type, &mut result.fft_planner.borrow_mut());
+        }
         result
     }
 
+    /// Get the current envelope of one instrument in a division.
+    pub fn get_envelope(&self, division: usize, instrument: usize) -> f32 {
+        self.divisions.borrow()[division].envelope[instrument]
+    }
+
+    /// Get the current frequency of one instrument in a division, not including pitch bend.
+    pub fn get_frequency(&self, division: usize, instrument: usize) -> f32 {
+        self.divisions.borrow()[division].frequency[instrument]
+    }
+
+    /// Get the current vibrato amplitude of one instrument in a division.
+    pub fn get_vibrato_amplitude(&self, division: usize, instrument: usize) -> f32 {
+        self.divisions.borrow()[division].instruments[instrument].get_vibrato_amplitude()
+    }
+
+    /// Get the current volume of one instrument in a division, including the bow strokes of tremolo.
+    pub fn get_instrument_volume(&self, division: usize, instrument: usize) -> f32 {
+        self.divisions.borrow()[division].instruments[instrument].get_volume()
+    }
+
+    /// Get the current (left, right) pan gains of one instrument in a division.
+    pub fn get_pan_gain(&self, division: usize, instrument: usize) -> (f32, f32) {
+        self.divisions.borrow()[division].pan_gain[instrument]
+    }
+
+    /// Get the output of one instrument for the most recent step, summed over all divisions.  This is
+    /// the dry signal before panning, body resonance, and other processing, scaled to the same level
+    /// as the main output.  It is always 0 unless separate outputs have been enabled with the
+    /// SetSeparateOutputs Message, or if there is no instrument with that index.
+    pub fn get_instrument_output(&self, instrument: usize) -> f32 {
+        self.instrument_outputs.get(instrument).copied().unwrap_or(0.0)
+    }
+
+    /// Get the number of Transitions queued in a division, including ones that have finished but not
+    /// yet been removed because delayed instruments may still be following them.  This is intended
+    /// for tests and diagnostics.
+    pub fn get_transition_count(&self, division: usize) -> usize {
+        self.divisions.borrow()[division].transitions.len()
+    }
+
+    /// Advance by a number of steps, discarding the output.  This is equivalent to calling generate()
+    /// that many times, and is intended for tests that examine the state at precise steps.
+    pub fn advance(&mut self, steps: usize) {
+        for _ in 0..steps {
+            self.generate();
+        }
+    }
+
+    /// Get the number of tails of previous notes that are still decaying, summed over all
+    /// instruments.  Divisions that have gone idle are silent, so their tails are not counted.
+    /// This is intended for diagnostics.
+    pub fn active_tail_count(&self) -> usize {
+        self.divisions.borrow().iter().filter(|d| d.steps_until_off > 0).map(|d| d.instruments.iter().map(|i| i.decaying_note_count()).sum::<usize>()).sum()
+    }
+
+    /// Get whether the sender for the message channel has been dropped.  If so, no further
+    /// Messages can be received until a new channel is provided with set_receiver().
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected
+    }
+
+    /// Replace the channel Messages are received from.  This can be used to reconnect after the
+    /// original sender has been dropped.
+    pub fn set_receiver(&mut self, message_receiver: mpsc::Receiver<Message>) {
+        self.message_receiver = message_receiver;
+        self.disconnected = false;
+    }
+
+    /// Get the most recent error that occurred while processing Messages, such as a note that could
+    /// not be played, and clear it.  This returns None if there has been no error since the last
+    /// call.  It does not allocate, so it can be polled from the audio thread.
+    pub fn take_last_error(&mut self) -> Option<SynthError> {
+        self.last_error.take()
+    }
+
+    /// Get whether any note is currently being held.  This is intended for diagnostics.
+    pub fn is_note_active(&self) -> bool {
+        self.divisions.borrow().iter().any(|d| d.current_note != -1)
+    }
+
+    /// Apply all the settings in a Preset at once.  This has the same effect as sending the
+    /// Messages returned by Preset::messages(), but takes effect immediately.
+    pub fn apply_preset(&mut self, preset: &Preset) {
+        for message in preset.messages() {
+            self.handle_message(message);
+        }
+    }
+
+    /// Get a snapshot of the current settings.  Values are reported in the same units used
+    /// by the Messages that set them.
+    pub fn get_state(&self) -> DirectorState {
+        DirectorState {
+            instrument_type: self.instrument_type,
+            instrument_count: self.instrument_count,
+            articulation: self.articulation,
+            volume: self.volume_target,
+            pitch_bend: self.bend_semitones,
+            bend_quantize: self.bend_quantize,
+            vibrato: self.vibrato,
+            vibrato_shape: self.vibrato_shape,
+            vibrato_phase_reset: self.vibrato_phase_reset,
+            vibrato_gradient: self.vibrato_gradient,
+            bow_position: self.bow_position,
+            bow_noise: self.bow_noise,
+            release_rate: self.release_rate,
+            release_tail: self.release_tail*1000/SAMPLE_RATE as i64,
+            harmonics: self.harmonics,
+            mute: self.mute,
+            polyphonic: self.polyphonic,
+            stereo_width: self.stereo_width,
+            pan_center: self.pan_center,
+            max_instrument_delay: self.max_instrument_delay,
+            attack_jitter: self.attack_jitter,
+            auto_gain: self.auto_gain,
+            compressor_enabled: self.compressor_enabled,
+            compressor_threshold: self.compressor_threshold,
+            compressor_ratio: self.compressor_ratio,
+            body_blend_type: self.body_blend_type,
+            body_blend: self.body_blend,
+            phase_decorrelation: self.phase_decorrelation,
+            excitation_coherence: self.excitation_coherence,
+            max_harmonic: self.max_harmonic,
+            attack_noise: self.attack_noise,
+            entry_scoop_cents: self.entry_scoop_cents,
+            entry_scoop_ms: self.entry_scoop_time*1000/SAMPLE_RATE as i64,
+            tail_decay: self.tail_decay,
+            sustain_level: self.sustain_level,
+            loudness_compensation: self.loudness_compensation,
+            idle_samples: self.idle_samples as i64,
+            idle_level: self.idle_level,
+            test_tone: self.test_tone,
+            mono_compatible: self.mono_compatible,
+            impulse_response: self.impulse_response,
+            air: self.air,
+            instrument_gains: self.instrument_gains.clone(),
+            bow_alternation: self.bow_alternation,
+            tremolo_rate: self.tremolo_rate,
+            stereo_tails: self.stereo_tails,
+            retrigger_same_note: self.retrigger_same_note,
+            polarity_invert: self.polarity_invert,
+            synth_bypass: self.synth_bypass,
+            voice_budget: self.voice_budget,
+            debug_tap: self.debug_tap,
+            sostenuto: self.sostenuto,
+            max_note_duration: self.max_note_duration*1000/SAMPLE_RATE as i64,
+            body_modes: self.body_modes.clone(),
+            reverb_width: self.reverb_width,
+            pitch_drift: self.pitch_drift,
+            unison_voices: self.unison_voices,
+            unison_detune: self.unison_detune,
+            inharmonicity: self.inharmonicity,
+            tilt_eq: self.tilt_eq,
+            divisi: self.divisi,
+            string_crossing: self.string_crossing,
+            attack_scale: self.attack_scale,
+            release_scale: self.release_scale,
+            note_tuning: self.note_tuning.clone(),
+            ensemble_tightness: self.ensemble_tightness,
+            noise_gate: self.noise_gate,
+            excitation_variation: self.excitation_variation,
+            separate_outputs: self.separate_outputs,
+            sustain_damping: self.sustain_damping
+        }
+    }
+
     /// Initialize the set of instruments controlled by this Director.  This is called when it is first
     /// created, and again whenever a Reinitialize message is received.
     fn initialize_instruments(&mut self, instrument_type: InstrumentType, instrument_count: usize) {
+        let same_body = instrument_type == self.instrument_type && !self.reverb.is_empty();
         self.instrument_type = instrument_type.clone();
         self.instrument_count = instrument_count;
+        self.instrument_outputs = vec![0.0; instrument_count];
         self.bend = 1.0;
+        self.bend_target = 1.0;
+        self.bend_semitones = 0.0;
+        self.set_type_parameters(instrument_type);
+        self.next_bow_down = true;
+        self.old_reverb.clear();
+        self.reverb_crossfade = 0.0;
+        if same_body {
+            // The body is unchanged, so keep the existing Reverbs to let the tail of what was
+            // playing continue.  Only the number of them may need to change.
+
+            if instrument_count > 1 && self.reverb.len() == 1 {
+                self.reverb.push(self.reverb[0].clone());
+            }
+            self.reverb.truncate(if instrument_count > 1 {2} else {1});
+        }
+        else {
+            self.create_reverbs();
+        }
+        for division in self.divisions.borrow_mut().iter_mut() {
+            division.initialize_instruments(self);
+        }
+        self.divisi_notes.clear();
+    }
+
+    /// Decide whether a Reinitialize message can be handled by switching the instrument type while
+    /// keeping the current notes playing.  This is possible when the number of instruments is unchanged,
+    /// a note is playing, and every current note is within the range of the new type.
+    fn can_switch_type(&self, instrument_type: InstrumentType, instrument_count: usize) -> bool {
+        if instrument_type == self.instrument_type || instrument_count != self.instrument_count || !self.is_note_active() {
+            return false;
+        }
+        self.divisions.borrow().iter().all(|d| d.current_note == -1 ||
+            (d.current_note >= instrument_type.lowest_note() && d.current_note <= instrument_type.highest_note()))
+    }
+
+    /// Switch to a different instrument type without interrupting the notes that are playing.  The
+    /// envelopes and frequencies are kept, while the parameters that depend on the type are changed.
+    /// The body resonance crossfades from the old IR to the new one, and the gains that depend on
+    /// the type ramp to their new values over the same time.
+    fn switch_instrument_type(&mut self, instrument_type: InstrumentType) {
+        self.instrument_type = instrument_type;
+        self.old_body_resonance = self.body_resonance;
+        self.old_bow_noise_scale = self.bow_noise_scale;
+        self.set_type_parameters(instrument_type);
+        self.old_reverb = std::mem::take(&mut self.reverb);
+        self.create_reverbs();
+        self.reverb_crossfade = 1.0;
+        for division in self.divisions.borrow_mut().iter_mut() {
+            division.switch_instrument_type(self);
+        }
+    }
+
+    /// Blend a value that changed when the instrument type was switched.  It moves from the old
+    /// value to the new one as the body resonance crossfades.
+    fn type_switch_blend(&self, old: f32, new: f32) -> f32 {
+        self.reverb_crossfade*old + (1.0-self.reverb_crossfade)*new
+    }
+
+    /// Set the parameters that depend on the instrument type.
+    fn set_type_parameters(&mut self, instrument_type: InstrumentType) {
+        let rumble_cutoff = match instrument_type {
+            InstrumentType::Violin => VIOLIN_RUMBLE_CUTOFF,
+            InstrumentType::Viola => VIOLA_RUMBLE_CUTOFF,
+            InstrumentType::Cello => CELLO_RUMBLE_CUTOFF,
+            InstrumentType::Bass => BASS_RUMBLE_CUTOFF
+        };
+        self.left_rumble_filter.set_cutoff(rumble_cutoff);
+        self.right_rumble_filter.set_cutoff(rumble_cutoff);
+
+        // The impulse responses are normalized to unit energy, so a given body_resonance produces
+        // the same amount of resonance with any of them.
+
         match instrument_type {
             InstrumentType::Violin => {
                 self.bow_noise_scale = 1.0;
-                self.body_resonance = 0.18;
+                self.body_resonance = 0.74;
                 self.tremolo_length = 4100;
                 self.tremolo_space = 200;
                 self.left_mute_filter = LowpassFilter::new(1200.0);
@@ -179,7 +963,7 @@ impl Director {
             }
             InstrumentType::Viola => {
                 self.bow_noise_scale = 0.6;
-                self.body_resonance = 0.18;
+                self.body_resonance = 1.06;
                 self.tremolo_length = 4600;
                 self.tremolo_space = 200;
                 self.left_mute_filter = LowpassFilter::new(800.0);
@@ -187,7 +971,7 @@ impl Director {
             }
             InstrumentType::Cello => {
                 self.bow_noise_scale = 0.6;
-                self.body_resonance = 0.22;
+                self.body_resonance = 0.78;
                 self.tremolo_length = 4600;
                 self.tremolo_space = 400;
                 self.left_mute_filter = LowpassFilter::new(400.0);
@@ -195,38 +979,107 @@ impl Director {
             }
             InstrumentType::Bass => {
                 self.bow_noise_scale = 0.9;
-                self.body_resonance = 0.3;
+                self.body_resonance = 0.9;
                 self.tremolo_length = 4500;
                 self.tremolo_space = 1000;
                 self.left_mute_filter = LowpassFilter::new(200.0);
                 self.right_mute_filter = LowpassFilter::new(200.0);
             }
         }
-        let ir = match instrument_type {
-            InstrumentType::Violin => parse_flac(include_bytes!("data/violin.flac")),
-            InstrumentType::Viola => parse_flac(include_bytes!("data/viola.flac")),
-            InstrumentType::Cello => parse_flac(include_bytes!("data/cello.flac")),
-            InstrumentType::Bass => parse_flac(include_bytes!("data/bass.flac"))
-        };
+    }
+
+    /// Get the factor by which the bow strokes for tremolo are scaled so they occur at the selected
+    /// rate.  A rate of 0 means to use the default for the instrument type.  On average, a stroke
+    /// lasts for the space before it (alternating between the full space and 80% of it), the stroke
+    /// length, an extension of 800 steps at full volume, a random extension averaging 250 steps, and
+    /// the 50 steps on average until the end is noticed by the next update of the Transitions.  All
+    /// but the last are scaled.
+    fn tremolo_scale(&self) -> f32 {
+        if self.tremolo_rate > 0.0 {
+            let default_steps = 0.9*self.tremolo_space as f32 + self.tremolo_length as f32 + 800.0*self.volume + 250.0;
+            (SAMPLE_RATE as f32/self.tremolo_rate - 50.0)/default_steps
+        }
+        else {
+            1.0
+        }
+    }
+
+    /// Update the gains used to pan the output when there is only one instrument.  Everything is
+    /// processed in mono in that case, so the pan center is applied at the very end.
+    fn update_mono_pan_gain(&mut self) {
+        let width = if self.mono_compatible {MONO_COMPATIBLE_WIDTH} else {1.0};
+        let angle = 0.25*PI*(1.0+width*self.pan_center);
+        let scale = std::f32::consts::SQRT_2;
+        self.mono_pan_gain_target = if self.pan_center == 0.0 {(1.0, 1.0)} else {(scale*angle.cos(), scale*angle.sin())};
+        if self.steps_until_off == 0 {
+            self.mono_pan_gain = self.mono_pan_gain_target;
+        }
+    }
+
+    /// Create the Reverbs that simulate resonance of the instrument body.  The impulse response
+    /// is the one selected with SetImpulseResponse, or if none has been selected, the one for the
+    /// current instrument type.  It is optionally blended with the one for another type.
+    fn create_reverbs(&mut self) {
+        let reverb = self.body_reverb(self.instrument_type);
         self.reverb.clear();
-        self.reverb.push(Reverb::new(&ir, &mut self.fft_planner.borrow_mut()));
-        if instrument_count > 1 {
-            self.reverb.push(Reverb::new(&ir, &mut self.fft_planner.borrow_mut()));
+        if self.instrument_count > 1 {
+            self.reverb.push(reverb.clone());
         }
-        for division in self.divisions.borrow_mut().iter_mut() {
-            division.initialize_instruments(self);
+        self.reverb.push(reverb);
+    }
+
+    /// Get a Reverb for the body of an instrument type, taking into account the selected impulse
+    /// response and body blend.  It is copied from the cache when possible, and otherwise added to it.
+    fn body_reverb(&mut self, instrument_type: InstrumentType) -> Reverb {
+        let which = self.impulse_response.unwrap_or(BundledIR::for_instrument(instrument_type));
+        let blended = BundledIR::for_instrument(self.body_blend_type);
+        let blend = if blended == which {0.0} else {self.body_blend};
+        let matches = |cached: &CachedReverb| cached.which == which && cached.blend == blend && (blend == 0.0 || cached.blended == blended);
+        if let Some(cached) = self.reverb_cache.iter().find(|cached| matches(cached)) {
+            return cached.reverb.clone();
+        }
+        let mut ir = self.body_ir(which);
+        if blend > 0.0 {
+            // Align the two impulse responses at the start, and zero pad the shorter one.
+
+            let other_ir = self.body_ir(blended);
+            if other_ir.len() > ir.len() {
+                ir.resize(other_ir.len(), 0.0);
+            }
+            for i in 0..ir.len() {
+                let other = if i < other_ir.len() {other_ir[i]} else {0.0};
+                ir[i] = (1.0-blend)*ir[i] + blend*other;
+            }
+            normalize_energy(&mut ir);
         }
+        let reverb = Reverb::new(&ir, &mut self.fft_planner.borrow_mut());
+        if self.reverb_cache.len() >= MAX_CACHED_REVERBS {
+            self.reverb_cache.remove(0);
+        }
+        self.reverb_cache.push(CachedReverb {which, blended, blend, reverb: reverb.clone()});
+        reverb
     }
 
-    /// Start playing a new note.
-    fn note_on(&mut self, note_index: i32, velocity: f32) -> Result<(), String> {
-        if note_index < self.instrument_type.lowest_note() || note_index > self.instrument_type.highest_note() {
-            // The note index is outside the range of this instrument.  Ignore it.
+    /// Get one of the bundled impulse responses.  Each one is decoded the first time it is needed,
+    /// then kept so that later changes of instrument type do not need to decode it again.
+    fn body_ir(&mut self, which: BundledIR) -> Vec<f32> {
+        if let Some((_, ir)) = self.ir_cache.iter().find(|(cached, _)| *cached == which) {
+            return ir.clone();
+        }
+        let ir = load_body_ir(which);
+        self.ir_cache.push((which, ir.clone()));
+        ir
+    }
 
-            return Ok(());
+    /// Start playing a new note.  This fails if the note is outside the range of the instrument,
+    /// which lies within the range of valid MIDI notes, or if no division is available to play it.
+    /// In divisi mode, notes are assigned to divisions the same way as in polyphonic mode.
+    fn note_on(&mut self, note_index: i32, velocity: f32) -> Result<(), SynthError> {
+        if note_index < self.instrument_type.lowest_note() || note_index > self.instrument_type.highest_note() {
+            return Err(SynthError::NoteOutOfRange {note_index});
         }
         let mut division_index = usize::MAX;
-        if self.polyphonic {
+        if self.polyphonic || self.divisi {
             // Select a division to play the note.  First try to find one that is completely idle.
 
             for (i, division) in self.divisions.borrow().iter().enumerate() {
@@ -250,32 +1103,109 @@ impl Director {
             division_index = 0;
         }
         if division_index != usize::MAX {
-            self.steps_until_off = 10000;
-            self.divisions.borrow_mut()[division_index].note_on(note_index, velocity, self)
+            // Successive notes alternate between down-bows and up-bows.  Notes played legato continue
+            // on the same bow, and after a silence the next note starts with a down-bow again.
+
+            let legato = !self.polyphonic && !self.divisi && self.divisions.borrow()[0].current_note != -1;
+            if self.steps_until_off == 0 {
+                self.next_bow_down = true;
+            }
+            let down_bow = if legato {!self.next_bow_down} else {self.next_bow_down};
+            if !legato {
+                self.next_bow_down = !self.next_bow_down;
+            }
+            self.steps_until_off = self.idle_samples;
+            self.divisions.borrow_mut()[division_index].note_on(note_index, velocity, down_bow, self)
         }
         else {
-            // No division is available.  Skip the note.
+            Err(SynthError::NoVoiceAvailable)
+        }
+    }
+
+    /// In divisi mode, divide the instruments among the notes that are currently held, so each note
+    /// is played by only some of them.  Instruments are dealt out to the notes in turn, so each
+    /// note is spread across the ensemble.  If there are more notes than instruments, the highest
+    /// notes are played and the rest are silenced until enough notes are released.  Divisions
+    /// that are releasing a note keep the instruments they had.  When divisi is disabled, every
+    /// division uses all instruments.  This only does anything when the held notes have changed.
+    fn update_divisi(&mut self) {
+        if self.divisions.borrow().iter().map(|d| d.current_note).eq(self.divisi_notes.iter().copied()) {
+            return;
+        }
+        self.divisi_notes.clear();
+        self.divisi_notes.extend(self.divisions.borrow().iter().map(|d| d.current_note));
+        let notes = &self.divisi_notes;
+        let held = notes.iter().filter(|&&note| note != -1).count();
+        let playing = held.min(self.instrument_count);
+        for (i, division) in self.divisions.borrow_mut().iter_mut().enumerate() {
+            if !self.divisi {
+                division.divisi_gain.fill(1.0);
+            }
+            else if notes[i] != -1 {
+                // Rank the notes from highest to lowest.
 
-            Ok(())
+                let rank = notes.iter().enumerate().filter(|&(j, &note)| note > notes[i] || (note == notes[i] && j < i)).count();
+                for (j, gain) in division.divisi_gain.iter_mut().enumerate() {
+                    *gain = if rank < playing && j%playing == rank {1.0} else {0.0};
+                }
+            }
+            division.update_pan_positions(self);
+        }
+    }
+
+    /// Release any note that has been playing for longer than the maximum note duration.  This
+    /// protects against notes that would otherwise play forever because a NoteOff was lost.
+    fn release_stuck_notes(&mut self) {
+        for division in self.divisions.borrow_mut().iter_mut() {
+            if division.current_note != -1 && self.step-division.note_start > self.max_note_duration {
+                division.sostenuto_held = false;
+                division.sostenuto_release = None;
+                division.note_off(division.current_note, 0.0, self);
+            }
         }
     }
 
     /// End a current note.
-    fn note_off(&mut self, note_index: i32) {
+    fn note_off(&mut self, note_index: i32, velocity: f32) {
         for division in self.divisions.borrow_mut().iter_mut() {
-            division.note_off(note_index, self)
+            division.note_off(note_index, velocity, self)
         }
    }
 
     /// This is called repeated to generate audio data.  Each generates the two channels
     /// (left, right) for the next sample.
     pub fn generate(&mut self) -> (f32, f32) {
-        // Deal with the queues of Messages and Transitions.  This only needs to be done occassionally.
+        self.begin_step();
 
-        if self.step%100 == 0 {
-            self.process_messages();
+        // When bypassed, Messages are still processed so the settings stay current, but no audio is
+        // generated.  Notes resume where they left off when the bypass is turned off.
+
+        if self.synth_bypass {
+            return (0.0, 0.0);
+        }
+
+        // In test tone mode, the synthesis model is bypassed and a pure sine wave is output.
+
+        let polarity = if self.polarity_invert {-1.0} else {1.0};
+        if let Some(frequency) = self.test_tone {
+            let value = polarity*TEST_TONE_LEVEL*(2.0*PI*self.test_tone_phase).sin();
+            self.test_tone_phase = (self.test_tone_phase + frequency/SAMPLE_RATE as f32) % 1.0;
+            return (value, value);
+        }
+
+        // Changes to the volume are smoothed to avoid zipper noise.
+
+        if self.volume != self.volume_target {
+            if (self.volume-self.volume_target).abs() < 1e-4 {
+                self.volume = self.volume_target;
+            }
+            else {
+                self.volume += VOLUME_SMOOTHING*(self.volume_target-self.volume);
+            }
+            for division in self.divisions.borrow_mut().iter_mut() {
+                division.update_volume(self);
+            }
         }
-        self.step += 1;
 
         // If nothing has been played for a while, we can return without doing anything.
 
@@ -288,31 +1218,340 @@ impl Director {
 
         let mut left = 0.0;
         let mut right = 0.0;
+        let output_scale = if self.polarity_invert {-1.0} else {1.0}*OUTPUT_LEVEL/(self.instrument_count as f32).sqrt();
         for division in self.divisions.borrow_mut().iter_mut() {
             let (div_left, div_right) = division.generate(self);
             left += div_left;
             right += div_right;
+            if self.separate_outputs {
+                for (output, signal) in self.instrument_outputs.iter_mut().zip(&division.outputs) {
+                    *output += output_scale*signal;
+                }
+            }
+        }
+        if self.voice_budget > 0 {
+            self.enforce_voice_budget();
+        }
+        self.process_output(left, right)
+    }
+
+    /// Process one sample of external audio in place of the instruments.  The input passes through
+    /// the body resonance and all the processing that is normally applied to the instruments, so
+    /// the synthesizer acts as a resonator for other sounds.  Messages are still processed, but
+    /// notes are not heard.
+    pub fn process_input(&mut self, left: f32, right: f32) -> (f32, f32) {
+        self.begin_step();
+        if self.synth_bypass {
+            return (0.0, 0.0);
+        }
+
+        // The input is at a normal audio level, so it is scaled up to cancel the gain that gets
+        // applied to the instruments.  Keep the Director active for as long as input arrives.  With a
+        // single instrument the input is mixed to mono on both channels, so process_output() finds
+        // no side signal in it.
+
+        let gain = (self.instrument_count as f32).sqrt()/OUTPUT_LEVEL;
+        let (left, right) = if self.reverb.len() == 1 {let mono = 0.5*(left+right); (mono, mono)} else {(left, right)};
+        self.steps_until_off = self.steps_until_off.max(100);
+        self.process_output(gain*left, gain*right)
+    }
+
+    /// This is called at the start of every step.  It deals with the queues of Messages and
+    /// Transitions, which only needs to be done occasionally.
+    fn begin_step(&mut self) {
+        if self.separate_outputs {
+            self.instrument_outputs.fill(0.0);
+        }
+        if self.step%100 == 0 {
+            self.process_messages();
+            self.update_bend();
+            if self.max_note_duration > 0 {
+                self.release_stuck_notes();
+            }
+            if self.divisi {
+                self.update_divisi();
+            }
+
+            // Delayed instruments may not have started following their transitions yet, so don't
+            // go idle while any are pending or any Division is still active.
+
+            if self.steps_until_off < 100 && self.divisions.borrow().iter().any(|d| !d.transitions.is_empty() || d.steps_until_off > 0) {
+                self.steps_until_off = 100;
+            }
+        }
+        self.step += 1;
+    }
+
+    /// Generate a block of audio, writing it into the left and right channels, which must have the
+    /// same length.  The result is identical to calling generate() once for each sample.  Messages
+    /// and Transitions are processed on the first and last step of every cycle of 100 steps, so
+    /// those steps are generated one at a time.  For the steps in between, each Instrument generates
+    /// all its samples at once, and they are then mixed, panned, and passed through the reverb
+    /// together.  See the block_benchmark example for a comparison of the speed.
+    pub fn generate_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        assert_eq!(left.len(), right.len());
+        let mut i = 0;
+        while i < left.len() {
+            let cycle_position = (self.step%100) as usize;
+            let block_size = (99-cycle_position).min(left.len()-i);
+            if cycle_position == 0 || cycle_position == 99 || !self.can_generate_block(block_size) {
+                (left[i], right[i]) = self.generate();
+                i += 1;
+            }
+            else {
+                self.generate_block_internal(&mut left[i..i+block_size], &mut right[i..i+block_size]);
+                i += block_size;
+            }
+        }
+    }
+
+    /// Generate audio into a buffer of interleaved stereo samples [L, R, L, R, ...], whose length
+    /// must be even.  The result is identical to calling generate() once for each pair of samples.
+    /// This is convenient for callback based audio APIs that expect interleaved output.
+    pub fn generate_interleaved(&mut self, out: &mut [f32]) {
+        assert!(out.len()%2 == 0, "The length of the output must be even");
+        for frame in out.chunks_exact_mut(2) {
+            (frame[0], frame[1]) = self.generate();
+        }
+    }
+
+    /// Get whether a block of samples can be generated together.  Anything that requires updating
+    /// the Instruments between samples forces them to be generated one at a time.
+    fn can_generate_block(&self, block_size: usize) -> bool {
+        !self.synth_bypass && self.test_tone.is_none() && self.volume == self.volume_target &&
+            self.voice_budget == 0 && !self.separate_outputs && self.steps_until_off >= block_size as i32
+    }
+
+    /// This is called by generate_block() to generate a block that contains no periodic updates.
+    fn generate_block_internal(&mut self, left: &mut [f32], right: &mut [f32]) {
+        self.step += left.len() as i64;
+        left.fill(0.0);
+        right.fill(0.0);
+        for division in self.divisions.borrow_mut().iter_mut() {
+            division.generate_block(self, left, right);
+        }
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            self.steps_until_off -= 1;
+            (*l, *r) = self.process_output(*l, *r);
+        }
+    }
+
+    /// Apply the body resonance, filters, and gain to the mixed output of all Divisions.
+    fn process_output(&mut self, mut left: f32, mut right: f32) -> (f32, f32) {
+        let polarity = if self.polarity_invert {-1.0} else {1.0};
+
+        // With a single instrument everything is processed in mono, but the tails of previous notes
+        // may be spread in stereo.  Split off the side signal so it can be added back after panning.
+
+        let mut side = 0.0;
+        if self.reverb.len() == 1 {
+            side = 0.5*(right-left);
+            left = 0.5*(left+right);
         }
         let mut left_resonance = self.body_resonance*self.reverb[0].process(left);
+        if self.reverb_crossfade > 0.0 {
+            left_resonance = (1.0-self.reverb_crossfade)*left_resonance + self.reverb_crossfade*self.old_body_resonance*self.old_reverb[0].process(left);
+        }
         if self.mute {
             left_resonance = self.left_mute_filter.process(left_resonance);
         }
-        left += left_resonance;
         if self.reverb.len() == 1 {
-            right = left;
+            left += left_resonance;
+            if self.mono_pan_gain != self.mono_pan_gain_target {
+                self.mono_pan_gain = smooth_pan_gain(self.mono_pan_gain, self.mono_pan_gain_target);
+            }
+            right = self.mono_pan_gain.1*left + side;
+            left = self.mono_pan_gain.0*left - side;
         }
         else {
             let mut right_resonance = self.body_resonance*self.reverb[1].process(right);
+            if self.reverb_crossfade > 0.0 {
+                right_resonance = (1.0-self.reverb_crossfade)*right_resonance + self.reverb_crossfade*self.old_body_resonance*self.old_reverb[1].process(right);
+            }
             if self.mute {
                 right_resonance = self.right_mute_filter.process(right_resonance);
             }
+
+            // The width of the resonance is adjusted by scaling its side signal, which leaves
+            // the dry signal and the mono sum unchanged.
+
+            if self.reverb_width != 1.0 {
+                let mid = 0.5*(left_resonance+right_resonance);
+                let side = 0.5*self.reverb_width*(left_resonance-right_resonance);
+                left_resonance = mid+side;
+                right_resonance = mid-side;
+            }
+            left += left_resonance;
             right += right_resonance;
         }
-        if self.steps_until_off < 100 && (left.abs() > 0.001 || right.abs() > 0.001) {
+        if self.reverb_crossfade > 0.0 {
+            self.reverb_crossfade -= 1.0/REVERB_CROSSFADE_STEPS;
+            if self.reverb_crossfade <= 0.0 {
+                self.reverb_crossfade = 0.0;
+                self.old_reverb.clear();
+            }
+        }
+        for ((_, _, gain), (left_filter, right_filter)) in self.body_modes.iter().zip(&mut self.body_mode_filters) {
+            if *gain != 0.0 {
+                left = left_filter.process(left);
+                right = right_filter.process(right);
+            }
+        }
+        if self.air > 0.0 {
+            left = self.left_air_filter.process(left);
+            right = self.right_air_filter.process(right);
+        }
+        left = self.left_rumble_filter.process(left);
+        right = self.right_rumble_filter.process(right);
+        if self.tilt_eq != 0.0 {
+            left = self.left_tilt_filter.process(left);
+            right = self.right_tilt_filter.process(right);
+        }
+        if self.steps_until_off < 100 && (left.abs() > self.idle_level || right.abs() > self.idle_level) {
             self.steps_until_off = 100;
         }
-        let scale = 0.01/(self.instrument_count as f32).sqrt();
-        (scale*left, scale*right)
+        let mut scale = OUTPUT_LEVEL/(self.instrument_count as f32).sqrt();
+        if self.auto_gain {
+            scale *= self.update_auto_gain(scale*left, scale*right);
+        }
+        scale *= polarity;
+        let output = if self.compressor_enabled {self.compressor.process(scale*left, scale*right)} else {(scale*left, scale*right)};
+        if self.steps_until_off == 0 {
+            self.clear_output();
+        }
+        output
+    }
+
+    /// This is called when the Director becomes idle.  Anything left in the reverb and filters is
+    /// below the idle level, but it would otherwise resume when the next note starts.
+    fn clear_output(&mut self) {
+        for reverb in self.reverb.iter_mut().chain(self.old_reverb.iter_mut()) {
+            reverb.reset();
+        }
+        self.left_mute_filter.reset();
+        self.right_mute_filter.reset();
+        self.left_air_filter.reset();
+        self.right_air_filter.reset();
+        self.left_rumble_filter.reset();
+        self.right_rumble_filter.reset();
+        self.left_tilt_filter.reset();
+        self.right_tilt_filter.reset();
+        for (left_filter, right_filter) in self.body_mode_filters.iter_mut() {
+            left_filter.reset();
+            right_filter.reset();
+        }
+        self.compressor.reset();
+    }
+
+    /// Generate a block of audio while applying a timeline of Messages.  Each event consists of a
+    /// sample index (relative to the start of the block) and a Message to apply immediately before
+    /// generating that sample.  Events are applied in order of their sample index, and those with
+    /// the same index are applied in the order given.  Events whose index is before the start or
+    /// after the end of the block are applied at the start or end respectively.  Messages sent
+    /// through the channel are still processed as usual.  This returns the (left, right) channels.
+    pub fn generate_with_events(&mut self, events: &[(i64, Message)], total_samples: usize) -> (Vec<f32>, Vec<f32>) {
+        let mut order: Vec<usize> = (0..events.len()).collect();
+        order.sort_by_key(|&i| events[i].0);
+        let mut next_event = 0;
+        let mut left = Vec::with_capacity(total_samples);
+        let mut right = Vec::with_capacity(total_samples);
+        for i in 0..total_samples {
+            while next_event < order.len() && events[order[next_event]].0 <= i as i64 {
+                self.handle_message(events[order[next_event]].1.clone());
+                next_event += 1;
+            }
+            let (l, r) = self.generate();
+            left.push(l);
+            right.push(r);
+        }
+        while next_event < order.len() {
+            self.handle_message(events[order[next_event]].1.clone());
+            next_event += 1;
+        }
+        (left, right)
+    }
+
+    /// Track the loudness of the output and return the gain to apply to it.  The mean square
+    /// amplitude is measured over a short window, and the gain moves smoothly toward the value
+    /// that would bring it to a fixed target level.  The gain is only adjusted while the signal is
+    /// loud enough, so releases and silence don't get boosted, and it is limited to a moderate
+    /// range so that dynamics are compressed rather than eliminated.
+    fn update_auto_gain(&mut self, left: f32, right: f32) -> f32 {
+        self.auto_gain_power = AUTO_GAIN_POWER_DECAY*self.auto_gain_power + (1.0-AUTO_GAIN_POWER_DECAY)*0.5*(left*left + right*right);
+        if self.step%100 == 0 {
+            let rms = self.auto_gain_power.sqrt();
+            if rms > AUTO_GAIN_MIN_RMS {
+                self.auto_gain_target = (AUTO_GAIN_TARGET_RMS/rms).clamp(AUTO_GAIN_MIN, AUTO_GAIN_MAX);
+            }
+        }
+        self.auto_gain_value += AUTO_GAIN_SMOOTHING*(self.auto_gain_target-self.auto_gain_value);
+        self.auto_gain_value
+    }
+
+    /// Get the number of elements currently being synthesized.  This is the number of instruments in
+    /// every active Division (including ones that are fading out after being removed), plus the tails
+    /// of previous notes they are still playing.
+    pub fn active_voice_count(&self) -> usize {
+        self.divisions.borrow().iter().filter(|d| d.steps_until_off > 0).map(|d| d.instruments.iter().chain(d.retiring.iter()).map(|i| 1+i.decaying_note_count()).sum::<usize>()).sum()
+    }
+
+    /// If more elements are being synthesized than the voice budget allows, stop the quietest tails of
+    /// previous notes until it is satisfied.  Instruments that are playing the current notes are never
+    /// stopped, so the budget cannot be met if it is smaller than the number of them.
+    fn enforce_voice_budget(&mut self) {
+        let mut excess = self.active_voice_count().saturating_sub(self.voice_budget);
+        let mut divisions = self.divisions.borrow_mut();
+        while excess > 0 {
+            let mut quietest: Option<(usize, usize, usize, f32)> = None;
+            for (i, division) in divisions.iter().enumerate() {
+                if division.steps_until_off == 0 {
+                    continue;
+                }
+                for (j, instrument) in division.instruments.iter().enumerate() {
+                    if let Some((index, level)) = instrument.quietest_tail() {
+                        if quietest.is_none_or(|q| level < q.3) {
+                            quietest = Some((i, j, index, level));
+                        }
+                    }
+                }
+            }
+            match quietest {
+                Some((i, j, index, _)) => divisions[i].instruments[j].remove_tail(index),
+                None => break
+            }
+            excess -= 1;
+        }
+    }
+
+    /// Compute the target pitch bend from the most recently requested one.  When quantizing, the
+    /// bend is rounded to the nearest semitone and update_bend() moves smoothly toward it.
+    /// Otherwise it is applied immediately.
+    fn set_bend_target(&mut self) {
+        let semitones = if self.bend_quantize {self.bend_semitones.round()} else {self.bend_semitones};
+        self.bend_target = f32::powf(2.0, semitones/12.0);
+        if !self.bend_quantize {
+            self.bend = self.bend_target;
+            for division in self.divisions.borrow_mut().iter_mut() {
+                division.update_frequency(self);
+            }
+        }
+    }
+
+    /// This is called occasionally by generate().  If the pitch bend has not yet reached its
+    /// target value, it moves part of the way there.
+    fn update_bend(&mut self) {
+        if self.bend == self.bend_target {
+            return;
+        }
+        if (self.bend-self.bend_target).abs() < 1e-4 {
+            self.bend = self.bend_target;
+        }
+        else {
+            self.bend += BEND_SMOOTHING*(self.bend_target-self.bend);
+        }
+        for division in self.divisions.borrow_mut().iter_mut() {
+            division.update_frequency(self);
+        }
     }
 
     /// This is called occasionally by generate().  It processes any Messages that have been
@@ -321,109 +1560,449 @@ impl Director {
         loop {
             match self.message_receiver.try_recv() {
                 Ok(message) => {
-                    match message {
-                        Message::Reinitialize {instrument_type, instrument_count} => {
-                            self.initialize_instruments(instrument_type, instrument_count);
-                        }
-                        Message::NoteOn {note_index, velocity} => {
-                            let _ = self.note_on(note_index, velocity);
-                        }
-                        Message::NoteOff {note_index} => {
-                            self.note_off(note_index);
-                        }
-                        Message::AllNotesOff => {
-                            for division in self.divisions.borrow_mut().iter_mut() {
-                                division.note_off(division.current_note, self)
-                            }
-                        }
-                        Message::SetVolume {volume} => {
-                            self.volume = volume;
-                            for division in self.divisions.borrow_mut().iter_mut() {
-                                division.update_volume(self);
-                            }
-                        }
-                        Message::SetArticulation {articulation} => {
-                            self.articulation = articulation;
-                            self.apply_filter = match articulation {
-                                Articulation::Arco => true,
-                                Articulation::Marcato => true,
-                                Articulation::Glissando => true,
-                                Articulation::Spiccato => true,
-                                Articulation::Pizzicato => false,
-                                Articulation::ColLegno => true,
-                                Articulation::Tremolo => true
-                            };
-                        }
-                        Message::SetPitchBend {semitones} => {
-                            self.bend = f32::powf(2.0, semitones as f32/12.0);
-                            for division in self.divisions.borrow_mut().iter_mut() {
-                                division.update_frequency(self);
-                            }
-                        }
-                        Message::SetVibrato {vibrato} => {
-                            self.vibrato = vibrato;
-                            for division in self.divisions.borrow_mut().iter_mut() {
-                                division.update_vibrato(self);
-                            }
-                        }
-                        Message::SetBowPosition {bow_position} => {
-                            self.bow_position = bow_position;
-                            for division in self.divisions.borrow_mut().iter_mut() {
-                                division.update_bow_position(self);
-                            }
-                        }
-                        Message::SetBowNoise {bow_noise} => {
-                            self.bow_noise = bow_noise;
-                        }
-                        Message::SetReleaseRate {release} => {
-                            self.release_rate = release;
-                        }
-                        Message::SetHarmonics {harmonics} => {
-                            self.harmonics = harmonics;
-                            for division in self.divisions.borrow_mut().iter_mut() {
-                                division.update_harmonics(self);
-                            }
-                        }
-                        Message::SetMute {mute} => {
-                            self.mute = mute;
-                            self.left_mute_filter.reset();
-                            self.right_mute_filter.reset();
-                        }
-                        Message::SetPolyphonic {polyphonic} => {
-                            self.polyphonic = polyphonic;
-                        }
-                        Message::SetStereoWidth {width} => {
-                            self.stereo_width = width;
-                            for division in self.divisions.borrow_mut().iter_mut() {
-                                division.update_pan_positions(self);
-                            }
-                        }
-                        Message::SetMaxInstrumentDelay {max_delay} => {
-                            self.max_instrument_delay = max_delay;
-                            for division in self.divisions.borrow_mut().iter_mut() {
-                                division.update_instrument_delays(self);
-                            }
+                    self.handle_message(message);
+                }
+                Err(mpsc::TryRecvError::Empty) => {
+                    break;
+                }
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.disconnected = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Get the Messages that set the individual settings controlled by the ensemble tightness.  From
+    /// 1 down to 0.5, only the delay between instruments increases.  Below 0.5 the instruments also
+    /// drift in pitch, vary in attack speed and vibrato, and the unison voices spread apart, all
+    /// growing linearly until they reach the LOOSE_* values at 0.
+    fn ensemble_tightness_messages(&self) -> Vec<Message> {
+        let tightness = self.ensemble_tightness;
+        let looseness = (1.0-2.0*tightness).max(0.0);
+        vec![
+            Message::SetMaxInstrumentDelay {max_delay: (LOOSE_INSTRUMENT_DELAY as f32*(1.0-tightness)).round() as i64},
+            Message::SetPitchDrift {amount: LOOSE_PITCH_DRIFT*looseness},
+            Message::SetAttackJitter {jitter: LOOSE_ATTACK_JITTER*looseness},
+            Message::SetVibratoGradient {amount: LOOSE_VIBRATO_GRADIENT*looseness},
+            Message::SetUnisonVoices {count: self.unison_voices, detune: LOOSE_UNISON_DETUNE*looseness}
+        ]
+    }
+
+    /// Get the frequency of a note, including any change to its tuning made with SetNoteTuning.
+    fn note_frequency(&self, note_index: i32) -> f32 {
+        let cents = self.note_tuning[note_index as usize];
+        440.0 * f32::powf(2.0, ((note_index-69) as f32+0.01*cents)/12.0)
+    }
+
+    /// Apply the changes specified by a Message.
+    fn handle_message(&mut self, message: Message) {
+        match message {
+            Message::Reinitialize {instrument_type, instrument_count} => {
+                if self.can_switch_type(instrument_type, instrument_count) {
+                    self.switch_instrument_type(instrument_type);
+                }
+                else {
+                    self.initialize_instruments(instrument_type, instrument_count);
+                }
+            }
+            Message::SetActiveInstrument {instrument_type} => {
+                if self.can_switch_type(instrument_type, self.instrument_count) {
+                    self.switch_instrument_type(instrument_type);
+                }
+                else if instrument_type != self.instrument_type {
+                    self.initialize_instruments(instrument_type, self.instrument_count);
+                }
+            }
+            Message::NoteOn {note_index, velocity} => {
+                // Notes that can't be played are skipped.  The error is recorded so the host can
+                // find out about it with take_last_error().
+
+                if let Err(error) = self.note_on(note_index, velocity) {
+                    self.last_error = Some(error);
+                }
+            }
+            Message::NoteOff {note_index, velocity} => {
+                self.note_off(note_index, velocity);
+            }
+            Message::AllNotesOff => {
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.sostenuto_held = false;
+                    division.sostenuto_release = None;
+                    division.note_off(division.current_note, 0.0, self)
+                }
+                self.next_bow_down = true;
+            }
+            Message::SetVolume {volume} => {
+                self.volume_target = volume;
+            }
+            Message::SetArticulation {articulation} => {
+                self.articulation = articulation;
+                self.apply_filter = match articulation {
+                    Articulation::Arco => true,
+                    Articulation::Marcato => true,
+                    Articulation::Glissando => true,
+                    Articulation::Spiccato => true,
+                    Articulation::Pizzicato => false,
+                    Articulation::ColLegno => true,
+                    Articulation::Tremolo => true
+                };
+            }
+            Message::SetPitchBend {semitones} => {
+                self.bend_semitones = semitones;
+                self.set_bend_target();
+            }
+            Message::SetVibratoShape {shape} => {
+                self.vibrato_shape = shape;
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_vibrato(self);
+                }
+            }
+            Message::SetVibratoPhaseReset {enabled} => {
+                self.vibrato_phase_reset = enabled;
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_vibrato(self);
+                }
+            }
+            Message::SetVibratoGradient {amount} => {
+                self.vibrato_gradient = amount.clamp(0.0, 1.0);
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_vibrato(self);
+                }
+            }
+            Message::SetVibrato {vibrato} => {
+                self.vibrato = vibrato;
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_vibrato(self);
+                }
+            }
+            Message::SetBowPosition {bow_position} => {
+                self.bow_position = bow_position;
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_bow_position(self);
+                }
+            }
+            Message::SetBowNoise {bow_noise} => {
+                self.bow_noise = bow_noise;
+            }
+            Message::SetReleaseRate {release} => {
+                self.release_rate = release;
+            }
+            Message::SetHarmonics {harmonics} => {
+                self.harmonics = harmonics;
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_harmonics(self);
+                }
+            }
+            Message::SetMute {mute} => {
+                self.mute = mute;
+                self.left_mute_filter.reset();
+                self.right_mute_filter.reset();
+            }
+            Message::SetPolyphonic {polyphonic} => {
+                self.polyphonic = polyphonic;
+            }
+            Message::SetStringCrossing {enabled} => {
+                self.string_crossing = enabled;
+            }
+            Message::SetAttackScale {scale} => {
+                self.attack_scale = scale.clamp(MIN_ENVELOPE_SCALE, MAX_ENVELOPE_SCALE);
+            }
+            Message::SetReleaseScale {scale} => {
+                self.release_scale = scale.clamp(MIN_ENVELOPE_SCALE, MAX_ENVELOPE_SCALE);
+            }
+            Message::SetNoiseGate {amount} => {
+                self.noise_gate = amount.clamp(0.0, 1.0);
+            }
+            Message::SetEnsembleTightness {value} => {
+                self.ensemble_tightness = value.clamp(0.0, 1.0);
+                for message in self.ensemble_tightness_messages() {
+                    self.handle_message(message);
+                }
+            }
+            Message::SetNoteTuning {note_index, cents} => {
+                if MIDI_NOTE_RANGE.contains(&note_index) {
+                    self.note_tuning[note_index as usize] = cents.clamp(-MAX_NOTE_TUNING, MAX_NOTE_TUNING);
+                    for division in self.divisions.borrow_mut().iter_mut() {
+                        division.retune_note(note_index, self);
+                    }
+                }
+            }
+            Message::SetDivisi {enabled} => {
+                self.divisi = enabled;
+                self.divisi_notes.clear();
+                self.update_divisi();
+            }
+            Message::SetStereoWidth {width} => {
+                self.stereo_width = width;
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_pan_positions(self);
+                }
+            }
+            Message::SetPanCenter {position} => {
+                self.pan_center = position.clamp(-1.0, 1.0);
+                self.update_mono_pan_gain();
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_pan_positions(self);
+                }
+            }
+            Message::SetMaxInstrumentDelay {max_delay} => {
+                self.max_instrument_delay = max_delay;
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_instrument_delays(self);
+                }
+            }
+            Message::SetAttackJitter {jitter} => {
+                // Each instrument's attack is scaled by a factor between 1-jitter/2 and 1+jitter/2,
+                // which must stay positive.
+
+                self.attack_jitter = jitter.clamp(0.0, 1.0);
+                if self.attack_jitter.is_nan() {
+                    self.attack_jitter = 0.0;
+                }
+            }
+            Message::SetReleaseTail {ms} => {
+                self.release_tail = ms.clamp(0, MAX_RELEASE_TAIL_MS)*SAMPLE_RATE as i64/1000;
+            }
+            Message::SetBendQuantize {enabled} => {
+                self.bend_quantize = enabled;
+                self.set_bend_target();
+            }
+            Message::SetBodyBlend {other_type, amount} => {
+                self.body_blend_type = other_type;
+                self.body_blend = amount.clamp(0.0, 1.0);
+                self.create_reverbs();
+            }
+            Message::SetPhaseDecorrelation {amount} => {
+                self.phase_decorrelation = amount.clamp(0.0, 1.0);
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_phase_decorrelation(self);
+                }
+            }
+            Message::SetExcitationCoherence {coherence} => {
+                self.excitation_coherence = coherence.clamp(0.0, 1.0);
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_excitation_coherence(self);
+                }
+            }
+            Message::SetExcitationVariation {amount} => {
+                self.excitation_variation = amount.clamp(0.0, 1.0);
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_excitation_variation(self);
+                }
+            }
+            Message::SetSustainDamping {amount} => {
+                self.sustain_damping = amount.clamp(0.0, 1.0);
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_sustain_damping(self);
+                }
+            }
+            Message::SetAttackNoise {amount} => {
+                self.attack_noise = amount.clamp(0.0, 1.0);
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_attack_noise(self);
+                }
+            }
+            Message::SetEntryScoop {cents, ms} => {
+                self.entry_scoop_cents = cents.clamp(0.0, MAX_ENTRY_SCOOP_CENTS);
+                self.entry_scoop_time = ms.clamp(0, MAX_ENTRY_SCOOP_MS)*SAMPLE_RATE as i64/1000;
+            }
+            Message::SetTailDecay {rate} => {
+                self.tail_decay = rate.clamp(MIN_TAIL_DECAY, MAX_TAIL_DECAY);
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_tail_decay(self);
+                }
+            }
+            Message::SetMaxHarmonic {ratio} => {
+                self.max_harmonic = ratio.clamp(0.0, 1.0);
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_max_harmonic(self);
+                }
+            }
+            Message::SetSustainLevel {level} => {
+                self.sustain_level = level.max(0.0);
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_sustain_level(self);
+                }
+            }
+            Message::SetIdleThreshold {samples, level} => {
+                // Processing stops once nothing has been played for the specified number of samples
+                // and the output has fallen below the specified level.
+
+                self.idle_samples = samples.clamp(100, i32::MAX as i64) as i32;
+                self.idle_level = level.max(0.0);
+            }
+            Message::SetTestTone {enabled, frequency} => {
+                self.test_tone = if enabled {Some(frequency.clamp(20.0, 0.45*SAMPLE_RATE as f32))} else {None};
+                self.test_tone_phase = 0.0;
+            }
+            Message::SetLoudnessCompensation {enabled} => {
+                self.loudness_compensation = enabled;
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_loudness_gain(self);
+                    division.update_volume(self);
+                }
+            }
+            Message::SetMonoCompatible {enabled} => {
+                self.mono_compatible = enabled;
+                self.update_mono_pan_gain();
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_pan_positions(self);
+                }
+            }
+            Message::SetImpulseResponse {which} => {
+                self.impulse_response = which;
+                self.create_reverbs();
+            }
+            Message::SetTremoloRate {hz} => {
+                self.tremolo_rate = if hz > 0.0 {hz.clamp(MIN_TREMOLO_RATE, MAX_TREMOLO_RATE)} else {0.0};
+            }
+            Message::SetStereoTails {enabled} => {
+                self.stereo_tails = enabled;
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_tail_spread(self);
+                }
+            }
+            Message::SetRetriggerSameNote {enabled} => {
+                self.retrigger_same_note = enabled;
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_retrigger_same_note(self);
+                }
+            }
+            Message::SetSostenuto {engaged} => {
+                // Pressing the pedal captures the notes that are currently playing.  They are held
+                // until it is released, even if their keys are released first.
+
+                if engaged && !self.sostenuto {
+                    for division in self.divisions.borrow_mut().iter_mut() {
+                        division.sostenuto_held = division.current_note != -1;
+                    }
+                }
+                if !engaged && self.sostenuto {
+                    for division in self.divisions.borrow_mut().iter_mut() {
+                        division.sostenuto_held = false;
+                        if let Some(velocity) = division.sostenuto_release.take() {
+                            division.note_off(division.current_note, velocity, self);
                         }
                     }
                 }
-                Err(_) => {
-                    break;
+                self.sostenuto = engaged;
+            }
+            Message::SetMaxNoteDuration {ms} => {
+                self.max_note_duration = ms.max(0)*SAMPLE_RATE as i64/1000;
+            }
+            Message::SetPitchDrift {amount} => {
+                self.pitch_drift = amount.clamp(0.0, 1.0);
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_pitch_drift(self);
+                }
+            }
+            Message::SetUnisonVoices {count, detune} => {
+                self.unison_voices = count.clamp(1, MAX_UNISON_VOICES);
+                self.unison_detune = detune.clamp(0.0, MAX_UNISON_DETUNE);
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_unison_voices(self);
+                }
+            }
+            Message::SetInharmonicity {amount} => {
+                self.inharmonicity = amount.clamp(0.0, 1.0);
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_inharmonicity(self);
+                }
+            }
+            Message::SetReverbWidth {width} => {
+                self.reverb_width = width.clamp(0.0, MAX_REVERB_WIDTH);
+            }
+            Message::SetBodyMode {index, freq, q, gain} => {
+                if index < MAX_BODY_MODES {
+                    let freq = freq.clamp(20.0, 0.45*SAMPLE_RATE as f32);
+                    let q = q.clamp(0.5, MAX_BODY_MODE_Q);
+                    let gain = gain.clamp(-MAX_BODY_MODE_GAIN, MAX_BODY_MODE_GAIN);
+                    self.body_modes[index] = (freq, q, gain);
+                    let linear_gain = 10.0_f32.powf(gain/20.0);
+                    self.body_mode_filters[index].0.set_parameters(freq, q, linear_gain);
+                    self.body_mode_filters[index].1.set_parameters(freq, q, linear_gain);
                 }
             }
+            Message::SetPolarityInvert {enabled} => {
+                self.polarity_invert = enabled;
+            }
+            Message::SetSynthBypass {enabled} => {
+                self.synth_bypass = enabled;
+            }
+            Message::SetSeparateOutputs {enabled} => {
+                self.separate_outputs = enabled;
+                self.instrument_outputs.fill(0.0);
+            }
+            Message::SetVoiceBudget {max} => {
+                self.voice_budget = max;
+            }
+            Message::SetDebugTap {tap} => {
+                self.debug_tap = tap;
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_debug_tap(self);
+                }
+            }
+            Message::SetBowAlternation {amount} => {
+                self.bow_alternation = amount.clamp(0.0, 1.0);
+            }
+            Message::SetInstrumentGains {gains} => {
+                self.instrument_gains = gains.iter().map(|g| g.max(0.0)).collect();
+                for division in self.divisions.borrow_mut().iter_mut() {
+                    division.update_pan_positions(self);
+                }
+            }
+            Message::SetAir {amount} => {
+                self.air = amount.clamp(0.0, 1.0);
+                self.left_air_filter.set_gain(1.0+AIR_MAX_BOOST*self.air);
+                self.right_air_filter.set_gain(1.0+AIR_MAX_BOOST*self.air);
+            }
+            Message::SetTiltEq {tilt} => {
+                self.tilt_eq = tilt.clamp(-1.0, 1.0);
+                let gain = 10.0_f32.powf(self.tilt_eq*MAX_TILT_DB/20.0);
+                self.left_tilt_filter.set_gain(gain);
+                self.right_tilt_filter.set_gain(gain);
+            }
+            Message::SetAutoGain {enabled} => {
+                self.auto_gain = enabled;
+                self.auto_gain_power = 0.0;
+                self.auto_gain_target = 1.0;
+                self.auto_gain_value = 1.0;
+            }
+            Message::SetCompressor {enabled, threshold, ratio} => {
+                if enabled && !self.compressor_enabled {
+                    self.compressor.reset();
+                }
+                self.compressor_enabled = enabled;
+                self.compressor_threshold = threshold.min(0.0);
+                self.compressor_ratio = ratio.max(1.0);
+                self.compressor.set_threshold(self.compressor_threshold);
+                self.compressor.set_ratio(self.compressor_ratio);
+            }
         }
     }
 }
 
 impl Division {
-    pub fn new() -> Self {
+    pub fn new(seed: Option<u32>) -> Self {
         Self {
             instruments: vec![],
-            random: Random::new(),
+            retiring: vec![],
+            retiring_pan_gain: vec![],
+            retire_steps: 0,
+            random: match seed {
+                Some(seed) => Random::with_seed(seed),
+                None => Random::new()
+            },
+            seed: seed,
             steps_until_off: 0,
             current_note: -1,
             current_note_articulation: Articulation::Arco,
             transitions: vec![],
             instrument_delays: vec![],
+            target_instrument_delays: vec![],
+            last_delay_step: 0,
+            attack_scale: vec![],
             envelope: vec![],
             frequency: vec![],
             tremolo_start: vec![],
@@ -433,23 +2012,66 @@ impl Division {
             envelope_after_transitions: 0.0,
             frequency_after_transitions: 0.0,
             instrument_pan: vec![],
+            pan_gain: vec![],
+            pan_gain_target: vec![],
             bow_position_shift: vec![],
+            bow_direction_gain: 1.0,
+            bow_direction_shift: 0.0,
+            loudness_gain: 1.0,
+            old_loudness_gain: 1.0,
             noise_position: vec![],
-            noise_filter: vec![]
+            noise_filter: vec![],
+            noise_gate_level: vec![],
+            noise_gate_gain: vec![],
+            outputs: vec![],
+            string_index: vec![],
+            sostenuto_held: false,
+            divisi_gain: vec![],
+            sostenuto_release: None,
+            note_start: 0,
+            block_signal: vec![],
+            block_side: vec![],
+            block_left: vec![],
+            block_right: vec![]
         }
     }
 
     /// Initialize the set of instruments controlled by this Director.  This is called when it is first
     /// created, and again whenever a Reinitialize message is received.
     fn initialize_instruments(&mut self, director: &Director) {
-        self.instruments.clear();
+        // If instruments are being removed while they are playing, keep them around until they
+        // have faded out.
+
         let instrument_count = director.instrument_count;
+        self.retiring.clear();
+        self.retiring_pan_gain.clear();
+        self.retire_steps = 0;
+        if self.steps_until_off > 0 && instrument_count < self.instruments.len() {
+            self.retiring = self.instruments.split_off(instrument_count);
+            self.retiring_pan_gain.extend_from_slice(&self.pan_gain[instrument_count..]);
+            for instrument in &mut self.retiring {
+                instrument.set_volume(0.0);
+            }
+            self.retire_steps = RETIRE_FADE_STEPS;
+            self.steps_until_off = self.steps_until_off.max(RETIRE_FADE_STEPS);
+        }
+        self.instruments.clear();
         for i in 0..instrument_count {
-            self.instruments.push(Instrument::new(director.instrument_type, i));
+            match self.seed {
+                Some(seed) => self.instruments.push(Instrument::new_seeded(director.instrument_type, i, seed.wrapping_add((i+1) as u32))),
+                None => self.instruments.push(Instrument::new(director.instrument_type, i))
+            }
         }
+        self.current_note = -1;
         self.transitions.clear();
         self.instrument_delays = vec![0; instrument_count];
+        self.divisi_gain = vec![1.0; instrument_count];
+        self.target_instrument_delays = vec![0; instrument_count];
+        self.last_delay_step = director.step;
+        self.attack_scale = vec![1.0; instrument_count];
         self.instrument_pan = vec![0.0; instrument_count];
+        self.pan_gain = vec![(0.0, 0.0); instrument_count];
+        self.pan_gain_target = vec![(0.0, 0.0); instrument_count];
         self.bow_position_shift = vec![0.0; instrument_count];
         self.envelope = vec![0.0; instrument_count];
         self.frequency = vec![440.0; instrument_count];
@@ -464,25 +2086,72 @@ impl Division {
             self.noise_position[i] = (director.noise_buffer.len() as f32*(i as f32+0.5*self.random.get_uniform())/instrument_count as f32) as usize;
         }
         self.noise_filter = vec![ResonantFilter::new(100.0, 100.0); instrument_count];
+        self.noise_gate_level = vec![0.0; instrument_count];
+        self.noise_gate_gain = vec![1.0; instrument_count];
+        self.outputs = vec![0.0; instrument_count];
+        self.string_index = vec![0; instrument_count];
         self.update_pan_positions(director);
+        self.pan_gain.copy_from_slice(&self.pan_gain_target);
         self.update_vibrato(director);
         self.update_harmonics(director);
+        self.update_phase_decorrelation(director);
+        self.update_tail_spread(director);
+        self.update_retrigger_same_note(director);
+        self.update_excitation_coherence(director);
+        self.update_excitation_variation(director);
+        self.update_sustain_damping(director);
+        self.update_max_harmonic(director);
+        self.update_attack_noise(director);
+        self.update_pitch_drift(director);
+        self.update_unison_voices(director);
+        self.update_inharmonicity(director);
+        self.update_tail_decay(director);
+        self.update_debug_tap(director);
+        self.update_sustain_level(director);
         self.update_volume(director);
         self.update_frequency(director);
         self.update_bow_position(director);
         self.update_instrument_delays(director);
     }
 
-    /// Start playing a new note.
-    fn note_on(&mut self, note_index: i32, velocity: f32, director: &Director) -> Result<(), String> {
-        self.transitions.clear();
+    /// Change the type of all Instruments, keeping the notes they are playing.
+    fn switch_instrument_type(&mut self, director: &Director) {
+        for instrument in self.instruments.iter_mut() {
+            instrument.set_instrument_type(director.instrument_type);
+        }
+        self.update_vibrato(director);
+        self.update_harmonics(director);
+        self.old_loudness_gain = self.loudness_gain;
+        self.update_loudness_gain(director);
+    }
+
+    /// Start playing a new note.  With bow alternation enabled, notes played on an up-bow are
+    /// slightly quieter and have a different bow position.
+    fn note_on(&mut self, note_index: i32, velocity: f32, down_bow: bool, director: &Director) -> Result<(), SynthError> {
+        self.sostenuto_held = false;
+        self.sostenuto_release = None;
+        self.note_start = director.step;
+        self.transitions.retain(|t| if let TransitionData::EnvelopeChange {..} = t.data {true} else {false});
+        self.supersede_envelope_transitions(director);
+        if down_bow {
+            self.bow_direction_gain = 1.0;
+            self.bow_direction_shift = 0.0;
+        }
+        else {
+            self.bow_direction_gain = 1.0-UP_BOW_LEVEL_REDUCTION*director.bow_alternation;
+            self.bow_direction_shift = UP_BOW_POSITION_SHIFT*director.bow_alternation;
+        }
         let current_freq = self.frequency[0];
+        for i in 0..self.attack_scale.len() {
+            self.attack_scale[i] = 1.0 + director.attack_jitter*(self.random.get_uniform()-0.5);
+        }
         for i in 0..self.envelope.len() {
-            let freq = 440.0 * f32::powf(2.0, (note_index-69) as f32/12.0);
+            let freq = director.note_frequency(note_index);
             self.frequency[i] = freq;
             self.noise_filter[i] = ResonantFilter::new(2.0*freq, freq);
+            self.string_index[i] = find_string(director.instrument_type.open_strings(), freq*director.bend, usize::MAX);
         }
-        let legato = self.current_note != -1 && !director.polyphonic;
+        let legato = self.current_note != -1 && !director.polyphonic && !director.divisi;
         let mut slide = false;
         if legato {
             if let Articulation::Glissando {} = &director.articulation {
@@ -490,8 +2159,18 @@ impl Division {
             }
         }
         self.update_frequency(director);
+
+        // The sizes for every instrument type were planned when the Director was created, so this
+        // normally finds them all cached.  It only plans new ones for notes bent outside that range.
+
+        if let Some(instrument) = self.instruments.first() {
+            instrument.prepare_note_fft_plans(&mut director.fft_planner.borrow_mut(), self.frequency[0]*director.bend);
+        }
         for instrument in &mut self.instruments {
             instrument.note_on(note_index, director.articulation);
+            if !legato {
+                instrument.start_attack_noise(velocity);
+            }
         }
         match &director.articulation {
             Articulation::Arco | Articulation::Glissando => {
@@ -505,12 +2184,14 @@ impl Division {
                     self.update_frequency(director);
                     self.add_transition(0, slide_time, director, TransitionData::FrequencyChange {start_frequency: current_freq, end_frequency: end_frequency});
                 }
-                let attack_time = 1000+(30000.0*(1.0-velocity)) as i64;
+                let attack_time = (arco_attack_time(velocity) as f32*director.attack_scale) as i64;
                 let start_envelope = 0.5*self.envelope[0];
                 self.add_envelope_transition(0, start_envelope, director);
                 self.add_transition(0, attack_time, director, TransitionData::EnvelopeChange {start_envelope: start_envelope, end_envelope: 1.0});
+                self.stagger_envelope_transitions();
                 if !legato {
                     self.add_transition(0, 10000, director, TransitionData::BowPositionChange { start_shift: -director.bow_position, end_shift: 0.0 });
+                    self.add_entry_scoop(director);
                 }
             }
             Articulation::Marcato => {
@@ -518,8 +2199,10 @@ impl Division {
                 let peak = 1.0+3.0*velocity;
                 self.add_envelope_transition(attack_time, peak, director);
                 self.add_transition(attack_time, 2*attack_time, director, TransitionData::EnvelopeChange {start_envelope: peak, end_envelope: 1.0});
+                self.stagger_envelope_transitions();
                 if !legato {
                     self.add_transition(0, 10000, director, TransitionData::BowPositionChange { start_shift: -director.bow_position, end_shift: 0.0 });
+                    self.add_entry_scoop(director);
                 }
             }
             Articulation::Spiccato => {
@@ -557,7 +2240,8 @@ impl Division {
                 self.add_envelope_transition(0, 1.0, director);
                 for i in 0..self.tremolo_start.len() {
                     self.tremolo_start[i] = director.step;
-                    self.tremolo_end[i] = director.step + director.tremolo_length + self.random.get_int() as i64%500;
+                    let extension = (self.random.get_int()%500) as f32;
+                    self.tremolo_end[i] = director.step + (director.tremolo_scale()*(director.tremolo_length as f32 + extension)) as i64;
                     self.tremolo_volume[i] = 1.0 + 0.5*self.random.get_uniform();
                     self.tremolo_down_bow[i] = true;
                 }
@@ -565,28 +2249,76 @@ impl Division {
         }
         self.current_note = note_index;
         self.current_note_articulation = director.articulation;
+        self.update_loudness_gain(director);
         self.update_transitions(director);
         Ok(())
     }
 
-    /// End the current note.
-    fn note_off(&mut self, note_index: i32, director: &Director) {
+    /// End the current note.  Release velocities above 0.5 make the release faster.  Lower values
+    /// are treated as the default, since many controllers always send 0 or 0.5.
+    fn note_off(&mut self, note_index: i32, velocity: f32, director: &Director) {
         if note_index != self.current_note {
             return;
         }
+        if self.sostenuto_held {
+            // The sostenuto pedal is holding this note.  Release it when the pedal comes up.
+
+            self.sostenuto_release = Some(velocity);
+            return;
+        }
         match &self.current_note_articulation {
             Articulation::Spiccato | Articulation::Pizzicato | Articulation::ColLegno => {}
             _ => {
-                let release_time = 1000 + (10000.0*(1.0-director.release_rate)) as i64;
-                self.add_envelope_transition(release_time, 0.0, director);
+                let mut release_time = (release_time(director.release_rate) as f32*director.release_scale) as i64;
+                if velocity > 0.5 {
+                    release_time = (release_time as f32*(1.0-1.5*(velocity.min(1.0)-0.5))) as i64;
+                }
+                if director.release_tail > 0 {
+                    // Release to a low level, then let it fade the rest of the way to silence
+                    // over the tail.  This keeps feeding the reverb a little longer.
+
+                    self.add_envelope_transition(release_time, RELEASE_TAIL_LEVEL, director);
+                    self.add_transition(release_time, director.release_tail, director, TransitionData::EnvelopeChange {start_envelope: RELEASE_TAIL_LEVEL, end_envelope: 0.0});
+                }
+                else {
+                    self.add_envelope_transition(release_time, 0.0, director);
+                }
             }
         }
         self.current_note = -1;
    }
 
+    /// Start a bowed note below its pitch and slide up to it, the way players sometimes scoop
+    /// into an entry.  This only affects notes that are not played legato.
+    fn add_entry_scoop(&mut self, director: &Director) {
+        if director.entry_scoop_cents == 0.0 || director.entry_scoop_time == 0 {
+            return;
+        }
+        let end_frequency = self.frequency[0];
+        let start_frequency = end_frequency*f32::powf(2.0, -director.entry_scoop_cents/1200.0);
+        for i in 0..self.frequency.len() {
+            self.frequency[i] = start_frequency;
+        }
+        self.update_frequency(director);
+        self.add_transition(0, director.entry_scoop_time, director, TransitionData::FrequencyChange {start_frequency, end_frequency});
+    }
+
+    /// Update the frequency after the tuning of a note has been changed, if it is the note being
+    /// played.  The frequency glides smoothly to the new tuning, replacing any other frequency
+    /// change in progress.
+    fn retune_note(&mut self, note_index: i32, director: &Director) {
+        if self.current_note != note_index {
+            return;
+        }
+        let start_frequency = self.frequency[0];
+        let end_frequency = director.note_frequency(note_index);
+        self.transitions.retain(|t| !matches!(t.data, TransitionData::FrequencyChange {..}));
+        self.add_transition(0, NOTE_RETUNE_TIME, director, TransitionData::FrequencyChange {start_frequency, end_frequency});
+    }
+
     /// Add a Transition to the queue.
     fn add_transition(&mut self, delay: i64, duration: i64, director: &Director, data: TransitionData) {
-        let transition = Transition { start: director.step+delay, end: director.step+delay+duration, data: data };
+        let transition = Transition { start: director.step+delay, end: director.step+delay+duration, origin: director.step, staggered: false, superseded: i64::MAX, instrument_start: vec![], data: data };
         match &transition.data {
             TransitionData::EnvelopeChange {start_envelope: _, end_envelope} => {
                 self.envelope_after_transitions = *end_envelope;
@@ -601,10 +2333,33 @@ impl Division {
     }
 
     fn add_envelope_transition(&mut self, time: i64, end_envelope: f32, director: &Director) {
-        // Remove all current envelope transitions.
+        // Replace all current envelope transitions.  Each instrument starts from the envelope it
+        // has reached when it gets to the new transition.
 
-        self.transitions.retain(|t| if let TransitionData::EnvelopeChange {..} = t.data {false} else {true});
+        self.supersede_envelope_transitions(director);
         self.add_transition(0, time, director, TransitionData::EnvelopeChange {start_envelope: self.envelope[0], end_envelope: end_envelope});
+        if let Some(transition) = self.transitions.last_mut() {
+            transition.instrument_start = vec![None; self.instruments.len()];
+        }
+    }
+
+    /// Mark all envelope transitions in the queue as superseded at the current step.
+    fn supersede_envelope_transitions(&mut self, director: &Director) {
+        for transition in &mut self.transitions {
+            if let TransitionData::EnvelopeChange {..} = transition.data {
+                transition.superseded = transition.superseded.min(director.step);
+            }
+        }
+    }
+
+    /// Make all envelope transitions in the queue staggered, so each instrument follows them at
+    /// its own speed as specified by attack_scale.
+    fn stagger_envelope_transitions(&mut self) {
+        for transition in &mut self.transitions {
+            if let TransitionData::EnvelopeChange {..} = transition.data {
+                transition.staggered = true;
+            }
+        }
     }
 
     /// This is called repeated to generate audio data.  Each generates the two channels
@@ -614,12 +2369,16 @@ impl Division {
 
         if director.step%100 == 0 {
             self.update_transitions(director);
+            self.update_noise_gate(director);
         }
 
         // If nothing has been played for a while, we can return without doing anything.
 
-        if self.instruments[0].get_volume() > 0.0 {
-            self.steps_until_off = 10000;
+        if director.separate_outputs {
+            self.outputs.fill(0.0);
+        }
+        if self.instruments.iter().any(|instrument| instrument.get_volume() > 0.0) {
+            self.steps_until_off = director.idle_samples;
         }
         if self.steps_until_off == 0 {
             return (0.0, 0.0);
@@ -630,46 +2389,199 @@ impl Division {
 
         let mut left = 0.0;
         let mut right = 0.0;
+        let bow_noise_scale = director.type_switch_blend(director.old_bow_noise_scale, director.bow_noise_scale);
         let noise_scale = match &director.articulation {
-            Articulation::ColLegno => {director.bow_noise_scale}
-            Articulation::Tremolo => {1.5*director.bow_noise_scale*director.bow_noise}
-            _ => {director.bow_noise_scale*director.bow_noise}
+            Articulation::ColLegno => {bow_noise_scale}
+            Articulation::Tremolo => {1.5*bow_noise_scale*director.bow_noise}
+            _ => {bow_noise_scale*director.bow_noise}
         };
         for i in 0..self.instruments.len() {
-            let mut noise = noise_scale*self.instruments[i].get_volume()*director.noise_buffer[self.noise_position[i]];
+            let mut noise = noise_scale*self.noise_gate_gain[i]*self.instruments[i].get_volume()*director.noise_buffer[self.noise_position[i]];
             noise += 5e-5*self.frequency[i]*self.noise_filter[i].process(noise);
-            let signal = self.instruments[i].generate(&mut director.fft_planner.borrow_mut()) + noise;
+            let (signal, side) = self.instruments[i].generate_stereo(&mut director.fft_planner.borrow_mut());
+            let signal = signal + noise;
+            if director.separate_outputs {
+                self.outputs[i] = signal;
+            }
             self.noise_position[i] = (self.noise_position[i]+1)%director.noise_buffer.len();
-            left += self.instrument_pan[i].cos()*signal;
-            right += self.instrument_pan[i].sin()*signal;
+            if self.pan_gain[i] != self.pan_gain_target[i] {
+                self.pan_gain[i] = smooth_pan_gain(self.pan_gain[i], self.pan_gain_target[i]);
+            }
+            left += self.pan_gain[i].0*(signal-side);
+            right += self.pan_gain[i].1*(signal+side);
+        }
+        if self.retire_steps > 0 {
+            let fade = self.retire_steps as f32/RETIRE_FADE_STEPS as f32;
+            for (instrument, gain) in self.retiring.iter_mut().zip(&self.retiring_pan_gain) {
+                let (signal, side) = instrument.generate_stereo(&mut director.fft_planner.borrow_mut());
+                left += fade*gain.0*(signal-side);
+                right += fade*gain.1*(signal+side);
+            }
+            self.retire_steps -= 1;
+            if self.retire_steps == 0 {
+                self.retiring.clear();
+                self.retiring_pan_gain.clear();
+            }
         }
-        if self.steps_until_off < 100 && (left.abs() > 0.001 || right.abs() > 0.001) {
+        if self.steps_until_off < 100 && (left.abs() > director.idle_level || right.abs() > director.idle_level) {
             self.steps_until_off = 100;
         }
+        if self.steps_until_off == 0 {
+            self.clear_output();
+        }
         (left, right)
     }
 
+    /// Update the gain applied to the bow noise of each Instrument.  With the noise gate enabled, the
+    /// noise follows how quickly the volume is changing, so it is strongest during attacks and
+    /// crescendos and fades during a steady sustain.  Col legno is not gated, since its noise is the
+    /// sound of the wood striking the string.
+    fn update_noise_gate(&mut self, director: &Director) {
+        let gated = director.noise_gate > 0.0 && director.articulation != Articulation::ColLegno;
+        for i in 0..self.instruments.len() {
+            let level = self.instruments[i].get_volume();
+            let motion = (level-self.noise_gate_level[i]).abs();
+            self.noise_gate_level[i] = level;
+            let target = if gated {1.0-director.noise_gate*(1.0-(motion/NOISE_GATE_MOTION).min(1.0))} else {1.0};
+            if target > self.noise_gate_gain[i] {
+                self.noise_gate_gain[i] = target;
+            }
+            else {
+                self.noise_gate_gain[i] += NOISE_GATE_RELEASE*(target-self.noise_gate_gain[i]);
+            }
+        }
+    }
+
+    /// This is called when the Division becomes idle.  It discards anything the Instruments are still
+    /// producing, so the next note starts from silence.
+    fn clear_output(&mut self) {
+        for instrument in self.instruments.iter_mut() {
+            instrument.clear_output();
+        }
+        for filter in self.noise_filter.iter_mut() {
+            filter.reset();
+        }
+        self.retiring.clear();
+        self.retiring_pan_gain.clear();
+        self.retire_steps = 0;
+    }
+
+    /// Generate a block of audio and add it to the left and right channels.  This is called by
+    /// Director::generate_block() for blocks that contain no periodic updates.
+    pub fn generate_block(&mut self, director: &Director, left: &mut [f32], right: &mut [f32]) {
+        let block_size = left.len();
+        let active = self.instruments.iter().any(|instrument| instrument.get_volume() > 0.0);
+        if active {
+            self.steps_until_off = director.idle_samples;
+        }
+
+        // If the Division might go idle partway through the block, generate it one sample at a time.
+
+        if (self.steps_until_off as usize) < block_size {
+            for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+                let (div_left, div_right) = self.generate(director);
+                *l += div_left;
+                *r += div_right;
+            }
+            return;
+        }
+
+        // Generate each Instrument's output for the whole block, then add noise and pan it.
+
+        for buffer in [&mut self.block_signal, &mut self.block_side, &mut self.block_left, &mut self.block_right] {
+            buffer.clear();
+            buffer.resize(block_size, 0.0);
+        }
+        let bow_noise_scale = director.type_switch_blend(director.old_bow_noise_scale, director.bow_noise_scale);
+        let noise_scale = match &director.articulation {
+            Articulation::ColLegno => {bow_noise_scale}
+            Articulation::Tremolo => {1.5*bow_noise_scale*director.bow_noise}
+            _ => {bow_noise_scale*director.bow_noise}
+        };
+        let mut fft_planner = director.fft_planner.borrow_mut();
+        for i in 0..self.instruments.len() {
+            let noise_volume = self.noise_gate_gain[i]*self.instruments[i].get_volume();
+            self.instruments[i].generate_block(&mut fft_planner, &mut self.block_signal, &mut self.block_side);
+            for j in 0..block_size {
+                let mut noise = noise_scale*noise_volume*director.noise_buffer[self.noise_position[i]];
+                noise += 5e-5*self.frequency[i]*self.noise_filter[i].process(noise);
+                let signal = self.block_signal[j] + noise;
+                let side = self.block_side[j];
+                self.noise_position[i] = (self.noise_position[i]+1)%director.noise_buffer.len();
+                if self.pan_gain[i] != self.pan_gain_target[i] {
+                    self.pan_gain[i] = smooth_pan_gain(self.pan_gain[i], self.pan_gain_target[i]);
+                }
+                self.block_left[j] += self.pan_gain[i].0*(signal-side);
+                self.block_right[j] += self.pan_gain[i].1*(signal+side);
+            }
+        }
+        if self.retire_steps > 0 {
+            let steps = block_size.min(self.retire_steps as usize);
+            for (instrument, gain) in self.retiring.iter_mut().zip(&self.retiring_pan_gain) {
+                instrument.generate_block(&mut fft_planner, &mut self.block_signal[..steps], &mut self.block_side[..steps]);
+                for j in 0..steps {
+                    let fade = (self.retire_steps-j as i32) as f32/RETIRE_FADE_STEPS as f32;
+                    let (signal, side) = (self.block_signal[j], self.block_side[j]);
+                    self.block_left[j] += fade*gain.0*(signal-side);
+                    self.block_right[j] += fade*gain.1*(signal+side);
+                }
+            }
+            self.retire_steps -= steps as i32;
+            if self.retire_steps == 0 {
+                self.retiring.clear();
+                self.retiring_pan_gain.clear();
+            }
+        }
+        for j in 0..block_size {
+            if active {
+                self.steps_until_off = director.idle_samples;
+            }
+            self.steps_until_off -= 1;
+            if self.steps_until_off < 100 && (self.block_left[j].abs() > director.idle_level || self.block_right[j].abs() > director.idle_level) {
+                self.steps_until_off = 100;
+            }
+            left[j] += self.block_left[j];
+            right[j] += self.block_right[j];
+        }
+        if self.steps_until_off == 0 {
+            self.clear_output();
+        }
+    }
+
     /// This is called occasionally by generate().  It processes any Transitions in the queue,
     /// updating the instruments as appropriate.
     fn update_transitions(&mut self, director: &Director) {
         let mut volume_changed = false;
         let mut frequency_changed = false;
         let mut bow_position_changed = false;
-        for transition in &self.transitions {
+        self.slew_instrument_delays(director);
+        for transition in &mut self.transitions {
             for i in 0..self.instruments.len() {
                 let j = director.step-self.instrument_delays[i];
-                if j >= transition.start {
-                    let fraction = (j-transition.start) as f32 / (transition.end-transition.start) as f32;
-                    let weight2 = if j < transition.end {0.5-0.5*(fraction*std::f32::consts::PI).cos()} else {1.0};
+                let (start, end) = transition.get_interval(self.attack_scale[i]);
+                if j >= start && j < transition.superseded {
+                    let fraction = (j-start) as f32 / (end-start) as f32;
+                    let weight2 = if j < end {0.5-0.5*(fraction*std::f32::consts::PI).cos()} else {1.0};
                     let weight1 = 1.0-weight2;
+                    if i < transition.instrument_start.len() && transition.instrument_start[i].is_none() {
+                        transition.instrument_start[i] = Some(self.envelope[i]);
+                    }
                     match &transition.data {
                         TransitionData::EnvelopeChange {start_envelope, end_envelope} => {
+                            let start_envelope = match transition.instrument_start.get(i) {
+                                Some(Some(envelope)) => *envelope,
+                                _ => *start_envelope
+                            };
                             self.envelope[i] = weight1*start_envelope + weight2*end_envelope;
                             volume_changed = true;
                         }
                         TransitionData::FrequencyChange {start_frequency, end_frequency} => {
                             self.frequency[i] = weight1*start_frequency + weight2*end_frequency;
                             frequency_changed = true;
+
+                            // A glissando stays on one string, so it should not cause a string crossing.
+
+                            self.string_index[i] = find_string(director.instrument_type.open_strings(), self.frequency[i]*director.bend, self.string_index[i]);
                         }
                         TransitionData::BowPositionChange {start_shift, end_shift} => {
                             self.bow_position_shift[i] = weight1*start_shift + weight2*end_shift;
@@ -684,6 +2596,14 @@ impl Division {
             frequency_changed = true;
             bow_position_changed = true;
         }
+        if self.old_loudness_gain != self.loudness_gain {
+            // The loudness gain is ramping after a switch of instrument type.
+
+            if director.reverb_crossfade == 0.0 {
+                self.old_loudness_gain = self.loudness_gain;
+            }
+            volume_changed = true;
+        }
         if volume_changed {
             self.update_volume(director);
             self.update_vibrato(director);
@@ -694,28 +2614,33 @@ impl Division {
         if bow_position_changed {
             self.update_bow_position(director);
         }
-        self.transitions.retain(|t| director.step < t.end+director.max_instrument_delay);
+        let max_scale = self.attack_scale.iter().fold(1.0, |a: f32, &b| a.max(b));
+        let max_delay = self.instrument_delays.iter().fold(director.max_instrument_delay, |a, &b| a.max(b));
+        self.transitions.retain(|t| director.step < t.get_interval(max_scale).1.min(t.superseded)+max_delay);
     }
 
     /// Update the volumes of all Instruments.  This is called whenever the Director's volume or
     /// envelope is changed.
     fn update_volume(&mut self, director: &Director) {
         let actual_volume = 0.05+0.95*director.volume;
+        let loudness_gain = director.type_switch_blend(self.old_loudness_gain, self.loudness_gain);
         for i in 0..self.instruments.len() {
-            let mut vol = actual_volume*self.envelope[i];
+            let mut vol = actual_volume*loudness_gain*self.bow_direction_gain*self.envelope[i];
             if let Articulation::Tremolo {} = &director.articulation {
                 // When playing tremolo, the volume needs to change continuously.
 
                 if director.step > self.tremolo_end[i] {
                     vol = 0.0;
                     self.tremolo_volume[i] = 0.5*(self.tremolo_volume[i] + 1.0 + 0.5*self.random.get_uniform());
+                    let scale = director.tremolo_scale();
                     if !self.tremolo_down_bow[i] {
-                        self.tremolo_start[i] = director.step+(0.8*director.tremolo_space as f32) as i64;
+                        self.tremolo_start[i] = director.step+(0.8*scale*director.tremolo_space as f32) as i64;
                     }
                     else {
-                        self.tremolo_start[i] = director.step+director.tremolo_space;
+                        self.tremolo_start[i] = director.step+(scale*director.tremolo_space as f32) as i64;
                     }
-                    self.tremolo_end[i] = self.tremolo_start[i] + director.tremolo_length + (800.0*director.volume) as i64 + self.random.get_int() as i64 % 500;
+                    let extension = 800.0*director.volume + (self.random.get_int()%500) as f32;
+                    self.tremolo_end[i] = self.tremolo_start[i] + (scale*(director.tremolo_length as f32 + extension)) as i64;
                     self.tremolo_down_bow[i] = !self.tremolo_down_bow[i];
                 }
                 else if director.step < self.tremolo_start[i] {
@@ -730,18 +2655,41 @@ impl Division {
         }
     }
 
+    /// Update the gain that compensates for variations in loudness across the instrument's range,
+    /// and between articulations.  After a note ends, its release keeps the gain it was played with.
+    fn update_loudness_gain(&mut self, director: &Director) {
+        let articulation_gain = self.current_note_articulation.loudness_compensation();
+        if !director.loudness_compensation {
+            self.loudness_gain = articulation_gain;
+        }
+        else if self.current_note != -1 {
+            self.loudness_gain = articulation_gain*director.instrument_type.loudness_compensation(self.current_note);
+        }
+    }
+
     /// Update the frequencies of all Instruments.  This is called whenever the Director's frequency or
     /// pitch bend is changed.
     fn update_frequency(&mut self, director: &Director) {
         for i in 0..self.instruments.len() {
             let mut freq = self.frequency[i]*director.bend;
+
+            // Keep track of which string each instrument is playing on.  When a bend moves it to
+            // a different string, the bow crossing to it creates a burst of noise.
+
+            let string = find_string(director.instrument_type.open_strings(), freq, self.string_index[i]);
+            if string != self.string_index[i] {
+                self.string_index[i] = string;
+                if director.string_crossing && self.current_note != -1 && self.instruments[i].get_volume() > 0.0 {
+                    self.instruments[i].start_attack_noise(STRING_CROSSING_LEVEL);
+                }
+            }
             if let Articulation::Tremolo {} = &director.articulation {
                 // When playing tremolo, the frequency needs to change continuously.
 
                 let freq_delta = 0.014*freq*director.volume;
                 let low_freq = freq-0.5*freq_delta;
                 if director.step < self.tremolo_start[i] {
-                    let x = (self.tremolo_start[i]-director.step) as f32 / director.tremolo_space as f32;
+                    let x = (self.tremolo_start[i]-director.step) as f32 / (director.tremolo_scale()*director.tremolo_space as f32);
                     freq = low_freq+x*freq_delta;
                 }
                 else {
@@ -756,7 +2704,7 @@ impl Division {
     /// Update the bow position of all Instruments.  This is called whenever the Director's bow position is changed.
     fn update_bow_position(&mut self, director: &Director) {
         for i in 0..self.instruments.len() {
-            let mut pos = f32::max(0.0, director.bow_position+self.bow_position_shift[i]);
+            let mut pos = f32::max(0.0, director.bow_position+self.bow_position_shift[i]+self.bow_direction_shift);
             if let Articulation::Tremolo {} = &director.articulation {
                 // When playing tremolo, the bow position needs to change continuously.
 
@@ -769,9 +2717,16 @@ impl Division {
         }
     }
     /// Update the vibrato of all Instruments.  This is called whenever the Director's vibrato is changed.
+    /// With a nonzero gradient, instruments later in the section use less vibrato, down to half as much
+    /// for the last one when the gradient is 1.
     fn update_vibrato(&mut self, director: &Director) {
-        for i in 0..self.instruments.len() {
-            self.instruments[i].set_vibrato_amplitude(0.01*director.vibrato*self.envelope[i]);
+        let instrument_count = self.instruments.len();
+        for i in 0..instrument_count {
+            let position = if instrument_count > 1 {i as f32/(instrument_count-1) as f32} else {0.0};
+            let scale = 1.0-0.5*director.vibrato_gradient*position;
+            self.instruments[i].set_vibrato_amplitude(0.01*director.vibrato*scale*self.envelope[i]);
+            self.instruments[i].set_vibrato_shape(director.vibrato_shape);
+            self.instruments[i].set_vibrato_phase_reset(director.vibrato_phase_reset);
         }
     }
 
@@ -782,43 +2737,288 @@ impl Division {
         }
     }
 
-    /// Update the position each instrument is panned to.
+    /// Update the phase decorrelation of all Instruments.
+    fn update_phase_decorrelation(&mut self, director: &Director) {
+        for instrument in &mut self.instruments.iter_mut() {
+            instrument.set_phase_decorrelation(director.phase_decorrelation);
+        }
+    }
+
+    /// Update the excitation coherence of all Instruments.
+    fn update_excitation_coherence(&mut self, director: &Director) {
+        for instrument in &mut self.instruments.iter_mut() {
+            instrument.set_excitation_coherence(director.excitation_coherence);
+        }
+    }
+
+    /// Update the excitation variation of all Instruments.
+    fn update_excitation_variation(&mut self, director: &Director) {
+        for instrument in &mut self.instruments.iter_mut() {
+            instrument.set_excitation_variation(director.excitation_variation);
+        }
+    }
+
+    /// Update the sustain damping of all Instruments.
+    fn update_sustain_damping(&mut self, director: &Director) {
+        for instrument in &mut self.instruments.iter_mut() {
+            instrument.set_sustain_damping(director.sustain_damping);
+        }
+    }
+
+    /// Update which stage of the synthesis model all Instruments output.
+    fn update_debug_tap(&mut self, director: &Director) {
+        for instrument in &mut self.instruments.iter_mut() {
+            instrument.set_debug_tap(director.debug_tap);
+        }
+    }
+
+    /// Update the amount of noise at the start of bowed notes for all Instruments.
+    fn update_attack_noise(&mut self, director: &Director) {
+        for instrument in &mut self.instruments.iter_mut() {
+            instrument.set_attack_noise(director.attack_noise);
+        }
+    }
+
+    /// Update the amount of pitch drift for all Instruments.
+    fn update_pitch_drift(&mut self, director: &Director) {
+        for instrument in &mut self.instruments.iter_mut() {
+            instrument.set_pitch_drift(director.pitch_drift);
+        }
+    }
+
+    /// Update the number of unison voices for all Instruments.
+    fn update_unison_voices(&mut self, director: &Director) {
+        for instrument in &mut self.instruments.iter_mut() {
+            instrument.set_unison_voices(director.unison_voices, director.unison_detune);
+        }
+    }
+
+    /// Update the amount of inharmonicity for all Instruments.
+    fn update_inharmonicity(&mut self, director: &Director) {
+        for instrument in &mut self.instruments.iter_mut() {
+            instrument.set_inharmonicity(director.inharmonicity);
+        }
+    }
+
+    /// Update the decay rate of note tails for all Instruments.
+    fn update_tail_decay(&mut self, director: &Director) {
+        for instrument in &mut self.instruments.iter_mut() {
+            instrument.set_tail_decay(director.tail_decay);
+        }
+    }
+
+    /// Update the highest frequency excited by the bow for all Instruments.
+    fn update_max_harmonic(&mut self, director: &Director) {
+        for instrument in &mut self.instruments.iter_mut() {
+            instrument.set_max_harmonic(director.max_harmonic);
+        }
+    }
+
+    /// Update how much the tails of previous notes are spread in stereo.
+    fn update_tail_spread(&mut self, director: &Director) {
+        let spread = if director.stereo_tails {STEREO_TAIL_SPREAD} else {0.0};
+        for instrument in &mut self.instruments.iter_mut() {
+            instrument.set_tail_spread(spread);
+        }
+    }
+
+    /// Update whether all Instruments start a new note when the previous one is repeated.
+    fn update_retrigger_same_note(&mut self, director: &Director) {
+        for instrument in &mut self.instruments.iter_mut() {
+            instrument.set_retrigger_same_note(director.retrigger_same_note);
+        }
+    }
+
+    /// Update the sustain level of all Instruments.
+    fn update_sustain_level(&mut self, director: &Director) {
+        for instrument in &mut self.instruments.iter_mut() {
+            instrument.set_sustain_level(director.sustain_level);
+        }
+    }
+
+    /// Update the position each instrument is panned to.  While the Division is producing sound,
+    /// the pan gains move smoothly from their current values to the new ones.
     fn update_pan_positions(&mut self, director: &Director) {
         let instrument_count = self.instruments.len();
         if instrument_count == 1 {
+            // A single instrument is processed in mono.  The Director pans the final output.
+
             self.instrument_pan[0] = 0.25*PI;
         }
         else {
+            // Positions are measured from 0 (hard left) to 1 (hard right).  The center of the ensemble
+            // is clamped so that the instruments at both edges stay within that range.
+
+            let half_width = 0.5*director.stereo_width.min(1.0);
+            let center = (0.5+0.5*director.pan_center).clamp(half_width, 1.0-half_width);
             for i in 0..instrument_count {
-                self.instrument_pan[i] = 0.5*PI*(0.5 + director.stereo_width*(i as f32 / (instrument_count-1) as f32 - 0.5));
+                self.instrument_pan[i] = 0.5*PI*(center + director.stereo_width*(i as f32 / (instrument_count-1) as f32 - 0.5));
             }
         }
+
+        // We use a constant power pan law.  In mono compatible mode, the positions are moved toward
+        // the center so that no instrument loses much level when the channels are summed to mono.
+        // The user may also specify a relative gain for each instrument.  Instruments not included
+        // in the list have a gain of 1.  In divisi mode, instruments that are not playing this
+        // division's note are silenced.
+
+        let width = if director.mono_compatible {MONO_COMPATIBLE_WIDTH} else {1.0};
+        for i in 0..instrument_count {
+            let pan = 0.25*PI + width*(self.instrument_pan[i]-0.25*PI);
+            let left_gain = pan.cos();
+            let right_gain = pan.sin();
+            let gain = *director.instrument_gains.get(i).unwrap_or(&1.0)*self.divisi_gain[i];
+            self.pan_gain_target[i] = (gain*left_gain, gain*right_gain);
+        }
+        if self.steps_until_off == 0 {
+            self.pan_gain.copy_from_slice(&self.pan_gain_target);
+        }
     }
 
-    /// Update the delay for each instrument.
+    /// Update the delay for each instrument.  If no Transitions are in progress the new delays
+    /// take effect immediately.  Otherwise slew_instrument_delays() moves toward them gradually.
     fn update_instrument_delays(&mut self, director: &Director) {
         let instrument_count = self.instruments.len();
         if instrument_count == 1 {
-            self.instrument_delays[0] = 0;
+            self.target_instrument_delays[0] = 0;
         }
         else {
             for i in 0..instrument_count {
                 let index = ((i+(instrument_count/2)) % instrument_count) as i64;
-                self.instrument_delays[i] = director.max_instrument_delay*index/(instrument_count-1) as i64;
+                self.target_instrument_delays[i] = director.max_instrument_delay*index/(instrument_count-1) as i64;
             }
         }
+        if self.transitions.is_empty() {
+            self.instrument_delays.copy_from_slice(&self.target_instrument_delays);
+        }
+    }
+
+    /// Move the delay for each instrument toward its target, limited by the number of steps since
+    /// this was last called.
+    fn slew_instrument_delays(&mut self, director: &Director) {
+        let max_change = (director.step-self.last_delay_step)/DELAY_SLEW_STEPS;
+        self.last_delay_step = director.step;
+        for (delay, target) in self.instrument_delays.iter_mut().zip(&self.target_instrument_delays) {
+            *delay += (target-*delay).clamp(-max_change, max_change);
+        }
+    }
+}
+
+/// Find which string a frequency is played on, given the open strings of the instrument and the
+/// string it was previously on.  It is the highest string whose open pitch is at or below the
+/// frequency, except that the frequency must pass STRING_CROSSING_HYSTERESIS semitones beyond an
+/// open string before it moves away from the previous string.
+fn find_string(open_strings: &[i32], frequency: f32, previous: usize) -> usize {
+    let pitch = 69.0 + 12.0*(frequency/440.0).log2();
+    let mut string = previous.min(open_strings.len()-1);
+    while string+1 < open_strings.len() && pitch >= open_strings[string+1] as f32 + STRING_CROSSING_HYSTERESIS {
+        string += 1;
+    }
+    while string > 0 && pitch < open_strings[string] as f32 - STRING_CROSSING_HYSTERESIS {
+        string -= 1;
+    }
+    string
+}
+
+/// Get the length of the attack (in steps) for a note played arco with a given velocity.  It
+/// follows a raised cosine curve.
+pub fn arco_attack_time(velocity: f32) -> i64 {
+    1000+(30000.0*(1.0-velocity)) as i64
+}
+
+/// Move a pair of pan gains one step toward their targets.  Once they get close enough, they
+/// are set exactly to the targets.
+fn smooth_pan_gain(current: (f32, f32), target: (f32, f32)) -> (f32, f32) {
+    if (current.0-target.0).abs() < 1e-5 && (current.1-target.1).abs() < 1e-5 {
+        return target;
+    }
+    (current.0 + PAN_SMOOTHING*(target.0-current.0), current.1 + PAN_SMOOTHING*(target.1-current.1))
+}
+
+/// Get the length of the release (in steps) for a given release rate.  It follows a raised
+/// cosine curve.
+pub fn release_time(release_rate: f32) -> i64 {
+    1000 + (10000.0*(1.0-release_rate)) as i64
+}
+
+/// Load one of the bundled impulse responses for an instrument body, normalized to unit energy.
+pub fn load_body_ir(which: BundledIR) -> Vec<f32> {
+    match which {
+        BundledIR::Violin => load_ir(include_bytes!("data/violin.flac")),
+        BundledIR::Viola => load_ir(include_bytes!("data/viola.flac")),
+        BundledIR::Cello => load_ir(include_bytes!("data/cello.flac")),
+        BundledIR::Bass => load_ir(include_bytes!("data/bass.flac"))
+    }
+}
+
+/// Load an impulse response from a FLAC file containing 16 bit mono audio at 48 kHz, normalized
+/// to unit energy.  If it cannot be decoded, a synthetic impulse response is used instead so the
+/// synthesizer still produces sound, and asset_decode_failed() will return true.
+pub fn load_ir(file: &[u8]) -> Vec<f32> {
+    let mut ir = parse_flac(file).unwrap_or_else(|_| {
+        ASSET_DECODE_FAILED.store(true, Ordering::Relaxed);
+        fallback_ir()
+    });
+    normalize_energy(&mut ir);
+    ir
+}
+
+/// Load a recording of bow noise from a FLAC file containing 16 bit mono audio at 48 kHz.  If it
+/// cannot be decoded, white noise is used instead, and asset_decode_failed() will return true.
+pub fn load_bow_noise(file: &[u8]) -> Vec<f32> {
+    parse_flac(file).unwrap_or_else(|_| {
+        ASSET_DECODE_FAILED.store(true, Ordering::Relaxed);
+        fallback_noise()
+    })
+}
+
+/// Get whether any audio data has failed to decode, so a substitute was used in its place.  Assets
+/// may be loaded on the audio thread, so instead of printing anything when that happens, a flag is
+/// set that the host can check and report from another thread.
+pub fn asset_decode_failed() -> bool {
+    ASSET_DECODE_FAILED.load(Ordering::Relaxed)
+}
+
+static ASSET_DECODE_FAILED: AtomicBool = AtomicBool::new(false);
+
+/// Scale an impulse response so the sum of the squares of its samples is 1.
+fn normalize_energy(ir: &mut [f32]) {
+    let energy: f32 = ir.iter().map(|x| x*x).sum();
+    if energy > 0.0 {
+        let scale = 1.0/energy.sqrt();
+        ir.iter_mut().for_each(|x| *x *= scale);
     }
 }
 
-/// Convert a FLAC encoded sample to raw audio data.
-fn parse_flac(file: &[u8]) -> Vec<f32> {
-    let mut reader = claxon::FlacReader::new(file).unwrap();
-    assert_eq!(48000, reader.streaminfo().sample_rate);
-    assert_eq!(1, reader.streaminfo().channels);
-    assert_eq!(16, reader.streaminfo().bits_per_sample);
+/// Decode a FLAC file containing 16 bit mono audio at 48 kHz.
+fn parse_flac(file: &[u8]) -> Result<Vec<f32>, claxon::Error> {
+    let mut reader = claxon::FlacReader::new(file)?;
+    let info = reader.streaminfo();
+    if info.sample_rate != SAMPLE_RATE as u32 {
+        return Err(claxon::Error::Unsupported("sample rate must be 48 kHz"));
+    }
+    if info.channels != 1 {
+        return Err(claxon::Error::Unsupported("audio must be mono"));
+    }
+    if info.bits_per_sample != 16 {
+        return Err(claxon::Error::Unsupported("audio must be 16 bit"));
+    }
     let mut samples = Vec::new();
     for sample in reader.samples() {
-        samples.push((sample.unwrap() as f32)/32768.0);
+        samples.push((sample? as f32)/32768.0);
     }
-    samples
+    Ok(samples)
+}
+
+/// Create a synthetic impulse response to use when a bundled one cannot be decoded.  It is a
+/// short burst of noise that decays over about 20 ms.
+fn fallback_ir() -> Vec<f32> {
+    let mut random = Random::with_seed(0);
+    (0..4800).map(|i| 0.3*(2.0*random.get_uniform()-1.0)*(-(i as f32)/960.0).exp()).collect()
+}
+
+/// Create white noise to use for the bow noise when the bundled recording cannot be decoded.
+fn fallback_noise() -> Vec<f32> {
+    let mut random = Random::with_seed(0);
+    (0..SAMPLE_RATE).map(|_| 0.55*(2.0*random.get_uniform()-1.0)).collect()
 }
@@ -0,0 +1,107 @@
+// Copyright 2025 by Peter Eastman
+//
+// This file is part of Viola Ex Machina.
+//
+// Viola Ex Machina is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Lesser General Public License as published by the Free Software Foundation, either
+// version 2.1 of the License, or (at your option) any later version.
+//
+// Viola Ex Machina is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License along with Viola Ex Machina.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use crate::random::Random;
+
+/// Dither is only added to signals quieter than this (about -60 dB).
+const THRESHOLD: f32 = 1e-3;
+
+/// Once dither is on, the level must rise this far above the threshold to turn it off again.
+/// Without the gap, a signal whose level hovers near the threshold would switch back and forth.
+const HYSTERESIS: f32 = 2.0;
+
+/// The time for the level to fall after the signal becomes quieter.
+const RELEASE_SECONDS: f32 = 0.05;
+
+/// The time over which the dither fades in or out when it is switched.
+const RAMP_SECONDS: f32 = 0.01;
+
+/// Adds triangular PDF dither to one channel of audio.  It is only applied to quiet signals,
+/// where quantization distortion would be most noticeable.  Whether to dither is decided from a
+/// peak envelope of the signal rather than from individual samples, so the noise does not follow
+/// the waveform or switch off at zero crossings.  It fades in and out when it is switched, and
+/// once the envelope falls below one least significant bit it stops completely, so silence is
+/// left as exact zeros.
+pub struct Ditherer {
+    random: Random,
+    amplitude: f32,
+    release: f32,
+    ramp: f32,
+    level: f32,
+    gain: f32,
+    active: bool
+}
+
+impl Ditherer {
+    /// Create a Ditherer.  The amplitude of the noise is one least significant bit of audio with
+    /// the specified bit depth.
+    pub fn new(sample_rate: f32, bits: u32) -> Self {
+        Self {
+            random: Random::new(),
+            amplitude: 2.0f32.powi(1-bits as i32),
+            release: 1.0-(-1.0/(RELEASE_SECONDS*sample_rate)).exp(),
+            ramp: 1.0/(RAMP_SECONDS*sample_rate),
+            level: 0.0,
+            gain: 0.0,
+            active: false
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.level = 0.0;
+        self.gain = 0.0;
+        self.active = false;
+    }
+
+    /// Get the current level of the signal, as tracked by the envelope follower.  This is intended
+    /// for tests and diagnostics.
+    pub fn get_level(&self) -> f32 {
+        self.level
+    }
+
+    /// Process one sample.
+    pub fn process(&mut self, x: f32) -> f32 {
+        let peak = x.abs();
+        if peak > self.level {
+            self.level = peak;
+        }
+        else {
+            self.level += self.release*(peak-self.level);
+
+            // In silence the level would decay toward zero forever and end up denormal.  Dither
+            // stops below one least significant bit, so the level can be snapped to zero there.
+
+            if self.level < self.amplitude {
+                self.level = 0.0;
+            }
+        }
+        if self.level < self.amplitude || self.level > HYSTERESIS*THRESHOLD {
+            self.active = false;
+        }
+        else if self.level < THRESHOLD {
+            self.active = true;
+        }
+        if self.active {
+            self.gain = (self.gain+self.ramp).min(1.0);
+        }
+        else {
+            self.gain = (self.gain-self.ramp).max(0.0);
+        }
+        if self.gain == 0.0 {
+            return x;
+        }
+        x + self.gain*self.amplitude*(self.random.get_uniform()-self.random.get_uniform())
+    }
+}
@@ -75,6 +75,19 @@ impl HighpassFilter {
             y: 0.0
         }
     }
+
+    pub fn reset(&mut self) {
+        self.x = 0.0;
+        self.y = 0.0;
+    }
+
+    /// Change the cutoff frequency (in Hz) without resetting the filter's state, so it can be
+    /// called while audio is playing.
+    pub fn set_cutoff(&mut self, cutoff: f32) {
+        let rc = 1.0/(2.0*PI*cutoff);
+        let dt = 1.0/SAMPLE_RATE as f32;
+        self.alpha = rc/(rc+dt);
+    }
 }
 
 impl Filter for HighpassFilter {
@@ -85,6 +98,70 @@ impl Filter for HighpassFilter {
     }
 }
 
+/// An IIR high shelf filter.  It passes low frequencies unchanged, and multiplies frequencies
+/// above the cutoff by a gain.
+#[derive(Copy, Clone)]
+pub struct HighShelfFilter {
+    highpass: HighpassFilter,
+    gain: f32
+}
+
+impl HighShelfFilter {
+    pub fn new(cutoff: f32, gain: f32) -> Self {
+        Self {
+            highpass: HighpassFilter::new(cutoff),
+            gain: gain
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.highpass.reset();
+    }
+
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+}
+
+impl Filter for HighShelfFilter {
+    fn process(&mut self, x: f32) -> f32 {
+        x + (self.gain-1.0)*self.highpass.process(x)
+    }
+}
+
+/// A first order tilt filter.  It splits the signal into low and high bands at the pivot
+/// frequency, multiplies the high band by a gain and the low band by its inverse, and sums them.
+/// A gain of 1 leaves the signal unchanged.
+#[derive(Copy, Clone)]
+pub struct TiltFilter {
+    lowpass: LowpassFilter,
+    gain: f32
+}
+
+impl TiltFilter {
+    pub fn new(pivot: f32, gain: f32) -> Self {
+        Self {
+            lowpass: LowpassFilter::new(pivot),
+            gain
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.lowpass.reset();
+    }
+
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+}
+
+impl Filter for TiltFilter {
+    fn process(&mut self, x: f32) -> f32 {
+        let low = self.lowpass.process(x);
+        low/self.gain + self.gain*(x-low)
+    }
+}
+
 /// An IIR bandpass filter.
 #[derive(Copy, Clone)]
 pub struct BandpassFilter {
@@ -107,6 +184,67 @@ impl Filter for BandpassFilter {
     }
 }
 
+/// An IIR peaking filter.  It multiplies frequencies near the center frequency by a gain, and
+/// passes others unchanged.  It is implemented as a resonant bandpass filter in parallel with the
+/// unfiltered signal, so the width of the peak is determined by the Q.
+#[derive(Copy, Clone)]
+pub struct PeakingFilter {
+    b0: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+    gain: f32
+}
+
+impl PeakingFilter {
+    pub fn new(center_frequency: f32, q: f32, gain: f32) -> Self {
+        let mut result = Self {
+            b0: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+            gain
+        };
+        result.set_parameters(center_frequency, q, gain);
+        result
+    }
+
+    pub fn reset(&mut self) {
+        self.x1 = 0.0;
+        self.x2 = 0.0;
+        self.y1 = 0.0;
+        self.y2 = 0.0;
+    }
+
+    /// Change the parameters of the filter.  Its internal state is preserved, so this can be done
+    /// while audio is passing through it.
+    pub fn set_parameters(&mut self, center_frequency: f32, q: f32, gain: f32) {
+        let w = 2.0*PI*center_frequency/SAMPLE_RATE as f32;
+        let alpha = w.sin()/(2.0*q);
+        self.b0 = alpha/(1.0+alpha);
+        self.a1 = -2.0*w.cos()/(1.0+alpha);
+        self.a2 = (1.0-alpha)/(1.0+alpha);
+        self.gain = gain;
+    }
+}
+
+impl Filter for PeakingFilter {
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0*(x-self.x2) - self.a1*self.y1 - self.a2*self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        x + (self.gain-1.0)*y
+    }
+}
+
 /// An IIR resonant filter.
 #[derive(Copy, Clone)]
 pub struct ResonantFilter {
@@ -127,6 +265,11 @@ impl ResonantFilter {
             y2: 0.0
         }
     }
+
+    pub fn reset(&mut self) {
+        self.y1 = 0.0;
+        self.y2 = 0.0;
+    }
 }
 
 impl Filter for ResonantFilter {
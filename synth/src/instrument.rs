@@ -15,13 +15,77 @@
 
 use std::f32::consts::PI;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use crate::random::Random;
 use crate::filter::{Filter, LowpassFilter};
-use crate::{InstrumentType, Articulation};
-use crate::SAMPLE_RATE;
+use crate::{InstrumentType, Articulation, VibratoShape, DebugTap};
+use crate::{MIDI_NOTE_RANGE, SAMPLE_RATE, flush_denormal};
 use realfft::{RealFftPlanner, ComplexToReal};
 use rustfft::num_complex::Complex;
 
+/// The range of frequencies (in Hz) an Instrument can play.  Extreme pitch bends can produce
+/// frequencies outside this range, so they get clamped to it.  Otherwise the period could be
+/// infinite, negative, or zero.
+const MIN_FREQUENCY: f32 = 8.0;
+const MAX_FREQUENCY: f32 = 10000.0;
+
+/// The largest spectrum size a note can reach, at the lowest frequency and the highest internal
+/// sample rate.  Buffers indexed by spectrum size are allocated to this size up front.
+const MAX_SPECTRUM_SIZE: usize = (2.0*SAMPLE_RATE as f32/MIN_FREQUENCY) as usize/2 + 2;
+
+/// The burst of noise at the start of a bowed note fades out over this time (in seconds).
+const ATTACK_NOISE_DURATION: f32 = 0.05;
+
+/// The damping applied to the tails of previous notes in each period, for a violin with the default
+/// tail decay rate.
+const TAIL_DAMPING: f32 = 0.15;
+
+/// The amplitude of the attack noise in each frequency component, relative to the bow excitation.
+const ATTACK_NOISE_LEVEL: f32 = 2.0;
+
+/// With the maximum amount of pitch drift, the tuning wanders with an RMS deviation of this many
+/// cents.  It changes with a time constant of PITCH_DRIFT_TIME seconds, and never goes farther
+/// than MAX_PITCH_DRIFT times the RMS deviation.
+const PITCH_DRIFT_CENTS: f32 = 5.0;
+const PITCH_DRIFT_TIME: f32 = 3.0;
+const MAX_PITCH_DRIFT: f32 = 3.0;
+
+/// With the maximum amount of inharmonicity, partial n is stretched sharp by 0.5*B*n^3 times the
+/// fundamental frequency, where B is this coefficient.  The stretch is produced by advancing the
+/// phase of each frequency component on every period, so it is limited to MAX_INHARMONIC_SHIFT
+/// times the fundamental to avoid artifacts at period boundaries.
+const MAX_INHARMONICITY: f32 = 1e-4;
+const MAX_INHARMONIC_SHIFT: f32 = 0.25;
+
+/// The strength of the damping apply_filter() applies on each period while a note is playing, and
+/// after it has ended.  See filter_decay().
+const FILTER_DAMPING: f32 = 0.15;
+const RELEASE_FILTER_DAMPING: f32 = 0.2;
+
+/// With the maximum amount of sustain damping, the extra fraction of each frequency component removed
+/// in each period approaches MAX_SUSTAIN_DAMPING as a note is held.  It increases with a time constant
+/// of SUSTAIN_DAMPING_TIME seconds.  Components below SUSTAIN_DAMPING_FREQUENCY (in Hz) are damped
+/// less, in proportion to their frequency.
+const MAX_SUSTAIN_DAMPING: f32 = 0.1;
+const SUSTAIN_DAMPING_TIME: f32 = 2.0;
+const SUSTAIN_DAMPING_FREQUENCY: f32 = 5000.0;
+
+/// The state of one extra copy of the string that is summed with the main one to thicken the
+/// tone.  These fields are swapped with the corresponding fields of the Instrument while the voice
+/// is being updated, so the same code generates both.
+struct UnisonVoice {
+    detune: f32,
+    new_note: bool,
+    spectrum_buffer: Vec<Complex<f32>>,
+    spectrum_temp: Vec<Complex<f32>>,
+    output_buffer: Vec<f32>,
+    spectrum_size: usize,
+    output_size: usize,
+    output_position: usize,
+    period: f32,
+    period_offset: f32
+}
+
 /// This struct combines a glottal source and two waveguides to form the complete synthesis model.
 /// In addition, consonants can be synthesized by injecting extra noise at an arbitrary point in
 /// the vocal tract.
@@ -36,13 +100,42 @@ pub struct Instrument {
     vibrato_low_frequency: f32,
     vibrato_high_frequency: f32,
     vibrato_amplitude: f32,
+    vibrato_shape: VibratoShape,
     vibrato_frequency_drift_amplitude: f32,
     vibrato_amplitude_drift_amplitude: f32,
     vibrato_phase: f32,
+    initial_vibrato_phase: f32,
+    vibrato_phase_reset: bool,
     vibrato_amplitude_drift: f32,
     frequency_drift_amplitude: f32,
     frequency_drift: f32,
+    pitch_drift_amount: f32,
+    pitch_drift: f32,
+    unison_voices: Vec<UnisonVoice>,
+    unison_detune: f32,
+    inharmonicity: f32,
+    unison_frequency: f32,
     pizzicato_exponent: i32,
+    excitation_coherence: f32,
+    excitation_variation: f32,
+    frozen_excitation: Vec<Complex<f32>>,
+    frozen_size: usize,
+    frozen_seed: u32,
+    sustain_damping: f32,
+    note_time: f32,
+    max_harmonic: f32,
+    attack_noise: f32,
+    tail_decay: f32,
+    attack_noise_level: f32,
+    attack_noise_time: f32,
+    sustain_level: f32,
+    sustain_gain: Vec<f32>,
+    phase_decorrelation: f32,
+    phase_offsets: Vec<f32>,
+    phase_seed: u32,
+    phase_rotation: Vec<Complex<f32>>,
+    debug_tap: DebugTap,
+    debug_buffer: Vec<Complex<f32>>,
     sample_rate: f32,
     spectrum_buffer: Vec<Complex<f32>>,
     spectrum_temp: Vec<Complex<f32>>,
@@ -55,74 +148,96 @@ pub struct Instrument {
     period_offset: f32,
     random: Random,
     lowpass: LowpassFilter,
+    side_lowpass: LowpassFilter,
+    tail_spread: f32,
     decaying_notes: Vec<DecayingNote>,
     start_new_note: bool,
+    retrigger_same_note: bool,
     last_note: i32,
     last_articulation: Articulation
 }
 
 impl Instrument {
     pub fn new(instrument_type: InstrumentType, index: usize) -> Self {
-        let vibrato_low_frequency;
-        let vibrato_high_frequency;
-        let spectrum_coeff;
-        let pizzicato_exponent;
-        let sample_rate;
-        let phase_shift: f32;
-        match instrument_type {
-            InstrumentType::Violin => {
-                vibrato_low_frequency = 5.15;
-                vibrato_high_frequency = 5.4;
-                spectrum_coeff = (0.18917376, 0.0050823237, 1.3494484, -2.9302601);
-                pizzicato_exponent = 20;
-                sample_rate = 2.0*SAMPLE_RATE as f32;
-                phase_shift = 0.65;
-            }
-            InstrumentType::Viola => {
-                vibrato_low_frequency = 5.15;
-                vibrato_high_frequency = 5.4;
-                spectrum_coeff = (0.59566957, -2.227358, 0.96543276, -2.170609);
-                pizzicato_exponent = 20;
-                sample_rate = SAMPLE_RATE as f32;
-                phase_shift = 0.35;
-            }
-            InstrumentType::Cello => {
-                vibrato_low_frequency = 5.0;
-                vibrato_high_frequency = 5.5;
-                spectrum_coeff = (-0.0037527911, 0.55326806, 2.1803675, -4.7307866);
-                pizzicato_exponent = 30;
-                sample_rate = SAMPLE_RATE as f32;
-                phase_shift = 0.0;
-            }
-            InstrumentType::Bass => {
-                vibrato_low_frequency = 4.9;
-                vibrato_high_frequency = 5.4;
-                spectrum_coeff = (0.34754488, -0.852053, 1.0190364, -2.5493735);
-                pizzicato_exponent = 40;
-                sample_rate = SAMPLE_RATE as f32;
-                phase_shift = 0.0;
-            }
+        Instrument::with_random(instrument_type, index, Random::new())
+    }
+
+    /// Create an Instrument whose random number generator has a specified seed.  Two
+    /// Instruments created with the same seed and given the same inputs produce identical output.
+    pub fn new_seeded(instrument_type: InstrumentType, index: usize, seed: u32) -> Self {
+        Instrument::with_random(instrument_type, index, Random::with_seed(seed))
+    }
+
+    /// Change the instrument type while a note is playing.  The parameters that depend on the type
+    /// are replaced, while the state of the note is kept.  If the internal sample rate changes, the
+    /// tails of previous notes are discarded, since they would otherwise play at the wrong pitch.
+    pub fn set_instrument_type(&mut self, instrument_type: InstrumentType) {
+        let params = TypeParameters::new(instrument_type);
+        if params.sample_rate != self.sample_rate {
+            self.decaying_notes.clear();
         }
-        let mut random = Random::new();
+        self.instrument_type = instrument_type;
+        self.spectrum_coeff = params.spectrum_coeff;
+        self.phase_shift = params.phase_shift;
+        self.vibrato_low_frequency = params.vibrato_low_frequency;
+        self.vibrato_high_frequency = params.vibrato_high_frequency;
+        self.pizzicato_exponent = params.pizzicato_exponent;
+        self.sample_rate = params.sample_rate;
+        self.sustain_gain.fill(0.0);
+        self.update_filter();
+    }
+
+    fn with_random(instrument_type: InstrumentType, index: usize, mut random: Random) -> Self {
+        let params = TypeParameters::new(instrument_type);
+        let phase_seed = random.state().i ^ 0x85ebca6b;
         Self {
             instrument_type: instrument_type,
-            spectrum_coeff: spectrum_coeff,
+            spectrum_coeff: params.spectrum_coeff,
             volume: 1.0,
             frequency: 440.0,
             bow_position: 0.5,
-            phase_shift: phase_shift,
+            phase_shift: params.phase_shift,
             harmonics: false,
-            vibrato_low_frequency: vibrato_low_frequency,
-            vibrato_high_frequency: vibrato_high_frequency,
+            vibrato_low_frequency: params.vibrato_low_frequency,
+            vibrato_high_frequency: params.vibrato_high_frequency,
             vibrato_amplitude: 0.0,
+            vibrato_shape: VibratoShape::Cubed,
             vibrato_frequency_drift_amplitude: 0.1,
             vibrato_amplitude_drift_amplitude: 0.4,
             vibrato_phase: 0.3*index as f32,
+            initial_vibrato_phase: 0.3*index as f32,
+            vibrato_phase_reset: false,
             vibrato_amplitude_drift: random.get_normal(),
             frequency_drift_amplitude: 0.002,
             frequency_drift: random.get_normal(),
-            pizzicato_exponent: pizzicato_exponent,
-            sample_rate: sample_rate,
+            pitch_drift_amount: 0.0,
+            pitch_drift: 0.0,
+            unison_voices: vec![],
+            unison_detune: 0.0,
+            inharmonicity: 0.0,
+            unison_frequency: 0.0,
+            pizzicato_exponent: params.pizzicato_exponent,
+            excitation_coherence: 0.0,
+            excitation_variation: 1.0,
+            frozen_excitation: vec![Complex::<f32>::new(0.0, 0.0); MAX_SPECTRUM_SIZE+1],
+            frozen_size: 0,
+            frozen_seed: 0,
+            sustain_damping: 0.0,
+            note_time: 0.0,
+            max_harmonic: 1.0,
+            attack_noise: 0.0,
+            tail_decay: 1.0,
+            attack_noise_level: 0.0,
+            attack_noise_time: ATTACK_NOISE_DURATION,
+            sustain_level: 0.0,
+            sustain_gain: vec![0.0; MAX_SPECTRUM_SIZE+1],
+            phase_decorrelation: 0.0,
+            phase_offsets: vec![],
+            phase_seed: phase_seed,
+            phase_rotation: vec![],
+            debug_tap: DebugTap::Output,
+            debug_buffer: vec![],
+            sample_rate: params.sample_rate,
             spectrum_buffer: vec![],
             spectrum_temp: vec![],
             scratch: vec![],
@@ -134,16 +249,22 @@ impl Instrument {
             period_offset: 0.0,
             random: random,
             lowpass: LowpassFilter::new(5000.0),
+            side_lowpass: LowpassFilter::new(5000.0),
+            tail_spread: 0.0,
             decaying_notes: vec![],
             start_new_note: false,
+            retrigger_same_note: false,
             last_note: 0,
             last_articulation: Articulation::Arco
         }
     }
 
-    /// Signal the start of a new note.
+    /// Signal the start of a new note.  Repeating the previous note normally continues it without a
+    /// new attack, unless retriggering has been enabled with set_retrigger_same_note().  The note
+    /// is clamped to the range of MIDI notes.
     pub fn note_on(&mut self, note: i32, articulation: Articulation) {
-        if note != self.last_note {
+        let note = note.clamp(*MIDI_NOTE_RANGE.start(), *MIDI_NOTE_RANGE.end());
+        if note != self.last_note || self.retrigger_same_note {
             self.start_new_note = true;
             self.last_note = note;
         }
@@ -151,11 +272,75 @@ impl Instrument {
         self.update_filter();
     }
 
+    /// Set whether repeating the previous note starts a new note.  If so, the previous one is moved
+    /// into a decaying tail just as when a different note is played.
+    pub fn set_retrigger_same_note(&mut self, enabled: bool) {
+        self.retrigger_same_note = enabled;
+    }
+
     /// Get the volume of the excitation from the bow (between 0.0 and 1.0).
     pub fn get_volume(&self) -> f32 {
         self.volume
     }
 
+    /// Get the number of tails of previous notes that are still decaying.
+    pub fn decaying_note_count(&self) -> usize {
+        self.decaying_notes.len()
+    }
+
+    /// Get the current offset of the tuning caused by pitch drift (in cents).  This is intended
+    /// for testing.
+    pub fn get_pitch_drift(&self) -> f32 {
+        self.pitch_drift_amount*PITCH_DRIFT_CENTS*self.pitch_drift
+    }
+
+    /// Get the length of the current period (in samples at the internal sample rate).  This is
+    /// intended for testing.
+    pub fn get_period(&self) -> f32 {
+        self.period
+    }
+
+    /// Get the fractional part of the period that was carried over to the next block.  This is
+    /// intended for testing.
+    pub fn get_period_offset(&self) -> f32 {
+        self.period_offset
+    }
+
+    /// Get the number of samples in the current block of output, and the number of frequency
+    /// components in the spectrum used to generate it.  This is intended for testing.
+    pub fn get_block_sizes(&self) -> (usize, usize) {
+        (self.output_size, self.spectrum_size)
+    }
+
+    /// Get the allocated lengths of the output and spectrum buffers.  This is intended for testing.
+    pub fn get_buffer_lengths(&self) -> (usize, usize) {
+        (self.output_buffer.len(), self.spectrum_buffer.len())
+    }
+
+    /// Get the sample rate the instrument generates audio at internally.  This is either the
+    /// output sample rate or twice that.
+    pub fn get_internal_sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    /// Find the tail of a previous note with the least energy.  This returns its index and a
+    /// measure of its level, or None if there are no tails.
+    pub(crate) fn quietest_tail(&self) -> Option<(usize, f32)> {
+        let mut result: Option<(usize, f32)> = None;
+        for (i, note) in self.decaying_notes.iter().enumerate() {
+            let level = note.level();
+            if result.is_none_or(|(_, quietest)| level < quietest) {
+                result = Some((i, level));
+            }
+        }
+        result
+    }
+
+    /// Immediately stop the tail of a previous note.
+    pub(crate) fn remove_tail(&mut self, index: usize) {
+        self.decaying_notes.swap_remove(index);
+    }
+
     /// Set the volume of the excitation from the bow (between 0.0 and 1.0).
     pub fn set_volume(&mut self, volume: f32) {
         self.volume = volume;
@@ -163,7 +348,10 @@ impl Instrument {
 
     /// Set the frequency of the string (in Hz).
     pub fn set_frequency(&mut self, frequency: f32) {
-        self.frequency = frequency;
+        self.frequency = frequency.clamp(MIN_FREQUENCY, MAX_FREQUENCY);
+        if self.frequency.is_nan() {
+            self.frequency = MIN_FREQUENCY;
+        }
     }
 
     /// Set the bow position along the string.
@@ -176,12 +364,220 @@ impl Instrument {
         self.vibrato_amplitude = amplitude;
     }
 
+    /// Get the amplitude of vibrato.
+    pub fn get_vibrato_amplitude(&self) -> f32 {
+        self.vibrato_amplitude
+    }
+
+    /// Set the waveform used for vibrato.
+    pub fn set_vibrato_shape(&mut self, shape: VibratoShape) {
+        self.vibrato_shape = shape;
+    }
+
+    /// Set whether the vibrato phase is reset at the start of each new note.  This makes every note
+    /// begin its vibrato the same way, regardless of when it is played.
+    pub fn set_vibrato_phase_reset(&mut self, reset: bool) {
+        self.vibrato_phase_reset = reset;
+    }
+
+    /// Get the current phase of the vibrato (measured in cycles).
+    pub fn get_vibrato_phase(&self) -> f32 {
+        self.vibrato_phase
+    }
+
     /// Set whether harmonics are enabled.
     pub fn set_harmonics(&mut self, harmonics: bool) {
         self.harmonics = harmonics;
         self.update_filter();
     }
 
+    /// Set the correlation between the real and imaginary parts of the random excitation
+    /// (between 0.0 and 1.0).
+    pub fn set_excitation_coherence(&mut self, coherence: f32) {
+        self.excitation_coherence = coherence;
+        self.frozen_size = 0;
+    }
+
+    /// Set how much the random excitation varies from one period to the next (between 0.0 and 1.0).
+    /// At 1 it is completely different on every period.  At 0 every period of a note receives the
+    /// same excitation, chosen at random when the note starts, giving a perfectly steady tone.
+    pub fn set_excitation_variation(&mut self, variation: f32) {
+        self.excitation_variation = variation;
+    }
+
+    /// Set the amount of broadband noise added at the start of a bowed note (between 0.0 and 1.0).
+    /// This imitates the noisy transient before the string settles into periodic motion.
+    pub fn set_attack_noise(&mut self, amount: f32) {
+        self.attack_noise = amount;
+    }
+
+    /// Set the amount by which the tuning slowly wanders over time (between 0.0 and 1.0).  This
+    /// imitates a player whose intonation drifts during long notes.  With 0 the tuning is stable.
+    pub fn set_pitch_drift(&mut self, amount: f32) {
+        self.pitch_drift_amount = amount;
+    }
+
+    /// Set the number of voices to generate (at least 1) and the detuning between them in cents.
+    /// Each voice after the first is an extra copy of the string with its own random excitation,
+    /// tuned up or down by at most the detuning, which makes the tone of a single player richer.
+    /// The voices are summed with a scale factor that keeps the level unchanged.  Each voice does
+    /// its own inverse FFT on every period, so the CPU time needed by the instrument is roughly
+    /// proportional to the count.  With a count of 1 the output is unchanged.
+    pub fn set_unison_voices(&mut self, count: usize, detune: f32) {
+        let extra = count.max(1)-1;
+        self.unison_voices.truncate(extra);
+        while self.unison_voices.len() < extra {
+            self.unison_voices.push(UnisonVoice {
+                detune: 1.0,
+                new_note: false,
+                spectrum_buffer: vec![],
+                spectrum_temp: vec![],
+                output_buffer: vec![],
+                spectrum_size: 0,
+                output_size: 0,
+                output_position: 0,
+                period: 0.0,
+                period_offset: 0.0
+            });
+        }
+
+        // Alternate voices are tuned up and down, spreading them evenly out to the full detuning.
+
+        self.unison_detune = detune;
+        let steps = extra.div_ceil(2);
+        for (i, voice) in self.unison_voices.iter_mut().enumerate() {
+            let sign = if i%2 == 0 {1.0} else {-1.0};
+            let cents = sign*detune*(i/2+1) as f32/steps as f32;
+            voice.detune = 2.0_f32.powf(cents/1200.0);
+        }
+    }
+
+    /// Get the number of voices being generated, and the detuning between them in cents.
+    pub fn get_unison_voices(&self) -> (usize, f32) {
+        (self.unison_voices.len()+1, self.unison_detune)
+    }
+
+    /// Set the amount of inharmonicity (between 0.0 and 1.0).  Real strings are stiff, so their
+    /// upper partials are slightly sharp of exact harmonics.  Each period of output only contains
+    /// exact harmonics, so this is approximated by advancing the phase of each partial a little on
+    /// every period, which raises its average frequency.  The shift is limited to a fraction of the
+    /// spacing between harmonics, so the highest partials are stretched less than on a real string.
+    /// With 0 the partials are exactly harmonic.
+    pub fn set_inharmonicity(&mut self, amount: f32) {
+        self.inharmonicity = amount;
+        self.sustain_gain.fill(0.0);
+    }
+
+    /// Set how quickly the tails of previous notes decay, relative to the default for the
+    /// instrument type.  This affects tails of notes that end after it is called.
+    pub fn set_tail_decay(&mut self, rate: f32) {
+        self.tail_decay = rate;
+    }
+
+    /// Set how much the high frequencies are damped as a note is held (between 0.0 and 1.0).  The
+    /// extra damping starts at 0 for each new note and increases gradually, so long notes settle
+    /// into a slightly darker tone.  With 0 the damping is constant.
+    pub fn set_sustain_damping(&mut self, amount: f32) {
+        self.sustain_damping = amount;
+    }
+
+    /// Begin the burst of attack noise for a new note.  Louder notes have more noise.  The burst
+    /// starts when the bow begins exciting the string, so it is not lost on delayed instruments.
+    pub fn start_attack_noise(&mut self, velocity: f32) {
+        if self.attack_noise > 0.0 {
+            self.attack_noise_level = self.attack_noise*velocity;
+            self.attack_noise_time = 0.0;
+        }
+    }
+
+    /// Set which stage of the synthesis model is output.
+    pub fn set_debug_tap(&mut self, tap: DebugTap) {
+        self.debug_tap = tap;
+    }
+
+    /// Set the highest frequency that is excited by the bow, as a fraction of the Nyquist frequency
+    /// (between 0.0 and 1.0).  Lower values give a darker tone with fewer harmonics.
+    pub fn set_max_harmonic(&mut self, ratio: f32) {
+        self.max_harmonic = ratio;
+    }
+
+    /// Set how much the tails of previous notes are spread in stereo (between 0.0 and 1.0).  Each
+    /// tail is given a random position, and generate_stereo() reports how far the output should be
+    /// shifted toward each side.  When this is 0, the tails are not spread.
+    pub fn set_tail_spread(&mut self, spread: f32) {
+        self.tail_spread = spread;
+        if spread == 0.0 {
+            for note in &mut self.decaying_notes {
+                note.pan = 0.0;
+            }
+        }
+    }
+
+    /// Set the target level for sustained notes.  When this is greater than 0, the excitation is
+    /// scaled so the steady state amplitude of a sustained note does not depend on its pitch.  The
+    /// level is relative to the amplitude of a note in the middle of the instrument's range.  When
+    /// it is 0, the amplitude is not normalized.
+    pub fn set_sustain_level(&mut self, level: f32) {
+        self.sustain_level = level;
+    }
+
+    /// Set the amount of phase decorrelation (between 0.0 and 1.0).  Each frequency component of the
+    /// output is shifted by a fixed random phase offset, scaled by this amount.  This makes the
+    /// instruments in a section less correlated with each other, so they sound less phasey
+    /// when summed.
+    pub fn set_phase_decorrelation(&mut self, amount: f32) {
+        self.phase_decorrelation = amount;
+        let size = self.phase_rotation.len().max(self.spectrum_buffer.len());
+        self.update_phase_rotation(size);
+    }
+
+    /// Update the rotations applied to the first size frequency components based on the phase
+    /// decorrelation.  The random offsets come from their own seed rather than the Instrument's
+    /// generator, so decorrelation does not change the excitation.  Each time they are extended,
+    /// they are regenerated from the same seed so the existing values are unchanged.
+    fn update_phase_rotation(&mut self, size: usize) {
+        if self.phase_decorrelation == 0.0 {
+            self.phase_rotation.clear();
+            return;
+        }
+        if self.phase_offsets.len() < size {
+            // The DC component must remain real.
+
+            let mut random = Random::with_seed(self.phase_seed);
+            self.phase_offsets.clear();
+            self.phase_offsets.push(0.0);
+            while self.phase_offsets.len() < size {
+                self.phase_offsets.push(2.0*PI*(random.get_uniform()-0.5));
+            }
+        }
+        self.phase_rotation.clear();
+        for i in 0..size {
+            self.phase_rotation.push(Complex::from_polar(1.0, self.phase_decorrelation*self.phase_offsets[i]));
+        }
+    }
+
+    /// Plan the inverse FFTs for every output size an instrument of the specified type is likely to
+    /// use, so they are cached in the planner before the first note.  Planning a new size on the
+    /// audio thread can take long enough to cause a dropout.  The output size is the length of one
+    /// period at the internal sample rate, so this covers the instrument's full note range extended
+    /// by two semitones in each direction for pitch bends, plus another 2% for vibrato and random
+    /// drift.  There are over a thousand sizes for the bass, so this takes several milliseconds and
+    /// should not be called from the audio thread.
+    pub fn prepare_fft_plans(instrument_type: InstrumentType, fft_planner: &mut RealFftPlanner::<f32>) {
+        let sample_rate = TypeParameters::new(instrument_type).sample_rate;
+        let lowest_freq = 440.0 * f32::powf(2.0, (instrument_type.lowest_note()-2-69) as f32/12.0);
+        let highest_freq = 440.0 * f32::powf(2.0, (instrument_type.highest_note()+2-69) as f32/12.0);
+        plan_ffts(fft_planner, sample_rate, lowest_freq, highest_freq);
+    }
+
+    /// Plan the inverse FFTs for the output sizes used when playing a single frequency, allowing 2%
+    /// in each direction for vibrato and random drift.  This is only a few dozen sizes even for the
+    /// lowest notes, so it is quick enough to call on the audio thread when a note starts.  Sizes
+    /// that have already been planned are reused.
+    pub fn prepare_note_fft_plans(&self, fft_planner: &mut RealFftPlanner::<f32>, frequency: f32) {
+        plan_ffts(fft_planner, self.sample_rate, frequency, frequency);
+    }
+
     /// Update the cutoff frequency of the lowpass filter.
     fn update_filter(&mut self) {
         let note_freq = 440.0 * f32::powf(2.0, (self.last_note-69) as f32/12.0);
@@ -193,6 +589,9 @@ impl Instrument {
     /// Add excitation from the bow to the spectrum.
     fn add_bow_excitation(&mut self) {
         let mut c = self.volume/(self.spectrum_size as f32).sqrt();
+        if self.sustain_level > 0.0 {
+            c *= self.sustain_level*self.sustain_gain();
+        }
         if self.harmonics {
             c *= 2.0;
         }
@@ -213,6 +612,7 @@ impl Instrument {
             }
         }
         let volume = f32::min(1.0, self.volume);
+        let end = ((self.max_harmonic*self.spectrum_size as f32) as usize).max(2).min(self.spectrum_size);
         match &self.last_articulation {
             Articulation::Pizzicato | Articulation::ColLegno => {
                 let x = (self.last_note-self.instrument_type.lowest_note()) as f32 / (self.instrument_type.highest_note()-self.instrument_type.lowest_note()) as f32;
@@ -223,10 +623,11 @@ impl Instrument {
                 else {
                     decay_target = 1.0-volume;
                 }
-                for i in 1..self.spectrum_size {
+                for i in 1..end {
                     let decay = 1.0-decay_target*(i as f32/self.spectrum_size as f32);
-                    let scale = c*decay*(1.0-i as f32/self.spectrum_size as f32).powi(self.pizzicato_exponent);
-                    self.spectrum_buffer[i] += Complex::<f32>::new(scale*self.random.get_uniform(), scale*self.random.get_uniform());
+                    let scale = c*decay*(1.0-i as f32/self.spectrum_size as f32).powi(self.pizzicato_exponent)*self.excitation_compensation(i);
+                    let excitation = self.next_excitation(i);
+                    self.spectrum_buffer[i] += scale*excitation;
                 }
             }
             _ => {
@@ -235,7 +636,12 @@ impl Instrument {
 
                 let (m1, b1, m2, b2) = self.spectrum_coeff;
                 let decay_target = 0.7*(1.0-volume)*(1.0-volume);
-                for i in 1..self.spectrum_size {
+                let mut burst = 0.0;
+                if self.attack_noise_time < ATTACK_NOISE_DURATION {
+                    burst = ATTACK_NOISE_LEVEL*self.attack_noise_level*(1.0-self.attack_noise_time/ATTACK_NOISE_DURATION);
+                    self.attack_noise_time += self.output_size as f32/self.sample_rate;
+                }
+                for i in 1..end {
                     let x = i as f32/self.spectrum_size as f32;
                     let logx = x.ln();
                     let y1 = f32::exp(-m1*logx + b1);
@@ -263,13 +669,116 @@ impl Instrument {
                             decay *= 0.05+3.9*self.bow_position;
                         }
                     }
-                    let scale = c*decay*f32::min(y1, y2);
-                    self.spectrum_buffer[i] += Complex::<f32>::new(scale*self.random.get_uniform(), scale*self.random.get_uniform());
+                    let scale = c*decay*f32::min(y1, y2)*self.excitation_compensation(i);
+                    let excitation = self.next_excitation(i);
+                    self.spectrum_buffer[i] += scale*excitation;
+                    if burst > 0.0 {
+                        self.spectrum_buffer[i] += burst*scale*random_excitation(&mut self.random, 0.0);
+                    }
                 }
             }
         }
     }
 
+    /// Get the random excitation to add to component i on this period.  With less than full
+    /// variation, it is a mix of a new random value and the note's fixed excitation.  The fixed part
+    /// accumulates coherently from one period to the next, so it is scaled to reach the same steady
+    /// state power as the random part, which accumulates incoherently.
+    fn next_excitation(&mut self, i: usize) -> Complex<f32> {
+        let excitation = random_excitation(&mut self.random, self.excitation_coherence);
+        if self.excitation_variation >= 1.0 {
+            return excitation;
+        }
+        if self.frozen_size <= i {
+            // Each time the pattern is extended, it is regenerated from the same seed so the existing
+            // values are unchanged.  The buffer is allocated up front to the largest size a note can
+            // need, so this never allocates.
+
+            let mut random = Random::with_seed(self.frozen_seed);
+            let size = self.spectrum_size.max(i+1);
+            for value in &mut self.frozen_excitation[..size] {
+                *value = random_excitation(&mut random, self.excitation_coherence);
+            }
+            self.frozen_size = size;
+        }
+        let (r, denom) = self.filter_response(i);
+        let coherent_gain = (denom/(1.0-r*r)).sqrt();
+        self.excitation_variation.sqrt()*excitation + (1.0-self.excitation_variation).sqrt()*coherent_gain*self.frozen_excitation[i]
+    }
+
+    /// Get the factor r by which apply_filter() multiplies component i on each period, and the
+    /// value of |1-r*exp(i*phi)|^2, where phi is the phase shift it applies.
+    fn filter_response(&self, i: usize) -> (f32, f32) {
+        let phase = self.filter_phase(i, self.spectrum_size);
+        let mut r = filter_decay(i, self.spectrum_size, FILTER_DAMPING);
+        if self.harmonics && i%4 != 0 {
+            r *= 0.1;
+        }
+        (r, (1.0-Complex::<f32>::from_polar(r, phase)).norm_sqr())
+    }
+
+    /// Get the total phase shift apply_filter() applies to component i on each period, for a
+    /// spectrum of the specified size.
+    fn filter_phase(&self, i: usize, spectrum_size: usize) -> f32 {
+        self.phase_shift*i as f32/spectrum_size as f32 + self.inharmonic_phase(i)
+    }
+
+    /// The bow excitation used to be biased, with a mean of 0.5+0.5i in every frequency component.  The
+    /// mean accumulates coherently from one period to the next, while the random part accumulates
+    /// incoherently, so the bias contributed much of the level.  This computes a scale factor that gives
+    /// the unbiased excitation the same steady state power in component i.  It depends on the decay
+    /// factor r and phase shift phi that apply_filter() applies to that component, so it must be kept
+    /// consistent with it.
+    fn excitation_compensation(&self, i: usize) -> f32 {
+        let (r, denom) = self.filter_response(i);
+        (0.25 + 0.75*(1.0-r*r)/denom).sqrt()
+    }
+
+    /// Get the factor by which to scale the excitation so that a sustained note at the current
+    /// spectrum size reaches the same steady state power as one in the middle of the instrument's
+    /// range.  Values are cached, since the spectrum size changes slightly on every period.
+    fn sustain_gain(&mut self) -> f32 {
+        if self.sustain_gain.len() <= self.spectrum_size {
+            self.sustain_gain.resize(self.spectrum_size+1, 0.0);
+        }
+        if self.sustain_gain[self.spectrum_size] == 0.0 {
+            let middle_note = 0.5*(self.instrument_type.lowest_note()+self.instrument_type.highest_note()) as f32;
+            let middle_frequency = 440.0*2.0f32.powf((middle_note-69.0)/12.0);
+            let reference_size = (0.5*self.sample_rate/middle_frequency + 1.0).floor() as usize;
+            self.sustain_gain[self.spectrum_size] = (self.steady_state_power(reference_size)/self.steady_state_power(self.spectrum_size)).sqrt();
+        }
+        self.sustain_gain[self.spectrum_size]
+    }
+
+    /// Estimate the steady state power of a note at full volume with the default bow position.  On
+    /// each period, component i receives random excitation with amplitude s and is then multiplied
+    /// by r, so its power converges to s^2 r^2/(1-r^2) times the variance of the excitation.  This
+    /// must be kept consistent with add_bow_excitation() and apply_filter().
+    fn steady_state_power(&self, spectrum_size: usize) -> f32 {
+        let (m1, b1, m2, b2) = self.spectrum_coeff;
+        let mut power = 0.0;
+        for i in 1..spectrum_size {
+            let x = i as f32/spectrum_size as f32;
+            let logx = x.ln();
+            let y = f32::min(f32::exp(-m1*logx + b1), f32::exp(-m2*logx + b2));
+            let r = filter_decay(i, spectrum_size, FILTER_DAMPING);
+            let denom = (1.0-Complex::<f32>::from_polar(r, self.filter_phase(i, spectrum_size))).norm_sqr();
+            let compensation = 0.25 + 0.75*(1.0-r*r)/denom;
+            power += y*y*compensation*r*r/(1.0-r*r);
+        }
+        power/spectrum_size as f32
+    }
+
+    /// Get the amount by which apply_filter() advances the phase of component i on each period to
+    /// make it inharmonic.
+    fn inharmonic_phase(&self, i: usize) -> f32 {
+        if self.inharmonicity == 0.0 {
+            return 0.0;
+        }
+        let shift = 0.5*self.inharmonicity*MAX_INHARMONICITY*(i as f32).powi(3);
+        2.0*PI*shift.min(MAX_INHARMONIC_SHIFT)
+    }
+
     /// Apply the filter to the spectrum buffer to damp the sound.
     fn apply_filter(&mut self) {
         if self.phase_shift != 0.0 {
@@ -281,11 +790,15 @@ impl Instrument {
                 self.spectrum_buffer[i] *= Complex::<f32>::new(phase.cos(), phase.sin());
             }
         }
-        let s = if self.volume == 0.0 {0.2} else {0.15}; // Make the sound decay faster after the end of the note.
+        if self.inharmonicity != 0.0 {
+            for i in 1..self.spectrum_size {
+                let rotation = Complex::<f32>::from_polar(1.0, self.inharmonic_phase(i));
+                self.spectrum_buffer[i] *= rotation;
+            }
+        }
+        let s = if self.volume == 0.0 {RELEASE_FILTER_DAMPING} else {FILTER_DAMPING}; // Make the sound decay faster after the end of the note.
         for i in 1..self.spectrum_size {
-            let f = i as f32/self.spectrum_size as f32;
-            let scale = 1.0-(0.07-0.06*(-8.0*f).exp())*(self.spectrum_size as f32).sqrt()*s;
-            self.spectrum_buffer[i] *= scale;
+            self.spectrum_buffer[i] *= filter_decay(i, self.spectrum_size, s);
         }
         if self.harmonics {
             for i in 1..self.spectrum_size {
@@ -294,15 +807,60 @@ impl Instrument {
                 }
             }
         }
+        if self.sustain_damping > 0.0 {
+            // This is deliberately left out of excitation_compensation(), so the high frequencies
+            // really do become quieter.
+
+            let damping = self.sustain_damping*MAX_SUSTAIN_DAMPING*(1.0-(-self.note_time/SUSTAIN_DAMPING_TIME).exp());
+            let frequency_step = 0.5*self.sample_rate/(self.spectrum_size as f32*SUSTAIN_DAMPING_FREQUENCY);
+            for i in 1..self.spectrum_size {
+                self.spectrum_buffer[i] *= 1.0-damping*(i as f32*frequency_step).min(1.0);
+            }
+        }
+    }
+
+    /// Discard all sound the instrument is still producing: the vibration of the string, the tails
+    /// of previous notes, and any output that has already been computed.  Settings and the timing of
+    /// the output are unaffected.  This is used when an instrument becomes idle, so the next note
+    /// starts from silence instead of resuming whatever was left over.
+    pub fn clear_output(&mut self) {
+        self.spectrum_buffer.fill(Complex::<f32>::new(0.0, 0.0));
+        self.output_buffer.fill(0.0);
+        self.decaying_notes.clear();
+        for voice in &mut self.unison_voices {
+            voice.spectrum_buffer.fill(Complex::<f32>::new(0.0, 0.0));
+            voice.output_buffer.fill(0.0);
+            voice.new_note = false;
+        }
+        self.lowpass.reset();
+        self.side_lowpass.reset();
     }
 
     /// Generate the next audio sample.
     pub fn generate(&mut self, fft_planner: &mut RealFftPlanner::<f32>) -> f32 {
+        self.generate_stereo(fft_planner).0
+    }
+
+    /// Generate the next audio sample, along with a side signal describing how the tails of
+    /// previous notes are spread in stereo.  The side signal should be subtracted from the left
+    /// channel and added to the right channel.  It is always 0 unless set_tail_spread() has been
+    /// called.
+    pub fn generate_stereo(&mut self, fft_planner: &mut RealFftPlanner::<f32>) -> (f32, f32) {
         if self.sample_rate == SAMPLE_RATE as f32 {
             self.generate_internal(fft_planner)
         }
         else {
-            0.5*(self.generate_internal(fft_planner)+self.generate_internal(fft_planner))
+            let (signal1, side1) = self.generate_internal(fft_planner);
+            let (signal2, side2) = self.generate_internal(fft_planner);
+            (0.5*(signal1+signal2), 0.5*(side1+side2))
+        }
+    }
+
+    /// Generate a block of audio samples along with the corresponding side signal.  This is
+    /// equivalent to calling generate_stereo() once for each sample.
+    pub fn generate_block(&mut self, fft_planner: &mut RealFftPlanner::<f32>, signal: &mut [f32], side: &mut [f32]) {
+        for (s, d) in signal.iter_mut().zip(side.iter_mut()) {
+            (*s, *d) = self.generate_stereo(fft_planner);
         }
     }
 
@@ -310,26 +868,38 @@ impl Instrument {
     /// audio generation can happen at either the output sample rate or twice that.  This
     /// generates data at the internal rate, and generate() calls it either once or twice
     /// for each output sample.
-    fn generate_internal(&mut self, fft_planner: &mut RealFftPlanner::<f32>) -> f32 {
+    fn generate_internal(&mut self, fft_planner: &mut RealFftPlanner::<f32>) -> (f32, f32) {
         let mut result = 0.0;
+        let mut side = 0.0;
         if self.output_position >= self.output_size {
             if self.start_new_note {
                 // We're at the start of a new note.  Move the tail of the previous note into
                 // a separate object where it will be unaffected by further changes.
 
-                if self.spectrum_size > 0 {
-                    self.decaying_notes.push(DecayingNote::new(&self.spectrum_buffer[..self.spectrum_size], self.output_size));
+                self.start_tail();
+                for voice in &mut self.unison_voices {
+                    voice.new_note = true;
                 }
-                for i in 1..self.spectrum_size {
-                    self.spectrum_buffer[i] = Complex::<f32>::new(0.0, 0.0);
+                if self.vibrato_phase_reset {
+                    self.vibrato_phase = self.initial_vibrato_phase;
                 }
+
+                // Choose a new fixed excitation for the note.  The seed is derived from the state of
+                // the random number generator without advancing it, so the rest of the sound is the
+                // same for any amount of variation.
+
+                self.frozen_seed = self.random.state().i ^ 0x9e3779b9;
+                self.frozen_size = 0;
+                self.note_time = 0.0;
                 self.start_new_note = false;
             }
 
             // Add the sound from the tails of previous notes.
 
             for note in &mut self.decaying_notes {
-                result += note.generate(fft_planner);
+                let output = note.generate(fft_planner);
+                result += output;
+                side += note.pan*output;
             }
             self.decaying_notes.retain(|n| !n.finished);
 
@@ -356,61 +926,221 @@ impl Instrument {
             self.frequency_drift = freq_drift_decay*self.frequency_drift + freq_drift_noise*self.random.get_normal();
             let vibrato_offset = vibrato_freq*self.period/self.sample_rate;
             self.vibrato_phase = (self.vibrato_phase+vibrato_offset) % 4.0;
-            let vibrato = (2.0*PI*self.vibrato_phase).sin();
-            let vibrato = vibrato*vibrato*vibrato;
-            let current_frequency = self.frequency * (1.0+self.frequency_drift_amplitude*self.frequency_drift) * (1.0+vibrato_amplitude*vibrato);
+            let vibrato = self.vibrato_shape.evaluate(self.vibrato_phase);
+            let mut current_frequency = self.frequency * (1.0+self.frequency_drift_amplitude*self.frequency_drift) * (1.0+vibrato_amplitude*vibrato);
 
-            // Update the buffer sizes.
+            // The pitch drift is a much slower random process that is kept within a limited range.
 
-            let new_period = self.sample_rate/current_frequency;
-            let new_output_size = (new_period+self.period_offset).floor() as usize;
-            let new_spectrum_size = (new_output_size as f32/2.0 + 1.0).floor() as usize;
-            if new_output_size > self.output_buffer.len() {
-                self.output_buffer.resize(new_output_size, 0.0);
+            if self.pitch_drift_amount > 0.0 {
+                let pitch_drift_decay = (-self.period/(PITCH_DRIFT_TIME*self.sample_rate)).exp();
+                let pitch_drift_noise = (1.0-pitch_drift_decay*pitch_drift_decay).sqrt();
+                self.pitch_drift = (pitch_drift_decay*self.pitch_drift + pitch_drift_noise*self.random.get_normal()).clamp(-MAX_PITCH_DRIFT, MAX_PITCH_DRIFT);
+                current_frequency *= 2.0_f32.powf(self.get_pitch_drift()/1200.0);
             }
-            if new_spectrum_size > self.spectrum_buffer.len() {
-                self.spectrum_buffer.resize(new_spectrum_size, Complex::<f32>::new(0.0, 0.0));
-                self.spectrum_temp.resize(new_spectrum_size, Complex::<f32>::new(0.0, 0.0));
-            }
-            for i in self.spectrum_size..new_spectrum_size {
-                self.spectrum_buffer[i] = Complex::<f32>::new(0.0, 0.0);
-            }
-            self.period = new_period;
-            self.output_size = new_output_size;
-            self.spectrum_size = new_spectrum_size;
-            self.period_offset = new_period+self.period_offset-new_output_size as f32;
 
-            // Update the spectrum.
+            self.unison_frequency = current_frequency;
+            self.note_time += self.period/self.sample_rate;
+            self.start_period(fft_planner, current_frequency);
+        }
+        else {
+            // Add the sound from the tails of previous notes.
 
-            if self.volume != 0.0 {
-                self.add_bow_excitation();
+            for note in &mut self.decaying_notes {
+                let output = note.generate(fft_planner);
+                result += output;
+                side += note.pan*output;
             }
-            self.apply_filter();
+        }
 
-            // Generate a new batch of output.
+        // Return output from the buffer, summing all the voices.
 
-            let fft = fft_planner.plan_fft_inverse(self.output_size);
-            if self.scratch.len() < fft.get_scratch_len() {
-                self.scratch.resize(fft.get_scratch_len(), Complex::<f32>::new(0.0, 0.0));
+        let mut voices = self.output_buffer[self.output_position];
+        self.output_position += 1;
+        if !self.unison_voices.is_empty() {
+            for i in 0..self.unison_voices.len() {
+                voices += self.generate_unison_voice(fft_planner, i);
             }
-            transform_spectrum(&fft, &self.spectrum_buffer[..self.spectrum_size], &mut self.spectrum_temp[..self.spectrum_size],
-                               &mut self.output_buffer[..self.output_size], &mut self.scratch[..]);
-            self.output_position = 0;
+            voices /= ((self.unison_voices.len()+1) as f32).sqrt();
         }
-        else {
-            // Add the sound from the tails of previous notes.
+        result += voices;
+        result = self.lowpass.process(result);
+        if self.tail_spread > 0.0 {
+            side = self.side_lowpass.process(side);
+        }
+        return (result, side);
+    }
 
-            for note in &mut self.decaying_notes {
-                result += note.generate(fft_planner);
+    /// Move the sound of the string into a separate object where it continues as the tail of the
+    /// previous note, unaffected by further changes, and clear the spectrum for a new note.
+    fn start_tail(&mut self) {
+        if self.spectrum_size > 0 {
+            // The tail continues with the same phases it was being output with.
+
+            let damping = TAIL_DAMPING*self.tail_decay*self.instrument_type.tail_decay_rate();
+            let mut note = DecayingNote::new(&self.spectrum_buffer[..self.spectrum_size], self.output_size, damping);
+            note.rotate_phases(&self.phase_rotation);
+            if !self.unison_voices.is_empty() {
+                note.scale(1.0/((self.unison_voices.len()+1) as f32).sqrt());
             }
+            if self.tail_spread > 0.0 {
+                note.pan = self.tail_spread*(2.0*self.random.get_uniform()-1.0);
+            }
+            self.decaying_notes.push(note);
+        }
+        for i in 1..self.spectrum_size {
+            self.spectrum_buffer[i] = Complex::<f32>::new(0.0, 0.0);
         }
+    }
 
-        // Return output from the buffer.
+    /// Generate the next sample from one of the extra unison voices.  Its state is swapped into
+    /// the Instrument so it can be updated in the same way as the main voice.  Each voice starts
+    /// new periods at its own times, using the frequency most recently computed for the main voice.
+    fn generate_unison_voice(&mut self, fft_planner: &mut RealFftPlanner::<f32>, index: usize) -> f32 {
+        self.swap_unison_voice(index);
+        if self.output_position >= self.output_size {
+            if self.unison_voices[index].new_note {
+                self.start_tail();
+                self.unison_voices[index].new_note = false;
+            }
 
-        result += self.output_buffer[self.output_position];
-        result = self.lowpass.process(result);
+            // The burst of attack noise is timed by the main voice.
+
+            let attack_noise_time = self.attack_noise_time;
+            let frequency = self.unison_frequency*self.unison_voices[index].detune;
+            self.start_period(fft_planner, frequency);
+            self.attack_noise_time = attack_noise_time;
+        }
+        let result = self.output_buffer[self.output_position];
         self.output_position += 1;
-        return result;
+        self.swap_unison_voice(index);
+        result
+    }
+
+    /// Exchange the state of a unison voice with that of the main voice.
+    fn swap_unison_voice(&mut self, index: usize) {
+        let voice = &mut self.unison_voices[index];
+        std::mem::swap(&mut self.spectrum_buffer, &mut voice.spectrum_buffer);
+        std::mem::swap(&mut self.spectrum_temp, &mut voice.spectrum_temp);
+        std::mem::swap(&mut self.output_buffer, &mut voice.output_buffer);
+        std::mem::swap(&mut self.spectrum_size, &mut voice.spectrum_size);
+        std::mem::swap(&mut self.output_size, &mut voice.output_size);
+        std::mem::swap(&mut self.output_position, &mut voice.output_position);
+        std::mem::swap(&mut self.period, &mut voice.period);
+        std::mem::swap(&mut self.period_offset, &mut voice.period_offset);
+    }
+
+    /// Begin a new period of the string's vibration at the specified frequency.  This updates the
+    /// buffer sizes, adds excitation from the bow, applies the filter, and computes the next block
+    /// of output.
+    fn start_period(&mut self, fft_planner: &mut RealFftPlanner::<f32>, current_frequency: f32) {
+        // Update the buffer sizes.  Vibrato and drift can move the frequency slightly outside the
+        // range set_frequency() clamps to, so clamp it again to keep the buffers bounded.
+
+        let current_frequency = current_frequency.clamp(MIN_FREQUENCY, MAX_FREQUENCY);
+        let new_period = self.sample_rate/current_frequency;
+        let new_output_size = (new_period+self.period_offset).floor() as usize;
+        let new_spectrum_size = (new_output_size as f32/2.0 + 1.0).floor() as usize;
+        if new_output_size > self.output_buffer.len() {
+            self.output_buffer.resize(new_output_size, 0.0);
+        }
+        if new_spectrum_size > self.spectrum_buffer.len() {
+            self.spectrum_buffer.resize(new_spectrum_size, Complex::<f32>::new(0.0, 0.0));
+            self.spectrum_temp.resize(new_spectrum_size, Complex::<f32>::new(0.0, 0.0));
+        }
+        for i in self.spectrum_size..new_spectrum_size {
+            self.spectrum_buffer[i] = Complex::<f32>::new(0.0, 0.0);
+        }
+        if self.phase_decorrelation != 0.0 && self.phase_rotation.len() < new_spectrum_size {
+            self.update_phase_rotation(self.spectrum_buffer.len());
+        }
+        self.period = new_period;
+        self.output_size = new_output_size;
+        self.spectrum_size = new_spectrum_size;
+        self.period_offset = new_period+self.period_offset-new_output_size as f32;
+
+        // Update the spectrum.  When a debug tap is selected, the spectrum to output is recorded
+        // before the filter is applied.
+
+        if self.debug_tap == DebugTap::Excitation {
+            self.debug_buffer.clear();
+            self.debug_buffer.extend_from_slice(&self.spectrum_buffer[..self.spectrum_size]);
+        }
+        if self.volume != 0.0 {
+            self.add_bow_excitation();
+        }
+        match self.debug_tap {
+            DebugTap::Output => {}
+            DebugTap::PreFilter => {
+                self.debug_buffer.clear();
+                self.debug_buffer.extend_from_slice(&self.spectrum_buffer[..self.spectrum_size]);
+            }
+            DebugTap::Excitation => {
+                for i in 0..self.spectrum_size {
+                    self.debug_buffer[i] = self.spectrum_buffer[i]-self.debug_buffer[i];
+                }
+            }
+        }
+        self.apply_filter();
+
+        // Generate a new batch of output.
+
+        let fft = fft_planner.plan_fft_inverse(self.output_size);
+        if self.scratch.len() < fft.get_scratch_len() {
+            self.scratch.resize(fft.get_scratch_len(), Complex::<f32>::new(0.0, 0.0));
+        }
+        let spectrum = if self.debug_tap == DebugTap::Output {&self.spectrum_buffer[..self.spectrum_size]} else {&self.debug_buffer[..]};
+        transform_spectrum(&fft, spectrum, &self.phase_rotation, &mut self.spectrum_temp[..self.spectrum_size],
+                           &mut self.output_buffer[..self.output_size], &mut self.scratch[..]);
+        self.output_position = 0;
+    }
+}
+
+/// The parameters of an Instrument that depend on its type.
+struct TypeParameters {
+    vibrato_low_frequency: f32,
+    vibrato_high_frequency: f32,
+    spectrum_coeff: (f32, f32, f32, f32),
+    pizzicato_exponent: i32,
+    sample_rate: f32,
+    phase_shift: f32
+}
+
+impl TypeParameters {
+    fn new(instrument_type: InstrumentType) -> Self {
+        match instrument_type {
+            InstrumentType::Violin => Self {
+                vibrato_low_frequency: 5.15,
+                vibrato_high_frequency: 5.4,
+                spectrum_coeff: (0.18917376, 0.0050823237, 1.3494484, -2.9302601),
+                pizzicato_exponent: 20,
+                sample_rate: 2.0*SAMPLE_RATE as f32,
+                phase_shift: 0.65
+            },
+            InstrumentType::Viola => Self {
+                vibrato_low_frequency: 5.15,
+                vibrato_high_frequency: 5.4,
+                spectrum_coeff: (0.59566957, -2.227358, 0.96543276, -2.170609),
+                pizzicato_exponent: 20,
+                sample_rate: SAMPLE_RATE as f32,
+                phase_shift: 0.35
+            },
+            InstrumentType::Cello => Self {
+                vibrato_low_frequency: 5.0,
+                vibrato_high_frequency: 5.5,
+                spectrum_coeff: (-0.0037527911, 0.55326806, 2.1803675, -4.7307866),
+                pizzicato_exponent: 30,
+                sample_rate: SAMPLE_RATE as f32,
+                phase_shift: 0.0
+            },
+            InstrumentType::Bass => Self {
+                vibrato_low_frequency: 4.9,
+                vibrato_high_frequency: 5.4,
+                spectrum_coeff: (0.34754488, -0.852053, 1.0190364, -2.5493735),
+                pizzicato_exponent: 40,
+                sample_rate: SAMPLE_RATE as f32,
+                phase_shift: 0.0
+            }
+        }
     }
 }
 
@@ -420,29 +1150,53 @@ struct DecayingNote {
     scratch: Vec<Complex<f32>>,
     output_buffer: Vec<f32>,
     output_position: usize,
+    damping: f32,
+    pan: f32,
     finished: bool
 }
 
 impl DecayingNote {
-    fn new(initial_spectrum: &[Complex<f32>], output_size: usize) -> Self {
+    fn new(initial_spectrum: &[Complex<f32>], output_size: usize, damping: f32) -> Self {
         let mut result = Self {
             spectrum_buffer: vec![],
             spectrum_temp: vec![Complex::<f32>::new(0.0, 0.0); initial_spectrum.len()],
             scratch: vec![],
             output_buffer: vec![0.0; output_size],
             output_position: output_size,
+            damping,
+            pan: 0.0,
             finished: false
         };
         result.spectrum_buffer.extend_from_slice(initial_spectrum);
         result
     }
 
+    /// Multiply each frequency component by a rotation.  If rotation is empty, nothing is done.
+    fn rotate_phases(&mut self, rotation: &[Complex<f32>]) {
+        if rotation.is_empty() {
+            return;
+        }
+        for (c, r) in self.spectrum_buffer.iter_mut().zip(rotation) {
+            *c *= r;
+        }
+    }
+
+    /// Multiply every frequency component by a constant.
+    fn scale(&mut self, factor: f32) {
+        for c in &mut self.spectrum_buffer {
+            *c *= factor;
+        }
+    }
+
+    /// Get the total energy of the spectrum.  This is a measure of how loud the note currently is.
+    fn level(&self) -> f32 {
+        self.spectrum_buffer.iter().map(|c| c.norm_sqr()).sum()
+    }
+
     fn apply_filter(&mut self) {
         let spectrum_size = self.spectrum_buffer.len();
         for i in 1..spectrum_size {
-            let f = i as f32/spectrum_size as f32;
-            let scale = 1.0-(0.07-0.06*(-8.0*f).exp())*(spectrum_size as f32).sqrt()*0.15;
-            self.spectrum_buffer[i] *= scale;
+            self.spectrum_buffer[i] *= filter_decay(i, spectrum_size, self.damping);
         }
     }
 
@@ -457,11 +1211,12 @@ impl DecayingNote {
             if self.scratch.len() < fft.get_scratch_len() {
                 self.scratch.resize(fft.get_scratch_len(), Complex::<f32>::new(0.0, 0.0));
             }
-            transform_spectrum(&fft, &self.spectrum_buffer[..], &mut self.spectrum_temp[..], &mut self.output_buffer[..], &mut self.scratch[..]);
+            transform_spectrum(&fft, &self.spectrum_buffer[..], &[], &mut self.spectrum_temp[..], &mut self.output_buffer[..], &mut self.scratch[..]);
             self.output_position = 0;
             self.finished = true;
             let mut max = 0.0;
             for i in 0..self.output_buffer.len() {
+                self.output_buffer[i] = flush_denormal(self.output_buffer[i]);
                 if self.output_buffer[i].abs() > max {
                     max = self.output_buffer[i].abs();
                 }
@@ -476,14 +1231,66 @@ impl DecayingNote {
     }
 }
 
-fn transform_spectrum(fft: &Arc<dyn ComplexToReal<f32>>, spectrum_buffer: &[Complex<f32>], spectrum_temp: &mut [Complex<f32>],
-                      output_buffer: &mut [f32], scratch: &mut [Complex<f32>]) {
+/// Plan the inverse FFTs for all output sizes needed to play frequencies between lowest_freq and
+/// highest_freq, plus 2% in each direction, at the specified internal sample rate.
+fn plan_ffts(fft_planner: &mut RealFftPlanner::<f32>, sample_rate: f32, lowest_freq: f32, highest_freq: f32) {
+    let lowest_freq = (lowest_freq/1.02).clamp(MIN_FREQUENCY, MAX_FREQUENCY);
+    let highest_freq = (highest_freq*1.02).clamp(MIN_FREQUENCY, MAX_FREQUENCY);
+    let min_size = (sample_rate/highest_freq).floor() as usize;
+    let max_size = (sample_rate/lowest_freq).ceil() as usize;
+    for size in min_size..=max_size {
+        fft_planner.plan_fft_inverse(size);
+    }
+}
+
+/// Generate a random complex value to add to one frequency component of the excitation.  The
+/// real and imaginary parts are each uniformly distributed between -1 and 1, so the mean is zero.
+/// Coherence specifies the correlation between them (between 0.0 and 1.0).
+pub fn random_excitation(random: &mut Random, coherence: f32) -> Complex<f32> {
+    let re = 2.0*random.get_uniform()-1.0;
+    let im = 2.0*random.get_uniform()-1.0;
+    Complex::<f32>::new(re, coherence*re + (1.0-coherence*coherence).sqrt()*im)
+}
+
+/// Get the factor by which the filter multiplies component i of a spectrum with the specified size
+/// on each period.  Higher frequencies decay faster, and larger values of damping make everything
+/// decay faster.
+fn filter_decay(i: usize, spectrum_size: usize, damping: f32) -> f32 {
+    let f = i as f32/spectrum_size as f32;
+    1.0-(0.07-0.06*(-8.0*f).exp())*(spectrum_size as f32).sqrt()*damping
+}
+
+/// Compute the output samples from a spectrum.  If phase_rotation is not empty, each frequency
+/// component is multiplied by the corresponding element of it.  For a real output, the DC
+/// component must be real, and so must the Nyquist component when the output size is even.  When
+/// it is odd there is no Nyquist component, and the last element may be complex.  The DC component
+/// is never excited or rotated, so only the Nyquist component needs to be fixed here.  If the
+/// transform fails anyway, the block of output is silent and fft_failed() will return true.
+fn transform_spectrum(fft: &Arc<dyn ComplexToReal<f32>>, spectrum_buffer: &[Complex<f32>], phase_rotation: &[Complex<f32>],
+                      spectrum_temp: &mut [Complex<f32>], output_buffer: &mut [f32], scratch: &mut [Complex<f32>]) {
     spectrum_temp.copy_from_slice(&spectrum_buffer);
+    if !phase_rotation.is_empty() {
+        for (c, r) in spectrum_temp.iter_mut().zip(phase_rotation) {
+            *c *= r;
+        }
+    }
     if output_buffer.len()%2 == 0 {
         spectrum_temp[spectrum_temp.len()-1].im = 0.0;
     }
     match fft.process_with_scratch(spectrum_temp, output_buffer, scratch) {
         Ok(_) => {}
-        Err(message) => {println!["{}", message]}
+        Err(_) => {
+            FFT_FAILED.store(true, Ordering::Relaxed);
+            output_buffer.fill(0.0);
+        }
     }
 }
+
+/// Get whether an inverse FFT has ever failed, so a block of output was replaced with silence.  This
+/// happens on the audio thread, so instead of printing anything, a flag is set that the host can
+/// check and report from another thread.
+pub fn fft_failed() -> bool {
+    FFT_FAILED.load(Ordering::Relaxed)
+}
+
+static FFT_FAILED: AtomicBool = AtomicBool::new(false);
@@ -15,6 +15,9 @@
 
 pub mod instrument;
 pub mod director;
+pub mod compressor;
+pub mod dither;
+pub mod preset;
 pub mod filter;
 pub mod random;
 pub mod resampler;
@@ -22,6 +25,40 @@ pub mod reverb;
 
 pub const SAMPLE_RATE: i32 = 48000;
 
+/// The range of note indices defined by MIDI.  Notes outside it are never played.
+pub const MIDI_NOTE_RANGE: std::ops::RangeInclusive<i32> = 0..=127;
+
+/// Values smaller than this are treated as silence by flush_denormal().
+const DENORMAL_THRESHOLD: f32 = 1e-20;
+
+/// Replace a value with 0 if it is so small it could become denormal.  Denormal floats
+/// are processed very slowly by some CPUs, which can cause sudden CPU spikes while long
+/// tails decay toward silence.
+#[inline]
+pub(crate) fn flush_denormal(x: f32) -> f32 {
+    if x.abs() < DENORMAL_THRESHOLD {0.0} else {x}
+}
+
+/// Errors that can occur while processing Messages.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SynthError {
+    /// The note is outside the range of the current instrument type.
+    NoteOutOfRange {note_index: i32},
+    /// In polyphonic mode, every division is already playing a note.
+    NoVoiceAvailable
+}
+
+impl std::fmt::Display for SynthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SynthError::NoteOutOfRange {note_index} => write!(f, "Note {} is outside the range of the instrument", note_index),
+            SynthError::NoVoiceAvailable => write!(f, "No voice is available to play the note")
+        }
+    }
+}
+
+impl std::error::Error for SynthError {}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum InstrumentType {
     Violin,
@@ -48,6 +85,105 @@ impl InstrumentType {
             InstrumentType::Bass => 67
         }
     }
+
+    /// Get the pitches of the open strings as MIDI notes, from lowest to highest.  The violin is
+    /// tuned G3 D4 A4 E5, the viola C3 G3 D4 A4, the cello C2 G2 D3 A3, and the bass E1 A1 D2 G2.
+    /// Notes below the lowest open string, such as the bass's low extension, belong to the lowest
+    /// string.
+    pub fn open_strings(&self) -> &'static [i32] {
+        match self {
+            InstrumentType::Violin => &[55, 62, 69, 76],
+            InstrumentType::Viola => &[48, 55, 62, 69],
+            InstrumentType::Cello => &[36, 43, 50, 57],
+            InstrumentType::Bass => &[28, 33, 38, 43]
+        }
+    }
+
+    /// Get how quickly the tails of previous notes decay, relative to a violin.  The longer strings
+    /// of larger instruments keep ringing longer.
+    pub fn tail_decay_rate(&self) -> f32 {
+        match self {
+            InstrumentType::Violin => 1.0,
+            InstrumentType::Viola => 0.9,
+            InstrumentType::Cello => 0.75,
+            InstrumentType::Bass => 0.6
+        }
+    }
+
+    /// Get a gain that compensates for the variation in loudness across the instrument's range,
+    /// so that equal dynamics sound roughly equally loud on every note.  The curve is specified
+    /// in dB at every sixth semitone starting from the lowest note, and interpolated in between.
+    /// It was determined by measuring the RMS output at constant dynamics, smoothed over about an
+    /// octave so it follows the overall trend rather than the resonances of the body.
+    pub fn loudness_compensation(&self, note: i32) -> f32 {
+        let curve: &[f32] = match self {
+            InstrumentType::Violin => &[3.7, 2.1, 0.1, -0.9, -1.7, -1.4, -0.3],
+            InstrumentType::Viola => &[3.2, 1.7, -2.2, -3.5, 0.4, 1.0, 1.5],
+            InstrumentType::Cello => &[4.0, 1.3, 2.9, 1.6, -1.1, -2.5, -2.7, -2.1],
+            InstrumentType::Bass => &[5.0, 2.9, -1.7, 1.4, 0.8, -1.6, -2.5, -2.1]
+        };
+        let x = ((note-self.lowest_note()) as f32/6.0).clamp(0.0, (curve.len()-1) as f32);
+        let i = (x as usize).min(curve.len()-2);
+        let db = curve[i] + (x-i as f32)*(curve[i+1]-curve[i]);
+        10.0f32.powf(db/20.0)
+    }
+}
+
+/// The impulse responses for instrument bodies that are bundled with the synthesizer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BundledIR {
+    Violin,
+    Viola,
+    Cello,
+    Bass
+}
+
+impl BundledIR {
+    /// Get the impulse response that is normally used for an instrument type.
+    pub fn for_instrument(instrument_type: InstrumentType) -> BundledIR {
+        match instrument_type {
+            InstrumentType::Violin => BundledIR::Violin,
+            InstrumentType::Viola => BundledIR::Viola,
+            InstrumentType::Cello => BundledIR::Cello,
+            InstrumentType::Bass => BundledIR::Bass
+        }
+    }
+}
+
+/// The waveform used for vibrato.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum VibratoShape {
+    /// A sine wave.  This gives the gentlest vibrato.
+    Sine,
+    /// A sine wave raised to the third power.  The pitch lingers near the center and moves
+    /// quickly to the extremes.
+    Cubed,
+    /// A triangle wave.  The pitch moves at a constant rate.
+    Triangle
+}
+
+impl VibratoShape {
+    /// Evaluate the waveform at a phase (measured in cycles).  The result is between -1 and 1.
+    pub fn evaluate(&self, phase: f32) -> f32 {
+        let sine = (2.0*std::f32::consts::PI*phase).sin();
+        match self {
+            VibratoShape::Sine => sine,
+            VibratoShape::Cubed => sine*sine*sine,
+            VibratoShape::Triangle => sine.clamp(-1.0, 1.0).asin()/(0.5*std::f32::consts::PI)
+        }
+    }
+}
+
+/// Which stage of the synthesis model an Instrument outputs.  Anything other than Output is
+/// intended for analyzing the model, not for making music.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DebugTap {
+    /// The normal output.
+    Output,
+    /// The spectrum after the bow excitation is added, but before it is damped by the filter.
+    PreFilter,
+    /// Only the excitation added by the bow in each period.
+    Excitation
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -59,4 +195,24 @@ pub enum Articulation {
     Pizzicato,
     ColLegno,
     Tremolo
+}
+
+impl Articulation {
+    /// Get a gain that compensates for the differences in loudness between articulations, so
+    /// switching articulation at fixed dynamics keeps roughly the same perceived level.  The values
+    /// (in dB) were determined by playing notes across the range of every instrument type at
+    /// velocity 0.8, taking the RMS of the loudest 100 ms of each note, and averaging the difference
+    /// from arco over all the types.  A 100 ms window roughly matches how the ear integrates the
+    /// loudness of short sounds.  The accent of marcato is part of its character, so it is not
+    /// compensated.
+    pub fn loudness_compensation(&self) -> f32 {
+        let db = match self {
+            Articulation::Arco | Articulation::Marcato | Articulation::Glissando => 0.0,
+            Articulation::Spiccato => -1.8,
+            Articulation::Pizzicato => 4.8,
+            Articulation::ColLegno => 8.3,
+            Articulation::Tremolo => 5.0
+        };
+        10.0f32.powf(db/20.0)
+    }
 }
\ No newline at end of file
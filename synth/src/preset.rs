@@ -0,0 +1,347 @@
+// Copyright 2026 by Peter Eastman
+//
+// This file is part of Viola Ex Machina.
+//
+// Viola Ex Machina is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Lesser General Public License as published by the Free Software Foundation, either
+// version 2.1 of the License, or (at your option) any later version.
+//
+// Viola Ex Machina is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License along with Viola Ex Machina.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use crate::director::{DirectorState, Message};
+use crate::{InstrumentType, Articulation, BundledIR, VibratoShape};
+
+/// A complete set of the settings that determine the sound of a Director.  It can be applied all
+/// at once with Director::apply_preset().  Values have the same meanings and units as in the
+/// corresponding Messages.
+///
+/// A Preset only includes settings that shape the sound.  Things that are normally changed while
+/// performing (volume, pitch bend, note tuning, mute, sostenuto) and diagnostic, routing, or resource
+/// settings (the test tone, synth bypass, separate outputs, debug tap, idle threshold, voice budget,
+/// polarity) are left unchanged.  The ensemble tightness is not included either, since the individual
+/// settings it controls already are.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preset {
+    pub instrument_type: InstrumentType,
+    pub instrument_count: usize,
+    pub articulation: Articulation,
+    pub polyphonic: bool,
+    pub bend_quantize: bool,
+    pub vibrato: f32,
+    pub vibrato_shape: VibratoShape,
+    pub vibrato_phase_reset: bool,
+    pub vibrato_gradient: f32,
+    pub bow_position: f32,
+    pub bow_noise: f32,
+    pub bow_alternation: f32,
+    pub release_rate: f32,
+    pub release_tail: i64,
+    pub harmonics: bool,
+    pub stereo_width: f32,
+    pub pan_center: f32,
+    pub stereo_tails: bool,
+    pub mono_compatible: bool,
+    pub max_instrument_delay: i64,
+    pub attack_jitter: f32,
+    pub attack_noise: f32,
+    pub entry_scoop_cents: f32,
+    pub entry_scoop_ms: i64,
+    pub tail_decay: f32,
+    pub auto_gain: bool,
+    pub compressor_enabled: bool,
+    pub compressor_threshold: f32,
+    pub compressor_ratio: f32,
+    pub impulse_response: Option<BundledIR>,
+    pub body_blend_type: InstrumentType,
+    pub body_blend: f32,
+    pub air: f32,
+    pub phase_decorrelation: f32,
+    pub excitation_coherence: f32,
+    pub max_harmonic: f32,
+    pub sustain_level: f32,
+    pub loudness_compensation: bool,
+    pub instrument_gains: Vec<f32>,
+    pub tremolo_rate: f32,
+    pub retrigger_same_note: bool,
+    pub body_modes: Vec<(f32, f32, f32)>,
+    pub reverb_width: f32,
+    pub pitch_drift: f32,
+    pub unison_voices: usize,
+    pub unison_detune: f32,
+    pub inharmonicity: f32,
+    pub tilt_eq: f32,
+    pub divisi: bool,
+    pub string_crossing: bool,
+    pub attack_scale: f32,
+    pub release_scale: f32,
+    pub noise_gate: f32,
+    pub excitation_variation: f32,
+    pub sustain_damping: f32
+}
+
+impl Preset {
+    /// Create a Preset with the default settings of a new Director.
+    pub fn new(instrument_type: InstrumentType, instrument_count: usize) -> Self {
+        Self {
+            instrument_type,
+            instrument_count,
+            articulation: Articulation::Arco,
+            polyphonic: false,
+            bend_quantize: false,
+            vibrato: 0.4,
+            vibrato_shape: VibratoShape::Cubed,
+            vibrato_phase_reset: false,
+            vibrato_gradient: 0.0,
+            bow_position: 0.5,
+            bow_noise: 0.5,
+            bow_alternation: 0.0,
+            release_rate: 0.5,
+            release_tail: 0,
+            harmonics: false,
+            stereo_width: 0.3,
+            pan_center: 0.0,
+            stereo_tails: false,
+            mono_compatible: false,
+            max_instrument_delay: 2000,
+            attack_jitter: 0.0,
+            attack_noise: 0.3,
+            entry_scoop_cents: 0.0,
+            entry_scoop_ms: 0,
+            tail_decay: 1.0,
+            auto_gain: false,
+            compressor_enabled: false,
+            compressor_threshold: -18.0,
+            compressor_ratio: 3.0,
+            impulse_response: None,
+            body_blend_type: instrument_type,
+            body_blend: 0.0,
+            air: 0.0,
+            phase_decorrelation: 0.0,
+            excitation_coherence: 0.0,
+            max_harmonic: 1.0,
+            sustain_level: 0.0,
+            loudness_compensation: false,
+            instrument_gains: vec![],
+            tremolo_rate: 0.0,
+            retrigger_same_note: false,
+            body_modes: vec![(500.0, 10.0, 0.0); 4],
+            reverb_width: 1.0,
+            pitch_drift: 0.0,
+            unison_voices: 1,
+            unison_detune: 0.0,
+            inharmonicity: 0.0,
+            tilt_eq: 0.0,
+            divisi: false,
+            string_crossing: false,
+            attack_scale: 1.0,
+            release_scale: 1.0,
+            noise_gate: 0.0,
+            excitation_variation: 1.0,
+            sustain_damping: 0.0
+        }
+    }
+
+    /// Create a Preset that captures the current settings of a Director.
+    pub fn from_state(state: &DirectorState) -> Self {
+        Self {
+            instrument_type: state.instrument_type,
+            instrument_count: state.instrument_count,
+            articulation: state.articulation,
+            polyphonic: state.polyphonic,
+            bend_quantize: state.bend_quantize,
+            vibrato: state.vibrato,
+            vibrato_shape: state.vibrato_shape,
+            vibrato_phase_reset: state.vibrato_phase_reset,
+            vibrato_gradient: state.vibrato_gradient,
+            bow_position: state.bow_position,
+            bow_noise: state.bow_noise,
+            bow_alternation: state.bow_alternation,
+            release_rate: state.release_rate,
+            release_tail: state.release_tail,
+            harmonics: state.harmonics,
+            stereo_width: state.stereo_width,
+            pan_center: state.pan_center,
+            stereo_tails: state.stereo_tails,
+            mono_compatible: state.mono_compatible,
+            max_instrument_delay: state.max_instrument_delay,
+            attack_jitter: state.attack_jitter,
+            attack_noise: state.attack_noise,
+            entry_scoop_cents: state.entry_scoop_cents,
+            entry_scoop_ms: state.entry_scoop_ms,
+            tail_decay: state.tail_decay,
+            auto_gain: state.auto_gain,
+            compressor_enabled: state.compressor_enabled,
+            compressor_threshold: state.compressor_threshold,
+            compressor_ratio: state.compressor_ratio,
+            impulse_response: state.impulse_response,
+            body_blend_type: state.body_blend_type,
+            body_blend: state.body_blend,
+            air: state.air,
+            phase_decorrelation: state.phase_decorrelation,
+            excitation_coherence: state.excitation_coherence,
+            max_harmonic: state.max_harmonic,
+            sustain_level: state.sustain_level,
+            loudness_compensation: state.loudness_compensation,
+            instrument_gains: state.instrument_gains.clone(),
+            tremolo_rate: state.tremolo_rate,
+            retrigger_same_note: state.retrigger_same_note,
+            body_modes: state.body_modes.clone(),
+            reverb_width: state.reverb_width,
+            pitch_drift: state.pitch_drift,
+            unison_voices: state.unison_voices,
+            unison_detune: state.unison_detune,
+            inharmonicity: state.inharmonicity,
+            tilt_eq: state.tilt_eq,
+            divisi: state.divisi,
+            string_crossing: state.string_crossing,
+            attack_scale: state.attack_scale,
+            release_scale: state.release_scale,
+            noise_gate: state.noise_gate,
+            excitation_variation: state.excitation_variation,
+            sustain_damping: state.sustain_damping
+        }
+    }
+
+    /// A single violin with expressive vibrato and a slight scoop into entries.
+    pub fn solo_violin() -> Self {
+        Self {
+            vibrato: 0.5,
+            vibrato_phase_reset: true,
+            attack_noise: 0.4,
+            entry_scoop_cents: 15.0,
+            entry_scoop_ms: 80,
+            release_tail: 300,
+            bow_alternation: 0.3,
+            air: 0.2,
+            ..Self::new(InstrumentType::Violin, 1)
+        }
+    }
+
+    /// A single cello with a warm, dark tone.
+    pub fn solo_cello() -> Self {
+        Self {
+            vibrato: 0.45,
+            bow_position: 0.4,
+            attack_noise: 0.35,
+            release_tail: 500,
+            bow_alternation: 0.3,
+            ..Self::new(InstrumentType::Cello, 1)
+        }
+    }
+
+    /// A large violin section with a wide stereo image and loosely synchronized players.
+    pub fn lush_section() -> Self {
+        Self {
+            vibrato: 0.35,
+            vibrato_gradient: 0.5,
+            stereo_width: 0.7,
+            stereo_tails: true,
+            reverb_width: 1.5,
+            pitch_drift: 0.3,
+            attack_jitter: 0.3,
+            attack_noise: 0.2,
+            phase_decorrelation: 0.5,
+            release_tail: 800,
+            loudness_compensation: true,
+            ..Self::new(InstrumentType::Violin, 12)
+        }
+    }
+
+    /// A bass section playing short, punchy notes.
+    pub fn bass_section() -> Self {
+        Self {
+            articulation: Articulation::Marcato,
+            vibrato: 0.25,
+            stereo_width: 0.4,
+            attack_jitter: 0.2,
+            attack_noise: 0.5,
+            compressor_enabled: true,
+            ..Self::new(InstrumentType::Bass, 4)
+        }
+    }
+
+    /// A string quartet voice that can play chords, with the output kept safe for mono playback.
+    pub fn polyphonic_ensemble() -> Self {
+        Self {
+            polyphonic: true,
+            mono_compatible: true,
+            loudness_compensation: true,
+            ..Self::new(InstrumentType::Viola, 4)
+        }
+    }
+
+    /// Get all the built-in Presets along with their names.
+    pub fn builtin() -> Vec<(&'static str, Preset)> {
+        vec![
+            ("Solo Violin", Preset::solo_violin()),
+            ("Solo Cello", Preset::solo_cello()),
+            ("Lush Section", Preset::lush_section()),
+            ("Bass Section", Preset::bass_section()),
+            ("Polyphonic Ensemble", Preset::polyphonic_ensemble())
+        ]
+    }
+
+    /// Get the Messages that apply this Preset.  Reinitialize comes first, since it determines
+    /// what the other settings apply to.
+    pub fn messages(&self) -> Vec<Message> {
+        let mut messages = vec![
+            Message::Reinitialize {instrument_type: self.instrument_type, instrument_count: self.instrument_count},
+            Message::SetArticulation {articulation: self.articulation},
+            Message::SetPolyphonic {polyphonic: self.polyphonic},
+            Message::SetBendQuantize {enabled: self.bend_quantize},
+            Message::SetVibrato {vibrato: self.vibrato},
+            Message::SetVibratoShape {shape: self.vibrato_shape},
+            Message::SetVibratoPhaseReset {enabled: self.vibrato_phase_reset},
+            Message::SetVibratoGradient {amount: self.vibrato_gradient},
+            Message::SetBowPosition {bow_position: self.bow_position},
+            Message::SetBowNoise {bow_noise: self.bow_noise},
+            Message::SetBowAlternation {amount: self.bow_alternation},
+            Message::SetReleaseRate {release: self.release_rate},
+            Message::SetReleaseTail {ms: self.release_tail},
+            Message::SetHarmonics {harmonics: self.harmonics},
+            Message::SetStereoWidth {width: self.stereo_width},
+            Message::SetPanCenter {position: self.pan_center},
+            Message::SetStereoTails {enabled: self.stereo_tails},
+            Message::SetMonoCompatible {enabled: self.mono_compatible},
+            Message::SetMaxInstrumentDelay {max_delay: self.max_instrument_delay},
+            Message::SetAttackJitter {jitter: self.attack_jitter},
+            Message::SetAttackNoise {amount: self.attack_noise},
+            Message::SetEntryScoop {cents: self.entry_scoop_cents, ms: self.entry_scoop_ms},
+            Message::SetTailDecay {rate: self.tail_decay},
+            Message::SetAutoGain {enabled: self.auto_gain},
+            Message::SetCompressor {enabled: self.compressor_enabled, threshold: self.compressor_threshold, ratio: self.compressor_ratio},
+            Message::SetImpulseResponse {which: self.impulse_response},
+            Message::SetBodyBlend {other_type: self.body_blend_type, amount: self.body_blend},
+            Message::SetAir {amount: self.air},
+            Message::SetPhaseDecorrelation {amount: self.phase_decorrelation},
+            Message::SetExcitationCoherence {coherence: self.excitation_coherence},
+            Message::SetMaxHarmonic {ratio: self.max_harmonic},
+            Message::SetSustainLevel {level: self.sustain_level},
+            Message::SetLoudnessCompensation {enabled: self.loudness_compensation},
+            Message::SetInstrumentGains {gains: self.instrument_gains.clone()},
+            Message::SetTremoloRate {hz: self.tremolo_rate},
+            Message::SetRetriggerSameNote {enabled: self.retrigger_same_note},
+            Message::SetReverbWidth {width: self.reverb_width},
+            Message::SetPitchDrift {amount: self.pitch_drift},
+            Message::SetUnisonVoices {count: self.unison_voices, detune: self.unison_detune},
+            Message::SetInharmonicity {amount: self.inharmonicity},
+            Message::SetTiltEq {tilt: self.tilt_eq},
+            Message::SetDivisi {enabled: self.divisi},
+            Message::SetStringCrossing {enabled: self.string_crossing},
+            Message::SetAttackScale {scale: self.attack_scale},
+            Message::SetReleaseScale {scale: self.release_scale},
+            Message::SetNoiseGate {amount: self.noise_gate},
+            Message::SetExcitationVariation {amount: self.excitation_variation},
+            Message::SetSustainDamping {amount: self.sustain_damping}
+        ];
+        for (index, &(freq, q, gain)) in self.body_modes.iter().enumerate() {
+            messages.push(Message::SetBodyMode {index, freq, q, gain});
+        }
+        messages
+    }
+}
@@ -13,10 +13,21 @@
 // You should have received a copy of the GNU Lesser General Public License along with Viola Ex Machina.
 // If not, see <https://www.gnu.org/licenses/>.
 
+#[cfg(all(feature = "getrandom", not(feature = "deterministic")))]
 use getrandom::getrandom;
+use std::sync::atomic::{AtomicU32, Ordering};
 
 const UNIFORM_SCALE: f32 = 1.0/(0x100000000i64 as f32);
 
+/// A snapshot of the position of a Random, which can be saved and later restored to continue
+/// the same sequence.  This includes the second value from the most recent pair of normally
+/// distributed values, if it has not been returned yet.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RandomState {
+    pub i: u32,
+    pub next_normal: Option<f32>
+}
+
 /// This is a quick and dirty random number generator.  It is based on the
 /// "even quicker generator" in Numerical Recipes.  Its statistical properties
 /// aren't great, but it's fine for our purposes, and it's very fast.
@@ -27,23 +38,39 @@ pub struct Random {
 }
 
 impl Random {
+    /// Create a generator with a seed that differs from every other generator created by this
+    /// process.  Normally the seed comes from the operating system.  If the crate is built with
+    /// the `deterministic` feature (or without the `getrandom` feature), it instead comes from a
+    /// hashed counter.  Generators still get distinct seeds so ensemble instruments remain
+    /// decorrelated, but every run of the program uses the same sequence of seeds, so identical
+    /// input produces identical output, and the seed a generator receives depends on the order
+    /// generators are created in.
     pub fn new() -> Self {
-        // Select a seed.
+        Random::with_seed(select_seed())
+    }
 
-        let mut data = [0u8; 4];
-        let mut seed = 0;
-        if let Ok(_) = getrandom(&mut data) {
-            for i in 0..4 {
-                seed += (data[i] as u32) << 8*i;
-            }
-        }
-        else {
-            // This should only happen in strange situations when something went wrong
-            // at the OS level.  Just use 0.
-        }
+    /// Create a generator with a specified seed.  Generators created with the same seed
+    /// produce identical sequences.
+    pub fn with_seed(seed: u32) -> Self {
         Self {i: seed, next_normal: 0.0, next_normal_valid: false}
     }
 
+    /// Get the current state of the generator.
+    pub fn state(&self) -> RandomState {
+        RandomState {
+            i: self.i,
+            next_normal: if self.next_normal_valid {Some(self.next_normal)} else {None}
+        }
+    }
+
+    /// Restore a state previously returned by state().  The generator will then produce the
+    /// same sequence it produced after the state was saved.
+    pub fn set_state(&mut self, state: RandomState) {
+        self.i = state.i;
+        self.next_normal = state.next_normal.unwrap_or(0.0);
+        self.next_normal_valid = state.next_normal.is_some();
+    }
+
     /// Get a random integer.
     pub fn get_int(&mut self) -> u32 {
         self.i = ((self.i as u64)*1664525u64 + 1013904223u64) as u32;
@@ -84,3 +111,36 @@ impl Random {
         samples[..count].to_vec()
     }
 }
+
+/// Select a seed from the operating system.
+#[cfg(all(feature = "getrandom", not(feature = "deterministic")))]
+fn select_seed() -> u32 {
+    let mut data = [0u8; 4];
+    if let Ok(_) = getrandom(&mut data) {
+        u32::from_le_bytes(data)
+    }
+    else {
+        // This should only happen in strange situations when something went wrong at the OS
+        // level.  Fall back to the counter so generators still differ from each other.
+        counter_seed()
+    }
+}
+
+/// Select a seed without involving the operating system.
+#[cfg(any(feature = "deterministic", not(feature = "getrandom")))]
+fn select_seed() -> u32 {
+    counter_seed()
+}
+
+/// Get the next seed in a fixed sequence.  Consecutive counter values are scrambled with the
+/// finalizer from MurmurHash3, so the seeds are spread across the full range of values.
+fn counter_seed() -> u32 {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    let mut x = COUNTER.fetch_add(1, Ordering::Relaxed).wrapping_mul(0x9e3779b9);
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x85ebca6b);
+    x ^= x >> 13;
+    x = x.wrapping_mul(0xc2b2ae35);
+    x ^= x >> 16;
+    x
+}
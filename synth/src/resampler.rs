@@ -14,9 +14,10 @@
 // If not, see <https://www.gnu.org/licenses/>.
 
 use crate::SAMPLE_RATE;
+use std::f32::consts::PI;
 
-/// Convert output from the synthesizer's native sample rate (48 kHz) to a different sample rate.
-/// The method used by this class is very fast and doesn't introduce latency, but the results may
+/// Convert output from the synthesizer's native sample rate (48 kHz) to a different sample rate,
+/// or input at a different sample rate to the native one.  The method used by this class is very fast and doesn't introduce latency, but the results may
 /// not always be the best.  When possible, it is preferable to output at the native sample rate.
 #[derive(Copy, Clone)]
 pub struct Resampler {
@@ -39,6 +40,15 @@ impl Resampler {
         }
     }
 
+    /// Create a Resampler that converts from a specified sample rate, measured in Hz, to the
+    /// synthesizer's native rate.
+    pub fn from_rate(sample_rate: f32) -> Self {
+        Self {
+            output_interval: sample_rate/SAMPLE_RATE as f32,
+            ..Resampler::new(SAMPLE_RATE as f32)
+        }
+    }
+
     /// Get whether there is output ready.
     pub fn has_output(&self) -> bool {
         self.next_output_time <= self.x2
@@ -64,4 +74,97 @@ impl Resampler {
         self.y2 = y;
         self.x2 += 1.0;
     }
-}
\ No newline at end of file
+}
+
+/// Half the width of the windowed sinc kernel used by SincResampler, measured in input samples.
+const SINC_HALF_WIDTH: usize = 16;
+
+/// Convert output from the synthesizer's native sample rate (48 kHz) to a different sample rate
+/// using a windowed sinc filter.  This produces much cleaner results than Resampler, but it is
+/// slower (each output sample requires a 32 tap convolution) and it introduces a latency of 16
+/// input samples.  It has the same interface as Resampler, so they can be used interchangeably.
+#[derive(Clone)]
+pub struct SincResampler {
+    output_interval: f32,
+    cutoff: f32,
+    history: Vec<f32>,
+    x2: f32,
+    next_output_time: f32
+}
+
+impl SincResampler {
+    /// Create a SincResampler that converts to a specified sample rate, measured in Hz.
+    pub fn new(sample_rate: f32) -> Self {
+        let output_interval = SAMPLE_RATE as f32/sample_rate;
+
+        // When downsampling, lower the cutoff to avoid aliasing.  Leave a little room for the
+        // transition band of the filter.
+
+        let cutoff = 0.95*f32::min(1.0, 1.0/output_interval);
+        Self {
+            output_interval: output_interval,
+            cutoff: cutoff,
+            history: vec![0.0; 2*SINC_HALF_WIDTH+2],
+            x2: 0.0,
+            next_output_time: 0.0
+        }
+    }
+
+    /// Get whether there is output ready.
+    pub fn has_output(&self) -> bool {
+        self.next_output_time <= self.x2-SINC_HALF_WIDTH as f32
+    }
+
+    /// Get the latency introduced by the filter, measured in output samples and rounded to the
+    /// nearest sample.
+    pub fn latency(&self) -> u32 {
+        (SINC_HALF_WIDTH as f32/self.output_interval).round() as u32
+    }
+
+    /// Get the next output sample.  This will panic if no output is ready.
+    pub fn get_output(&mut self) -> f32 {
+        assert!(self.has_output(), "No output ready");
+        let width = self.history.len();
+        let first_time = self.x2-(width-1) as f32;
+        let mut result = 0.0;
+        for i in 0..width {
+            let d = self.next_output_time-(first_time+i as f32);
+            if d.abs() < SINC_HALF_WIDTH as f32 {
+                let window = 0.5+0.5*(PI*d/SINC_HALF_WIDTH as f32).cos();
+                result += self.history[i]*self.cutoff*sinc(self.cutoff*d)*window;
+            }
+        }
+        self.next_output_time += self.output_interval;
+        while self.x2 > 1.0 && self.next_output_time > 1.0 {
+            self.x2 -= 1.0;
+            self.next_output_time -= 1.0;
+        }
+        result
+    }
+
+    /// Clear all stored input, returning to the state it was in when first created.
+    pub fn reset(&mut self) {
+        self.history.fill(0.0);
+        self.x2 = 0.0;
+        self.next_output_time = 0.0;
+    }
+
+    /// Add an input value.  This will panic if there is output waiting to be retreived.
+    pub fn add_input(&mut self, y: f32) {
+        assert!(!self.has_output(), "Cannot add input when output is ready");
+        let width = self.history.len();
+        self.history.copy_within(1..width, 0);
+        self.history[width-1] = y;
+        self.x2 += 1.0;
+    }
+}
+
+/// The normalized sinc function, sin(pi*x)/(pi*x).
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    }
+    else {
+        (PI*x).sin()/(PI*x)
+    }
+}
@@ -13,11 +13,16 @@
 // You should have received a copy of the GNU Lesser General Public License along with Viola Ex Machina.
 // If not, see <https://www.gnu.org/licenses/>.
 
+use crate::flush_denormal;
 use realfft::{RealFftPlanner, RealToComplex, ComplexToReal};
 use rustfft::num_complex::Complex;
 use std::sync::Arc;
 
+/// The default length of the initial part of the IR that is convolved directly.
+pub const DEFAULT_DIRECT_WIDTH: usize = 16;
+
 /// This is a convolutional reverb.
+#[derive(Clone)]
 pub struct Reverb {
     input_ring: Vec<f32>,
     output_ring: Vec<f32>,
@@ -30,8 +35,20 @@ pub struct Reverb {
 }
 
 impl Reverb {
-    /// Create a reverb to convolve an input signal with an IR in real time. 
+    /// Create a reverb to convolve an input signal with an IR in real time.  This uses the
+    /// default partitioning, in which the blocks keep doubling in size until they cover the
+    /// whole IR.
     pub fn new(ir: &Vec<f32>, fft_planner: &mut RealFftPlanner::<f32>) -> Self {
+        Reverb::with_partitions(ir, DEFAULT_DIRECT_WIDTH, usize::MAX, fft_planner)
+    }
+
+    /// Create a reverb with custom partitioning of the IR.  The first `direct_width` samples
+    /// (rounded up to a power of 2) are convolved directly.  The rest is divided into blocks that
+    /// are convolved with FFTs.  Each block is twice the size of the previous one, up to a maximum
+    /// of `max_block_width` (rounded down to a power of 2), after which all blocks are the same size.
+    /// Smaller blocks reduce the worst case CPU cost of a single sample, while larger ones reduce
+    /// the average cost.
+    pub fn with_partitions(ir: &Vec<f32>, direct_width: usize, max_block_width: usize, fft_planner: &mut RealFftPlanner::<f32>) -> Self {
         // Extend the IR length to the next power of 2.
 
         let mut width = 1;
@@ -47,20 +64,21 @@ impl Reverb {
         let mut input = vec![0.0; 2*width];
         let mut scratch = vec![];
 
-        // Build a series of blocks, each twice as large as the previous one, to convolve
-        // parts of the IR with the input.
+        // Build a series of blocks, each twice as large as the previous one until reaching
+        // the maximum size, to convolve parts of the IR with the input.
 
-        let direct_width = usize::min(width, 16);
+        let direct_width = usize::min(width, usize::max(direct_width, 1).next_power_of_two());
         let mut blocks = vec![];
         let mut block_width = 2*direct_width;
-        while block_width <= width {
-            // Create a block to convolve with the second half of (the remaining part of) the IR.
+        let mut offset = direct_width;
+        while offset < width {
+            // Create a block to convolve with the next part of the IR.
 
             let fft = fft_planner.plan_fft_forward(block_width);
             if scratch.len() < fft.get_scratch_len() {
                 scratch.resize(fft.get_scratch_len(), Complex::<f32>::new(0.0, 0.0));
             }
-            input[..block_width/2].copy_from_slice(&ir[block_width/2..block_width]);
+            input[..block_width/2].copy_from_slice(&ir[offset..offset+block_width/2]);
             for i in block_width/2..block_width {
                 input[i] = 0.0;
             }
@@ -69,8 +87,11 @@ impl Reverb {
                 Ok(_) => {}
                 Err(message) => {println!["{}", message]}
             }
-            blocks.push(Block::new(output, block_width, fft_planner));
-            block_width *= 2;
+            blocks.push(Block::new(output, block_width, offset, fft_planner));
+            offset += block_width/2;
+            if block_width <= max_block_width/2 {
+                block_width *= 2;
+            }
         }
         ir.truncate(direct_width);
 
@@ -86,9 +107,20 @@ impl Reverb {
         }
     }
 
+    /// Discard all input that has been processed so far, so the reverb is silent until it receives
+    /// more input.
+    pub fn reset(&mut self) {
+        self.input_ring.fill(0.0);
+        self.output_ring.fill(0.0);
+    }
+
     /// Compute the convolution.  This function takes the next input samples and returns
     /// the next output sample.
     pub fn process(&mut self, input: f32) -> f32 {
+        // Long tails decay toward zero.  Flush tiny values so they never become denormal.
+
+        let input = flush_denormal(input);
+
         // For the initial part of the IR, it's faster to convolve directly instead
         // of using FFTs.
 
@@ -128,15 +160,16 @@ impl Reverb {
                     Err(message) => {println!["{}", message]}
                 }
                 let scale = 1.0/block.width as f32;
+                let start = self.position+1+block.offset-half_width;
                 for i in 0..block.width {
-                    self.output_ring[(self.position+1+i)&mask] += scale*self.real_temp[i];
+                    self.output_ring[(start+i)&mask] += scale*self.real_temp[i];
                 }
             }
         }
 
         // Return the result.
 
-        let result = self.output_ring[self.position];
+        let result = flush_denormal(self.output_ring[self.position]);
         self.output_ring[self.position] = 0.0;
         self.position = (self.position+1)&mask;
         result
@@ -144,18 +177,21 @@ impl Reverb {
 }
 
 /// This contains data for convolving the input with a block of the IR.
+#[derive(Clone)]
 struct Block {
     ir: Vec<Complex<f32>>,
     width: usize,
+    offset: usize,
     fft_forward: Arc<dyn RealToComplex<f32>>,
     fft_inverse: Arc<dyn ComplexToReal<f32>>
 }
 
 impl Block {
-    fn new(ir: Vec<Complex<f32>>, width: usize, fft_planner: &mut RealFftPlanner::<f32>) -> Self {
+    fn new(ir: Vec<Complex<f32>>, width: usize, offset: usize, fft_planner: &mut RealFftPlanner::<f32>) -> Self {
         Self {
             ir: ir,
             width: width,
+            offset: offset,
             fft_forward: fft_planner.plan_fft_forward(width),
             fft_inverse: fft_planner.plan_fft_inverse(width)
         }
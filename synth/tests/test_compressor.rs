@@ -0,0 +1,62 @@
+// Copyright 2025 by Peter Eastman
+//
+// This file is part of Viola Ex Machina.
+//
+// Viola Ex Machina is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Lesser General Public License as published by the Free Software Foundation, either
+// version 2.1 of the License, or (at your option) any later version.
+//
+// Viola Ex Machina is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License along with Viola Ex Machina.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use synth::compressor::Compressor;
+use synth::SAMPLE_RATE;
+use std::f32::consts::PI;
+
+/// Process a sine wave of a given amplitude and return the output amplitude after the
+/// compressor has settled.
+fn compute_output_amplitude(compressor: &mut Compressor, amplitude: f32) -> f32 {
+    let f = 2.0*PI*440.0/SAMPLE_RATE as f32;
+    let mut max_amplitude = 0.0;
+    for i in 0..SAMPLE_RATE {
+        let x = amplitude*(f*i as f32).sin();
+        let (left, right) = compressor.process(x, x);
+        assert_eq!(left, right);
+        if i > SAMPLE_RATE/2 {
+            max_amplitude = f32::max(max_amplitude, left.abs());
+        }
+    }
+    max_amplitude
+}
+
+#[test]
+fn test_below_threshold() {
+    // Signals below the threshold should be unchanged.
+
+    let mut compressor = Compressor::new(-12.0, 4.0);
+    let amplitude = compute_output_amplitude(&mut compressor, 0.1);
+    assert!((amplitude-0.1).abs() < 1e-4);
+}
+
+#[test]
+fn test_above_threshold() {
+    // A signal 12 dB above the threshold with a 4:1 ratio should come out roughly 3 dB above it.
+    // The detector follows the rectified sine, so the level it sees is somewhat below the peak.
+
+    let mut compressor = Compressor::new(-24.0, 4.0);
+    let threshold = 10.0f32.powf(-24.0/20.0);
+    let input = 10.0f32.powf(-12.0/20.0);
+    let output = compute_output_amplitude(&mut compressor, input);
+    let output_db = 20.0*(output/threshold).log10();
+    assert!(output_db > 2.0 && output_db < 6.0);
+
+    // A higher ratio should reduce it more.
+
+    compressor.set_ratio(10.0);
+    let output2 = compute_output_amplitude(&mut compressor, input);
+    assert!(output2 < output);
+}
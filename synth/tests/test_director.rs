@@ -0,0 +1,2031 @@
+// Copyright 2026 by Peter Eastman
+//
+// This file is part of Viola Ex Machina.
+//
+// Viola Ex Machina is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Lesser General Public License as published by the Free Software Foundation, either
+// version 2.1 of the License, or (at your option) any later version.
+//
+// Viola Ex Machina is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License along with Viola Ex Machina.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use synth::director::{Director, Message, asset_decode_failed, load_body_ir, load_bow_noise, load_ir};
+use synth::{InstrumentType, Articulation, BundledIR, SynthError, VibratoShape, SAMPLE_RATE};
+use synth::random::Random;
+use realfft::RealFftPlanner;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::f32::consts::PI;
+use std::sync::mpsc;
+
+/// An allocator that counts the allocations made by each thread, so tests can check that an
+/// operation does not allocate.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const {Cell::new(0)};
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get()+1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Get the number of allocations the current thread has made.
+fn allocation_count() -> usize {
+    ALLOCATIONS.with(|count| count.get())
+}
+
+/// Play a note, release it, and return the number of steps until the envelope reaches zero.
+/// Also check that the output eventually becomes silent.
+fn measure_release_time(tail_ms: i64, velocity: f32) -> usize {
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Cello, 1, receiver, 0);
+    let _ = sender.send(Message::SetReleaseTail {ms: tail_ms});
+    let _ = sender.send(Message::NoteOn {note_index: 48, velocity: 0.8});
+    for _ in 0..20000 {
+        director.generate();
+    }
+    let _ = sender.send(Message::NoteOff {note_index: 48, velocity: velocity});
+    let mut release_time = None;
+    let mut last_sound = 0;
+    for i in 0..200000 {
+        let (left, right) = director.generate();
+        if release_time.is_none() && director.get_envelope(0, 0) == 0.0 {
+            release_time = Some(i);
+        }
+        if left != 0.0 || right != 0.0 {
+            last_sound = i;
+        }
+    }
+    assert!(last_sound < 190000);
+    release_time.unwrap()
+}
+
+#[test]
+fn test_release_tail() {
+    // A release tail should make the sound last longer, but it still must eventually stop.
+
+    let without_tail = measure_release_time(0, 0.0);
+    let with_tail = measure_release_time(1000, 0.0);
+    assert!(with_tail > without_tail+40000);
+}
+
+#[test]
+fn test_release_velocity() {
+    // A high release velocity should make the release faster.  Standard values should not change it.
+
+    let slow = measure_release_time(0, 0.5);
+    let default = measure_release_time(0, 0.0);
+    let fast = measure_release_time(0, 1.0);
+    assert!(fast < slow/2);
+    assert_eq!(slow, default);
+}
+
+#[test]
+fn test_get_state() {
+    // The state should reflect the defaults, then any Messages that have been processed.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new(InstrumentType::Viola, 2, receiver);
+    let state = director.get_state();
+    assert_eq!(InstrumentType::Viola, state.instrument_type);
+    assert_eq!(2, state.instrument_count);
+    assert_eq!(Articulation::Arco, state.articulation);
+    assert_eq!(0.0, state.pitch_bend);
+    assert_eq!(VibratoShape::Cubed, state.vibrato_shape);
+    assert!(!state.mute);
+    let _ = sender.send(Message::SetArticulation {articulation: Articulation::Pizzicato});
+    let _ = sender.send(Message::SetMute {mute: true});
+    let _ = sender.send(Message::SetPitchBend {semitones: -1.5});
+    let _ = sender.send(Message::SetReleaseTail {ms: 500});
+    let _ = sender.send(Message::SetVibratoShape {shape: VibratoShape::Sine});
+    director.generate();
+    let state = director.get_state();
+    assert_eq!(Articulation::Pizzicato, state.articulation);
+    assert!(state.mute);
+    assert!((state.pitch_bend+1.5).abs() < 1e-5);
+    assert_eq!(500, state.release_tail);
+    assert_eq!(VibratoShape::Sine, state.vibrato_shape);
+}
+
+#[test]
+fn test_bend_quantize() {
+    // A quantized bend should be rounded to the nearest semitone and reached smoothly.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new(InstrumentType::Violin, 1, receiver);
+    let _ = sender.send(Message::SetBendQuantize {enabled: true});
+    let _ = sender.send(Message::SetPitchBend {semitones: 0.7});
+    director.generate();
+    let target = f32::powf(2.0, 1.0/12.0);
+    assert!(director.bend > 1.0 && director.bend < target);
+    for _ in 0..10000 {
+        director.generate();
+    }
+    assert_eq!(target, director.bend);
+
+    // Without quantization, the bend is applied exactly and immediately.
+
+    let _ = sender.send(Message::SetBendQuantize {enabled: false});
+    for _ in 0..100 {
+        director.generate();
+    }
+    assert!((director.bend-f32::powf(2.0, 0.7/12.0)).abs() < 1e-6);
+}
+
+/// Play a sustained note with the specified number of instruments and settings, and return the
+/// RMS amplitude of the output once it has settled.
+fn measure_rms(instrument_count: usize, settings: &[Message]) -> f32 {
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Cello, instrument_count, receiver, 0);
+    for message in settings {
+        let _ = sender.send(message.clone());
+    }
+    let _ = sender.send(Message::NoteOn {note_index: 48, velocity: 1.0});
+    let mut sum = 0.0;
+    for i in 0..96000 {
+        let (left, right) = director.generate();
+        if i >= 48000 {
+            sum += left*left + right*right;
+        }
+    }
+    (sum/96000.0).sqrt()
+}
+
+#[test]
+fn test_auto_gain() {
+    // With automatic gain enabled, the level should be nearly independent of the number of instruments.
+
+    let rms1 = measure_rms(1, &[Message::SetAutoGain {enabled: true}]);
+    let rms4 = measure_rms(4, &[Message::SetAutoGain {enabled: true}]);
+    assert!(rms4/rms1 > 0.8 && rms4/rms1 < 1.25);
+}
+
+#[test]
+fn test_idle_and_wake() {
+    // A Director that has not played anything should produce exact silence.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new(InstrumentType::Violin, 2, receiver);
+    for _ in 0..5000 {
+        assert_eq!((0.0, 0.0), director.generate());
+    }
+
+    // Once a note starts, it should become audible within the attack.
+
+    let _ = sender.send(Message::NoteOn {note_index: 67, velocity: 1.0});
+    let mut first_sound = None;
+    for i in 0..10000 {
+        let (left, right) = director.generate();
+        if first_sound.is_none() && (left.abs() > 1e-3 || right.abs() > 1e-3) {
+            first_sound = Some(i);
+        }
+    }
+    assert!(first_sound.is_some());
+    assert!(first_sound.unwrap() < 5000);
+}
+
+/// Play a sustained note on a single cello with the specified phase decorrelation, and return
+/// the left channel of the output.
+fn render_decorrelated(amount: f32) -> Vec<f32> {
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Cello, 1, receiver, 0);
+    let _ = sender.send(Message::SetPhaseDecorrelation {amount: amount});
+    let _ = sender.send(Message::NoteOn {note_index: 48, velocity: 1.0});
+    (0..96000).map(|_| director.generate().0).collect()
+}
+
+#[test]
+fn test_phase_decorrelation() {
+    // Decorrelation only shifts the phases of the frequency components, so it should change the
+    // waveform substantially while leaving the level nearly unchanged.
+
+    let correlated = render_decorrelated(0.0);
+    let decorrelated = render_decorrelated(1.0);
+    let power = |x: &[f32]| x.iter().map(|v| v*v).sum::<f32>();
+    let diff: Vec<f32> = correlated.iter().zip(&decorrelated).map(|(a, b)| a-b).collect();
+    assert!(power(&diff[48000..]) > 0.2*power(&correlated[48000..]));
+    let ratio = power(&decorrelated[48000..])/power(&correlated[48000..]);
+    assert!(ratio > 0.8 && ratio < 1.25);
+
+    // Decorrelating the instruments should not greatly change the level of the mono sum.
+
+    let settings = [Message::SetStereoWidth {width: 0.0}];
+    let correlated = measure_rms(4, &settings);
+    let settings = [Message::SetStereoWidth {width: 0.0}, Message::SetPhaseDecorrelation {amount: 1.0}];
+    let decorrelated = measure_rms(4, &settings);
+    assert!(decorrelated/correlated > 0.5 && decorrelated/correlated < 1.5);
+}
+
+/// Play a note with the ensemble spread across the full stereo field, and return the power of the
+/// stereo output and of the mono sum.
+fn measure_mono_fold_down(mono_compatible: bool) -> (f32, f32) {
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Viola, 4, receiver, 0);
+    let _ = sender.send(Message::SetMonoCompatible {enabled: mono_compatible});
+    let _ = sender.send(Message::SetStereoWidth {width: 1.0});
+    let _ = sender.send(Message::NoteOn {note_index: 60, velocity: 1.0});
+    let mut stereo = 0.0;
+    let mut mono = 0.0;
+    for i in 0..96000 {
+        let (left, right) = director.generate();
+        if i >= 24000 {
+            stereo += 0.5*(left*left + right*right);
+            mono += 0.25*(left+right)*(left+right);
+        }
+    }
+    (stereo, mono)
+}
+
+#[test]
+fn test_mono_compatible() {
+    // In mono compatible mode, summing the channels to mono should not lose much energy
+    // compared to the stereo signal.  Without it, more is lost.
+
+    let (stereo, mono) = measure_mono_fold_down(true);
+    let db = 10.0*(mono/stereo).log10();
+    assert!(db > -1.0);
+    let (normal_stereo, normal_mono) = measure_mono_fold_down(false);
+    let normal_db = 10.0*(normal_mono/normal_stereo).log10();
+    assert!(normal_db < -1.0);
+
+    // The level of the stereo output should not change.
+
+    let change = 10.0*(stereo/normal_stereo).log10();
+    assert!(change.abs() < 0.5);
+}
+
+#[test]
+fn test_volume_smoothing() {
+    // Changes to the volume should happen gradually rather than in steps.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new(InstrumentType::Cello, 1, receiver);
+    let _ = sender.send(Message::NoteOn {note_index: 48, velocity: 1.0});
+    for _ in 0..10000 {
+        director.generate();
+    }
+    let mut max_change: f32 = 0.0;
+    for i in 0..4800 {
+        if i%200 == 0 {
+            let _ = sender.send(Message::SetVolume {volume: 1.0 - i as f32/4600.0});
+        }
+        let previous = director.volume;
+        director.generate();
+        max_change = max_change.max((director.volume-previous).abs());
+    }
+    assert!(max_change < 0.002);
+    for _ in 0..4800 {
+        director.generate();
+    }
+    assert!(director.volume.abs() < 0.01);
+}
+
+#[test]
+fn test_seeded() {
+    // Two Directors with the same seed should produce identical output.  One with a different
+    // seed should not.
+
+    let mut outputs = vec![];
+    for seed in [5, 5, 6] {
+        let (sender, receiver) = mpsc::channel();
+        let mut director = Director::new_seeded(InstrumentType::Viola, 3, receiver, seed);
+        let _ = sender.send(Message::SetAttackJitter {jitter: 0.5});
+        let _ = sender.send(Message::NoteOn {note_index: 55, velocity: 0.8});
+        let mut output = vec![];
+        for _ in 0..20000 {
+            output.push(director.generate());
+        }
+        outputs.push(output);
+    }
+    assert!(outputs[0] == outputs[1]);
+    assert!(outputs[0] != outputs[2]);
+}
+
+/// Play a note on a seeded ensemble with the specified attack jitter, and return the output.
+fn render_attack_jitter(jitter: f32) -> Vec<(f32, f32)> {
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Viola, 3, receiver, 0);
+    let _ = sender.send(Message::SetAttackJitter {jitter: jitter});
+    let _ = sender.send(Message::NoteOn {note_index: 55, velocity: 0.8});
+    (0..20000).map(|_| director.generate()).collect()
+}
+
+#[test]
+fn test_attack_jitter_range() {
+    // Out of range values should be clamped, so every instrument still plays a normal attack.
+
+    let full = render_attack_jitter(1.0);
+    let none = render_attack_jitter(0.0);
+    assert!(full.iter().all(|(left, right)| left.is_finite() && right.is_finite()));
+    assert!(full != none);
+    assert!(render_attack_jitter(3.0) == full);
+    assert!(render_attack_jitter(-1.0) == none);
+    assert!(render_attack_jitter(f32::NAN) == none);
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new(InstrumentType::Viola, 3, receiver);
+    let _ = sender.send(Message::SetAttackJitter {jitter: 2.5});
+    director.generate();
+    assert_eq!(1.0, director.get_state().attack_jitter);
+}
+
+#[test]
+fn test_note_errors() {
+    // Notes that can't be played should be reported by take_last_error(), which clears the error.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new(InstrumentType::Violin, 1, receiver);
+    assert_eq!(None, director.take_last_error());
+    let _ = sender.send(Message::NoteOn {note_index: 20, velocity: 0.8});
+    director.advance(100);
+    assert_eq!(Some(SynthError::NoteOutOfRange {note_index: 20}), director.take_last_error());
+    assert_eq!(None, director.take_last_error());
+
+    // In polyphonic mode, a fifth note can't be played while four are held.
+
+    let _ = sender.send(Message::SetPolyphonic {polyphonic: true});
+    for note_index in 60..65 {
+        let _ = sender.send(Message::NoteOn {note_index, velocity: 0.8});
+    }
+    director.advance(100);
+    assert_eq!(Some(SynthError::NoVoiceAvailable), director.take_last_error());
+}
+
+#[test]
+fn test_diagnostics() {
+    // Changing notes should leave the tail of the previous one decaying.  Once everything has
+    // been released, there should be no active notes or tails.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new(InstrumentType::Cello, 2, receiver);
+    assert!(!director.is_note_active());
+    assert_eq!(0, director.active_tail_count());
+    let _ = sender.send(Message::NoteOn {note_index: 48, velocity: 0.8});
+    for _ in 0..10000 {
+        director.generate();
+    }
+    assert!(director.is_note_active());
+    let _ = sender.send(Message::NoteOn {note_index: 50, velocity: 0.8});
+    let _ = sender.send(Message::NoteOff {note_index: 48, velocity: 0.0});
+    for _ in 0..2000 {
+        director.generate();
+    }
+    assert!(director.is_note_active());
+    assert!(director.active_tail_count() > 0);
+    let _ = sender.send(Message::NoteOff {note_index: 50, velocity: 0.0});
+    for _ in 0..200000 {
+        director.generate();
+    }
+    assert!(!director.is_note_active());
+    assert_eq!(0, director.active_tail_count());
+}
+
+#[test]
+fn test_extreme_bend() {
+    // Extreme pitch bends should not cause a panic or produce invalid output.
+
+    for semitones in [-1000.0, -200.0, 200.0, 1000.0] {
+        let (sender, receiver) = mpsc::channel();
+        let mut director = Director::new(InstrumentType::Violin, 1, receiver);
+        let _ = sender.send(Message::SetPitchBend {semitones: semitones});
+        let _ = sender.send(Message::NoteOn {note_index: 60, velocity: 0.8});
+        for _ in 0..20000 {
+            let (left, right) = director.generate();
+            assert!(left.is_finite() && right.is_finite());
+        }
+    }
+}
+
+#[test]
+fn test_instrument_gains() {
+    // With full stereo width, the first instrument is panned hard left and the second hard right.
+    // Setting the gain of the second one to zero should silence the right channel.  Extra gains
+    // beyond the number of instruments are ignored.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new(InstrumentType::Cello, 2, receiver);
+    let _ = sender.send(Message::SetStereoWidth {width: 1.0});
+    let _ = sender.send(Message::SetInstrumentGains {gains: vec![1.0, 0.0, 0.5]});
+    let _ = sender.send(Message::NoteOn {note_index: 48, velocity: 0.8});
+    let mut left_energy = 0.0;
+    let mut right_energy = 0.0;
+    for _ in 0..48000 {
+        let (left, right) = director.generate();
+        left_energy += left*left;
+        right_energy += right*right;
+    }
+    assert!(left_energy > 0.0);
+    assert!(right_energy < 1e-6*left_energy);
+    assert_eq!(vec![1.0, 0.0, 0.5], director.get_state().instrument_gains);
+}
+
+#[test]
+fn test_pan_center() {
+    // A single instrument centered at the far left should be silent in the right channel.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Cello, 1, receiver, 0);
+    let _ = sender.send(Message::SetPanCenter {position: -1.0});
+    let _ = sender.send(Message::NoteOn {note_index: 48, velocity: 0.8});
+    let mut left_energy = 0.0;
+    let mut right_energy = 0.0;
+    for _ in 0..48000 {
+        let (left, right) = director.generate();
+        left_energy += left*left;
+        right_energy += right*right;
+    }
+    assert!(left_energy > 0.0);
+    assert!(right_energy < 1e-6*left_energy);
+
+    // With a wide ensemble, the center is limited so the spread stays in range.  The leftmost
+    // instrument ends up in the middle, so the right channel should be louder.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Cello, 2, receiver, 0);
+    let _ = sender.send(Message::SetStereoWidth {width: 0.5});
+    let _ = sender.send(Message::SetPanCenter {position: 2.0});
+    let _ = sender.send(Message::NoteOn {note_index: 48, velocity: 0.8});
+    let mut left_energy = 0.0;
+    let mut right_energy = 0.0;
+    for _ in 0..48000 {
+        let (left, right) = director.generate();
+        left_energy += left*left;
+        right_energy += right*right;
+    }
+    assert!(left_energy > 0.0);
+    assert!(right_energy > 2.0*left_energy);
+    assert_eq!(1.0, director.get_state().pan_center);
+}
+
+/// Generate the specified number of samples and return the largest change between consecutive
+/// samples in either channel.  last holds the previous sample, and is updated.
+fn max_step(director: &mut Director, last: &mut (f32, f32), samples: usize) -> f32 {
+    let mut max_change: f32 = 0.0;
+    for _ in 0..samples {
+        let output = director.generate();
+        max_change = max_change.max((output.0-last.0).abs()).max((output.1-last.1).abs());
+        *last = output;
+    }
+    max_change
+}
+
+#[test]
+fn test_switch_type() {
+    // Changing only the instrument type should keep the current note playing.  The output should
+    // change no more abruptly during the switch than it does while either type is playing normally.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Viola, 2, receiver, 0);
+    let _ = sender.send(Message::SetLoudnessCompensation {enabled: true});
+    let _ = sender.send(Message::NoteOn {note_index: 60, velocity: 0.8});
+    for _ in 0..15000 {
+        director.generate();
+    }
+    let mut last = director.generate();
+    let before = max_step(&mut director, &mut last, 5000);
+    let envelope = director.get_envelope(0, 0);
+    assert!(envelope > 0.0);
+    let _ = sender.send(Message::Reinitialize {instrument_type: InstrumentType::Violin, instrument_count: 2});
+    let during = max_step(&mut director, &mut last, 5000);
+    assert_eq!(InstrumentType::Violin, director.get_state().instrument_type);
+    assert!(director.is_note_active());
+    assert_eq!(envelope, director.get_envelope(0, 0));
+    max_step(&mut director, &mut last, 5000);
+    let after = max_step(&mut director, &mut last, 5000);
+    assert!(during < 1.5*before.max(after));
+
+    // Changing the instrument count requires reinitializing everything, which stops the note.
+
+    let _ = sender.send(Message::Reinitialize {instrument_type: InstrumentType::Violin, instrument_count: 3});
+    for _ in 0..200 {
+        director.generate();
+    }
+    assert!(!director.is_note_active());
+}
+
+#[test]
+fn test_fft_plans_after_switch() {
+    // The FFT sizes for every instrument type should be planned when the Director is created, so
+    // a note played after switching types does not need to plan new ones on the audio thread.
+    // Looking up a size that has already been planned does not allocate.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new(InstrumentType::Cello, 1, receiver);
+    let _ = sender.send(Message::NoteOn {note_index: 48, velocity: 0.8});
+    for _ in 0..1000 {
+        director.generate();
+    }
+    let _ = sender.send(Message::Reinitialize {instrument_type: InstrumentType::Bass, instrument_count: 1});
+    director.generate();
+    assert_eq!(InstrumentType::Bass, director.get_state().instrument_type);
+    assert!(director.is_note_active());
+    let frequency = 440.0*f32::powf(2.0, (28-69) as f32/12.0);
+    let min_size = (SAMPLE_RATE as f32/(1.02*frequency)).floor() as usize;
+    let max_size = (1.02*SAMPLE_RATE as f32/frequency).ceil() as usize;
+    let mut fft_planner = director.fft_planner.borrow_mut();
+    let allocations = allocation_count();
+    for size in min_size..=max_size {
+        fft_planner.plan_fft_inverse(size);
+    }
+    assert_eq!(allocations, allocation_count());
+}
+
+#[test]
+fn test_generate_with_events() {
+    // Rendering a timeline with a seeded Director should be reproducible, and the order in which
+    // events are listed should not matter.
+
+    let events = vec![
+        (0, Message::NoteOn {note_index: 50, velocity: 0.8}),
+        (10000, Message::SetVibrato {vibrato: 1.0}),
+        (20000, Message::SetPitchBend {semitones: 1.0}),
+        (30000, Message::NoteOff {note_index: 50, velocity: 0.0})
+    ];
+    let mut shuffled = events.clone();
+    shuffled.reverse();
+    let mut outputs = vec![];
+    for e in [&events, &events, &shuffled] {
+        let (_sender, receiver) = mpsc::channel();
+        let mut director = Director::new_seeded(InstrumentType::Cello, 2, receiver, 10);
+        outputs.push(director.generate_with_events(e, 150000));
+        assert_eq!(1.0, director.get_state().pitch_bend);
+        assert!(!director.is_note_active());
+    }
+    assert!(outputs[0] == outputs[1]);
+    assert!(outputs[0] == outputs[2]);
+
+    // The note should start immediately and end after the release.
+
+    let (left, right) = &outputs[0];
+    assert_eq!(150000, left.len());
+    assert!(left[..30000].iter().any(|&x| x != 0.0));
+    assert!(left[140000..].iter().all(|&x| x == 0.0));
+    assert!(right[140000..].iter().all(|&x| x == 0.0));
+}
+
+/// Play a note with a seeded Director and return the mean square amplitude of the output.
+fn measure_power_at_volume(volume: f32) -> f32 {
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Viola, 1, receiver, 3);
+    let _ = sender.send(Message::SetVolume {volume: volume});
+    let _ = sender.send(Message::NoteOn {note_index: 60, velocity: 0.8});
+    let mut power = 0.0;
+    for i in 0..72000 {
+        let (left, right) = director.generate();
+        if i >= 24000 {
+            power += left*left + right*right;
+        }
+    }
+    power/48000.0
+}
+
+#[test]
+fn test_message_pipeline() {
+    // Messages sent through the channel are processed every 100 steps.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new(InstrumentType::Viola, 1, receiver);
+    for _ in 0..150 {
+        director.generate();
+    }
+    let _ = sender.send(Message::SetVolume {volume: 0.5});
+    for _ in 0..50 {
+        director.generate();
+    }
+    assert_eq!(1.0, director.get_state().volume);
+    director.generate();
+    assert_eq!(0.5, director.get_state().volume);
+
+    // The output level should respond to the volume.
+
+    let loud = measure_power_at_volume(1.0);
+    let quiet = measure_power_at_volume(0.3);
+    assert!(loud > 0.0);
+    assert!(quiet < 0.5*loud);
+}
+
+#[test]
+fn test_bow_alternation() {
+    // The first note is a down-bow, so it should be unaffected by bow alternation.  The second note
+    // is an up-bow, which should be quieter.
+
+    let events = vec![
+        (0, Message::NoteOn {note_index: 48, velocity: 0.8}),
+        (30000, Message::NoteOff {note_index: 48, velocity: 0.0}),
+        (40000, Message::NoteOn {note_index: 48, velocity: 0.8}),
+        (70000, Message::NoteOff {note_index: 48, velocity: 0.0})
+    ];
+    let mut outputs = vec![];
+    for amount in [0.0, 1.0] {
+        let (sender, receiver) = mpsc::channel();
+        let mut director = Director::new_seeded(InstrumentType::Cello, 1, receiver, 7);
+        let _ = sender.send(Message::SetBowAlternation {amount: amount});
+        outputs.push(director.generate_with_events(&events, 70000).0);
+    }
+    assert!(outputs[0][..40000] == outputs[1][..40000]);
+    let plain: f32 = outputs[0][50000..].iter().map(|x| x*x).sum();
+    let alternated: f32 = outputs[1][50000..].iter().map(|x| x*x).sum();
+    assert!(alternated < plain);
+}
+
+#[test]
+fn test_rumble_filter() {
+    // When the bass plays its lowest open string (E1, 41 Hz), the output should contain very
+    // little energy below 20 Hz.  Without the filter, there is about three times more than
+    // this allows.
+
+    let (_sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Bass, 1, receiver, 3);
+    let events = vec![(0, Message::NoteOn {note_index: 28, velocity: 0.8})];
+    let (left, _) = director.generate_with_events(&events, 24000+65536);
+    let mut windowed: Vec<f32> = left[24000..].iter().enumerate().map(|(i, x)| x*(1.0-(2.0*PI*i as f32/65536.0).cos())).collect();
+    let mut fft_planner = RealFftPlanner::<f32>::new();
+    let fft = fft_planner.plan_fft_forward(windowed.len());
+    let mut spectrum = fft.make_output_vec();
+    let _ = fft.process(&mut windowed, &mut spectrum);
+    let mut low = 0.0;
+    let mut total = 0.0;
+    for (i, x) in spectrum.iter().enumerate() {
+        let freq = i as f32*SAMPLE_RATE as f32/65536.0;
+        if freq < 20.0 {
+            low += x.norm_sqr();
+        }
+        total += x.norm_sqr();
+    }
+    assert!(low < 3e-4*total);
+}
+
+/// Play a tremolo note at the specified rate, and return the measured number of bow strokes per
+/// second.
+fn measure_tremolo_rate(hz: f32) -> f32 {
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Violin, 1, receiver, 0);
+    let _ = sender.send(Message::SetArticulation {articulation: Articulation::Tremolo});
+    let _ = sender.send(Message::SetTremoloRate {hz: hz});
+    let _ = sender.send(Message::NoteOn {note_index: 67, velocity: 0.8});
+    for _ in 0..SAMPLE_RATE {
+        director.generate();
+    }
+    let seconds = 10;
+    let mut strokes = 0;
+    let mut bowing = director.get_instrument_volume(0, 0) > 0.0;
+    for _ in 0..seconds*SAMPLE_RATE {
+        director.generate();
+        let volume = director.get_instrument_volume(0, 0);
+        if volume > 0.0 && !bowing {
+            strokes += 1;
+        }
+        bowing = volume > 0.0;
+    }
+    strokes as f32/seconds as f32
+}
+
+#[test]
+fn test_tremolo_rate() {
+    // The bow strokes should occur at the selected rate, across the whole range.
+
+    for hz in [4.0, 12.0, 30.0] {
+        let rate = measure_tremolo_rate(hz);
+        assert!((rate-hz).abs() < 0.05*hz, "requested {} Hz, measured {} Hz", hz, rate);
+    }
+
+    // The rate is stored, and a rate of 0 restores the default strokes.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new(InstrumentType::Violin, 1, receiver);
+    let _ = sender.send(Message::SetTremoloRate {hz: 12.0});
+    director.generate();
+    assert_eq!(12.0, director.get_state().tremolo_rate);
+    let default_rate = measure_tremolo_rate(0.0);
+    assert!(default_rate > 5.0 && default_rate < 12.0);
+}
+
+#[test]
+fn test_delayed_envelope() {
+    // With two instruments, the first one is delayed by the full max_instrument_delay.  If the note
+    // ends before it even starts, it should still play the attack, then release.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Viola, 2, receiver, 0);
+    let _ = sender.send(Message::SetMaxInstrumentDelay {max_delay: 20000});
+    let _ = sender.send(Message::NoteOn {note_index: 60, velocity: 1.0});
+    for _ in 0..10000 {
+        director.generate();
+    }
+    assert!(director.get_envelope(0, 1) > 0.9);
+    assert_eq!(0.0, director.get_envelope(0, 0));
+    let _ = sender.send(Message::NoteOff {note_index: 60, velocity: 0.0});
+    let mut max_envelope: f32 = 0.0;
+    for _ in 0..20000 {
+        director.generate();
+        max_envelope = max_envelope.max(director.get_envelope(0, 0));
+    }
+    assert!(max_envelope > 0.9);
+    for _ in 0..100000 {
+        director.generate();
+    }
+    assert_eq!(0.0, director.get_envelope(0, 0));
+    assert_eq!(0.0, director.get_envelope(0, 1));
+}
+
+#[test]
+fn test_change_delay_during_attack() {
+    // Changing the time spread in the middle of a slow attack should not make any instrument's
+    // envelope stall or move backward.  Each one should still rise steadily to the full level.
+
+    for (initial_delay, new_delay) in [(2000, 20000), (20000, 0)] {
+        let (sender, receiver) = mpsc::channel();
+        let mut director = Director::new_seeded(InstrumentType::Violin, 4, receiver, 0);
+        let _ = sender.send(Message::SetMaxInstrumentDelay {max_delay: initial_delay});
+        let _ = sender.send(Message::NoteOn {note_index: 60, velocity: 0.3});
+        for _ in 0..10000 {
+            director.generate();
+        }
+        let _ = sender.send(Message::SetMaxInstrumentDelay {max_delay: new_delay});
+        let mut previous: Vec<f32> = (0..4).map(|i| director.get_envelope(0, i)).collect();
+        let mut checkpoint = previous.clone();
+        for step in 1..=100000 {
+            director.generate();
+            for i in 0..4 {
+                let envelope = director.get_envelope(0, i);
+                assert!(envelope >= previous[i]);
+                if step%1000 == 0 {
+                    if envelope > 0.0 && envelope < 1.0 {
+                        assert!(envelope > checkpoint[i]);
+                    }
+                    checkpoint[i] = envelope;
+                }
+                previous[i] = envelope;
+            }
+        }
+        for i in 0..4 {
+            assert_eq!(1.0, director.get_envelope(0, i));
+        }
+    }
+}
+
+#[test]
+fn test_stereo_tails() {
+    // With no stereo width, the left and right channels are normally identical.  Spreading the
+    // tails in stereo should make them differ while the tails of previous notes decay.  This
+    // should also work with a single instrument, which is processed in mono.
+
+    for (instrument_count, stereo_tails) in [(1, false), (1, true), (2, false), (2, true)] {
+        let (sender, receiver) = mpsc::channel();
+        let mut director = Director::new_seeded(InstrumentType::Violin, instrument_count, receiver, 0);
+        let _ = sender.send(Message::SetStereoWidth {width: 0.0});
+        let _ = sender.send(Message::SetStereoTails {enabled: stereo_tails});
+        let mut sum_energy = 0.0;
+        let mut difference_energy = 0.0;
+        for note in [60, 64, 67, 72] {
+            let _ = sender.send(Message::NoteOn {note_index: note, velocity: 0.8});
+            for _ in 0..12000 {
+                let (left, right) = director.generate();
+                sum_energy += (left+right)*(left+right);
+                difference_energy += (left-right)*(left-right);
+            }
+        }
+        assert_eq!(stereo_tails, director.get_state().stereo_tails);
+        if stereo_tails {
+            assert!(difference_energy > 1e-3*sum_energy);
+        }
+        else {
+            assert!(difference_energy < 1e-8*sum_energy);
+        }
+    }
+}
+
+#[test]
+fn test_loudness_compensation() {
+    // Render notes across the range of each instrument at fixed dynamics.  With loudness
+    // compensation, the low, middle, and high parts of the range should have similar RMS.
+    // Individual notes vary more because of body resonances, so average over each part.
+
+    for instrument_type in [InstrumentType::Violin, InstrumentType::Viola, InstrumentType::Cello, InstrumentType::Bass] {
+        let mut log_rms = vec![];
+        for note in (instrument_type.lowest_note()..=instrument_type.highest_note()).step_by(3) {
+            let (sender, receiver) = mpsc::channel();
+            let mut director = Director::new_seeded(instrument_type, 1, receiver, 0);
+            let _ = sender.send(Message::SetLoudnessCompensation {enabled: true});
+            let _ = sender.send(Message::NoteOn {note_index: note, velocity: 1.0});
+            let mut sum = 0.0;
+            for i in 0..16000 {
+                let (left, _) = director.generate();
+                if i >= 8000 {
+                    sum += left*left;
+                }
+            }
+            log_rms.push(0.5*(sum/8000.0).ln());
+        }
+        let n = log_rms.len()/3;
+        let parts: Vec<f32> = [&log_rms[..n], &log_rms[n..2*n], &log_rms[2*n..]].iter().map(|p| p.iter().sum::<f32>()/p.len() as f32).collect();
+        let max = parts.iter().fold(f32::MIN, |a, &b| a.max(b));
+        let min = parts.iter().fold(f32::MAX, |a, &b| a.min(b));
+        assert!(max-min < 1.6f32.ln());
+    }
+}
+
+#[test]
+fn test_idle_threshold() {
+    // A shorter idle window and a higher level threshold should make the Director stop generating
+    // sooner after the end of a note.
+
+    let mut last_sound = vec![];
+    for settings in [None, Some(Message::SetIdleThreshold {samples: 1000, level: 0.01})] {
+        let (sender, receiver) = mpsc::channel();
+        let mut director = Director::new_seeded(InstrumentType::Violin, 1, receiver, 0);
+        if let Some(message) = settings {
+            let _ = sender.send(message);
+        }
+        let _ = sender.send(Message::NoteOn {note_index: 67, velocity: 1.0});
+        for _ in 0..20000 {
+            director.generate();
+        }
+        let _ = sender.send(Message::NoteOff {note_index: 67, velocity: 0.0});
+        let mut last = 0;
+        for i in 0..200000 {
+            if director.generate() != (0.0, 0.0) {
+                last = i;
+            }
+        }
+        last_sound.push(last);
+    }
+    assert!(last_sound[1] < last_sound[0]);
+}
+
+#[test]
+fn test_test_tone() {
+    // The test tone should be a sine wave of the requested frequency, even with no notes playing.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new(InstrumentType::Cello, 2, receiver);
+    let _ = sender.send(Message::SetTestTone {enabled: true, frequency: 1000.0});
+    let mut crossings: i32 = 0;
+    let mut max: f32 = 0.0;
+    let mut last = director.generate().0;
+    for _ in 0..SAMPLE_RATE {
+        let (left, right) = director.generate();
+        assert_eq!(left, right);
+        if (left > 0.0) != (last > 0.0) {
+            crossings += 1;
+        }
+        max = max.max(left.abs());
+        last = left;
+    }
+    assert!((crossings-2000).abs() <= 2);
+    assert!((max-0.25).abs() < 1e-3);
+    assert_eq!(Some(1000.0), director.get_state().test_tone);
+
+    // Turning it off should return to silence.
+
+    let _ = sender.send(Message::SetTestTone {enabled: false, frequency: 1000.0});
+    for _ in 0..200 {
+        director.generate();
+    }
+    assert_eq!((0.0, 0.0), director.generate());
+}
+
+#[test]
+fn test_disconnected() {
+    // Dropping the sender should be detected, and a new channel can be connected in its place.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new(InstrumentType::Violin, 1, receiver);
+    for _ in 0..200 {
+        director.generate();
+    }
+    assert!(!director.is_disconnected());
+    drop(sender);
+    for _ in 0..200 {
+        director.generate();
+    }
+    assert!(director.is_disconnected());
+    let (sender, receiver) = mpsc::channel();
+    director.set_receiver(receiver);
+    assert!(!director.is_disconnected());
+    let _ = sender.send(Message::SetVolume {volume: 0.5});
+    for _ in 0..200 {
+        director.generate();
+    }
+    assert!(!director.is_disconnected());
+    assert_eq!(0.5, director.get_state().volume);
+}
+
+#[test]
+fn test_vibrato_gradient() {
+    // By default every instrument uses the same vibrato.  With a gradient, later instruments use less.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Violin, 4, receiver, 0);
+    let _ = sender.send(Message::NoteOn {note_index: 67, velocity: 0.8});
+    for _ in 0..24000 {
+        director.generate();
+    }
+    let relative_vibrato = |director: &Director, i: usize| director.get_vibrato_amplitude(0, i)/director.get_envelope(0, i);
+    assert!((relative_vibrato(&director, 3)/relative_vibrato(&director, 0)-1.0).abs() < 1e-3);
+    let _ = sender.send(Message::SetVibratoGradient {amount: 1.0});
+    director.generate();
+    assert!((relative_vibrato(&director, 3)/relative_vibrato(&director, 0)-0.5).abs() < 1e-3);
+    assert_eq!(1.0, director.get_state().vibrato_gradient);
+}
+
+#[test]
+fn test_polarity_invert() {
+    // Inverting the polarity should negate the output without otherwise changing it.
+
+    let mut outputs = vec![];
+    for invert in [false, true] {
+        let (sender, receiver) = mpsc::channel();
+        let mut director = Director::new_seeded(InstrumentType::Cello, 2, receiver, 0);
+        let _ = sender.send(Message::SetPolarityInvert {enabled: invert});
+        let _ = sender.send(Message::NoteOn {note_index: 48, velocity: 0.8});
+        outputs.push((0..5000).map(|_| director.generate()).collect::<Vec<(f32, f32)>>());
+    }
+    assert!(outputs[0].iter().any(|&(left, _)| left != 0.0));
+    for i in 0..outputs[0].len() {
+        assert_eq!(outputs[0][i].0, -outputs[1][i].0);
+        assert_eq!(outputs[0][i].1, -outputs[1][i].1);
+    }
+}
+
+/// Decode a FLAC file with 16 bit samples, without any of the processing done by the synthesizer.
+fn decode_flac(file: &[u8]) -> Vec<f32> {
+    let mut reader = claxon::FlacReader::new(file).unwrap();
+    reader.samples().map(|sample| sample.unwrap() as f32/32768.0).collect()
+}
+
+#[test]
+fn test_load_ir() {
+    // A valid file should be decoded.  A corrupted one should be replaced by a synthetic impulse
+    // response instead of panicking, and the failure should be reported.
+
+    let file = include_bytes!("../src/data/violin.flac");
+    assert!(load_ir(file) == load_body_ir(BundledIR::Violin));
+    for bad_file in [&file[..100], b"not a flac file"] {
+        let ir = load_ir(bad_file);
+        assert!(ir.len() > 1000);
+        let energy: f32 = ir.iter().map(|x| x*x).sum();
+        assert!((energy-1.0).abs() < 1e-3);
+        assert!(asset_decode_failed());
+    }
+}
+
+#[test]
+fn test_load_bow_noise() {
+    // A valid file should be decoded unchanged.  A corrupted one should be replaced by white noise
+    // that has a similar level.
+
+    let file = include_bytes!("../src/data/bow_noise.flac");
+    let noise = load_bow_noise(file);
+    assert!(noise == decode_flac(file));
+    let fallback = load_bow_noise(&file[..100]);
+    assert!(fallback.len() > 1000);
+    assert!(fallback.iter().all(|x| x.abs() <= 1.0));
+    let rms = |x: &[f32]| (x.iter().map(|v| v*v).sum::<f32>()/x.len() as f32).sqrt();
+    let ratio = rms(&fallback)/rms(&noise);
+    assert!(ratio > 0.5 && ratio < 2.0);
+    assert!(asset_decode_failed());
+}
+
+#[test]
+fn test_pan_smoothing() {
+    // Changing the stereo width during a note should move the pans smoothly to their new positions.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Viola, 4, receiver, 0);
+    let _ = sender.send(Message::SetStereoWidth {width: 1.0});
+    let _ = sender.send(Message::NoteOn {note_index: 60, velocity: 0.8});
+    for _ in 0..10000 {
+        director.generate();
+    }
+    let start = director.get_pan_gain(0, 0);
+    assert!(start.0 > 0.9);
+    let _ = sender.send(Message::SetStereoWidth {width: 0.0});
+    let mut previous = start;
+    let mut max_change: f32 = 0.0;
+    for _ in 0..10000 {
+        director.generate();
+        let gain = director.get_pan_gain(0, 0);
+        max_change = max_change.max((gain.0-previous.0).abs()).max((gain.1-previous.1).abs());
+        previous = gain;
+    }
+    assert!(max_change > 0.0);
+    assert!(max_change < 0.01);
+    assert!((previous.0-previous.1).abs() < 1e-3);
+}
+
+#[test]
+fn test_synth_bypass() {
+    // While bypassed, the output should be silent but Messages should still be processed.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Violin, 1, receiver, 0);
+    let _ = sender.send(Message::SetSynthBypass {enabled: true});
+    let _ = sender.send(Message::NoteOn {note_index: 60, velocity: 0.8});
+    let _ = sender.send(Message::SetVolume {volume: 0.5});
+    for _ in 0..10000 {
+        assert_eq!((0.0, 0.0), director.generate());
+    }
+    let state = director.get_state();
+    assert!(state.synth_bypass);
+    assert_eq!(0.5, state.volume);
+    let _ = sender.send(Message::SetSynthBypass {enabled: false});
+    let mut energy = 0.0;
+    for _ in 0..10000 {
+        let (left, right) = director.generate();
+        energy += left*left + right*right;
+    }
+    assert!(energy > 0.0);
+}
+
+#[test]
+fn test_voice_budget() {
+    // Rapidly playing many overlapping notes creates lots of tails.  With a voice budget, the
+    // number of elements being synthesized should never exceed it.
+
+    for budget in [0, 6] {
+        let (sender, receiver) = mpsc::channel();
+        let mut director = Director::new_seeded(InstrumentType::Violin, 2, receiver, 0);
+        let _ = sender.send(Message::SetVoiceBudget {max: budget});
+        let mut max_count = 0;
+        for i in 0..20 {
+            let _ = sender.send(Message::NoteOn {note_index: 60+i%12, velocity: 0.8});
+            for _ in 0..1000 {
+                let (left, right) = director.generate();
+                assert!(left.is_finite() && right.is_finite());
+                max_count = max_count.max(director.active_voice_count());
+            }
+        }
+        assert_eq!(budget, director.get_state().voice_budget);
+        if budget == 0 {
+            assert!(max_count > 6);
+        }
+        else {
+            assert!(max_count <= budget);
+        }
+    }
+}
+
+#[test]
+fn test_retire_instruments() {
+    // Reducing the number of instruments while they are playing should fade them out rather than
+    // cutting them off.  The new instruments are silent until the next note.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Cello, 4, receiver, 0);
+    let _ = sender.send(Message::NoteOn {note_index: 48, velocity: 0.8});
+    for _ in 0..20000 {
+        director.generate();
+    }
+    let _ = sender.send(Message::Reinitialize {instrument_type: InstrumentType::Cello, instrument_count: 2});
+    let mut early_energy = 0.0;
+    for _ in 0..500 {
+        let (left, right) = director.generate();
+        early_energy += left*left + right*right;
+    }
+    assert!(early_energy > 0.0);
+    assert!(director.active_voice_count() > 2);
+    for _ in 0..5000 {
+        director.generate();
+    }
+    assert_eq!(2, director.active_voice_count());
+}
+
+#[test]
+fn test_entry_scoop() {
+    // A scoop should start the note below pitch and reach it after the specified time.  Legato
+    // notes should not scoop.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Violin, 1, receiver, 0);
+    let _ = sender.send(Message::SetMaxInstrumentDelay {max_delay: 0});
+    let _ = sender.send(Message::SetEntryScoop {cents: 50.0, ms: 100});
+    let _ = sender.send(Message::NoteOn {note_index: 69, velocity: 0.8});
+    director.generate();
+    let state = director.get_state();
+    assert_eq!(50.0, state.entry_scoop_cents);
+    assert_eq!(100, state.entry_scoop_ms);
+    let start = director.get_frequency(0, 0);
+    assert!((start-440.0*f32::powf(2.0, -50.0/1200.0)).abs() < 0.5);
+    let mut previous = start;
+    for i in 1..(SAMPLE_RATE/10) {
+        director.generate();
+        let freq = director.get_frequency(0, 0);
+        assert!(freq >= previous);
+        if i < SAMPLE_RATE/20 {
+            assert!(freq < 440.0);
+        }
+        previous = freq;
+    }
+    for _ in 0..100 {
+        director.generate();
+    }
+    assert!((director.get_frequency(0, 0)-440.0).abs() < 1e-3);
+    let _ = sender.send(Message::NoteOn {note_index: 71, velocity: 0.8});
+    director.generate();
+    assert!((director.get_frequency(0, 0)-440.0*f32::powf(2.0, 2.0/12.0)).abs() < 1e-3);
+
+    // With zero cents, the note should start at pitch.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Violin, 1, receiver, 0);
+    let _ = sender.send(Message::SetEntryScoop {cents: 0.0, ms: 100});
+    let _ = sender.send(Message::NoteOn {note_index: 69, velocity: 0.8});
+    director.generate();
+    assert!((director.get_frequency(0, 0)-440.0).abs() < 1e-3);
+}
+
+#[test]
+fn test_out_of_range_notes() {
+    // Notes outside the MIDI range should be ignored without disturbing the note that is playing.
+    // Notes at the edges of the instrument's range should be played normally.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Cello, 1, receiver, 0);
+    let _ = sender.send(Message::NoteOn {note_index: 48, velocity: 0.8});
+    for _ in 0..100 {
+        director.generate();
+    }
+    let freq = director.get_frequency(0, 0);
+    assert!((freq-130.8).abs() < 0.1);
+    for note in [i32::MIN, -1000, -1, 0, 127, 128, 1000, i32::MAX] {
+        let _ = sender.send(Message::NoteOn {note_index: note, velocity: 0.8});
+        let _ = sender.send(Message::NoteOff {note_index: note, velocity: 0.8});
+        for _ in 0..100 {
+            let (left, right) = director.generate();
+            assert!(left.is_finite() && right.is_finite());
+        }
+        assert_eq!(freq, director.get_frequency(0, 0));
+    }
+    for note in [InstrumentType::Cello.lowest_note(), InstrumentType::Cello.highest_note()] {
+        let _ = sender.send(Message::NoteOn {note_index: note, velocity: 0.8});
+        for _ in 0..100 {
+            director.generate();
+        }
+        let expected = 440.0*f32::powf(2.0, (note-69) as f32/12.0);
+        assert!((director.get_frequency(0, 0)-expected).abs() < 1e-3*expected);
+    }
+}
+
+#[test]
+fn test_sostenuto() {
+    // A note that is playing when the sostenuto pedal is pressed should be held after its key is
+    // released, then released along with the pedal.  A note started while the pedal is down
+    // should not be held.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Viola, 1, receiver, 0);
+    let _ = sender.send(Message::SetMaxInstrumentDelay {max_delay: 0});
+    let _ = sender.send(Message::NoteOn {note_index: 60, velocity: 0.8});
+    let _ = sender.send(Message::SetSostenuto {engaged: true});
+    let _ = sender.send(Message::NoteOff {note_index: 60, velocity: 0.0});
+    for _ in 0..48000 {
+        director.generate();
+    }
+    assert!(director.get_state().sostenuto);
+    assert!(director.get_envelope(0, 0) > 0.5);
+    let _ = sender.send(Message::SetSostenuto {engaged: false});
+    for _ in 0..48000 {
+        director.generate();
+    }
+    assert!(!director.get_state().sostenuto);
+    assert_eq!(0.0, director.get_envelope(0, 0));
+    let _ = sender.send(Message::SetSostenuto {engaged: true});
+    let _ = sender.send(Message::NoteOn {note_index: 62, velocity: 0.8});
+    for _ in 0..48000 {
+        director.generate();
+    }
+    assert!(director.get_envelope(0, 0) > 0.5);
+    let _ = sender.send(Message::NoteOff {note_index: 62, velocity: 0.0});
+    for _ in 0..48000 {
+        director.generate();
+    }
+    assert_eq!(0.0, director.get_envelope(0, 0));
+}
+
+#[test]
+fn test_body_ir_energy() {
+    // The bundled impulse responses have very different energies as recorded, but they should all
+    // have unit energy once loaded.
+
+    let raw: Vec<f32> = [&include_bytes!("../src/data/violin.flac")[..], &include_bytes!("../src/data/viola.flac")[..]]
+        .iter().map(|file| decode_flac(file).iter().map(|x| x*x).sum()).collect();
+    assert!(raw[1] > 1.5*raw[0]);
+    for which in [BundledIR::Violin, BundledIR::Viola, BundledIR::Cello, BundledIR::Bass] {
+        let energy: f32 = load_body_ir(which).iter().map(|x| x*x).sum();
+        assert!((energy-1.0).abs() < 1e-3);
+    }
+}
+
+#[test]
+fn test_max_note_duration() {
+    // When a maximum duration is set, a note that is never released should be released
+    // automatically.  A new note restarts the timer.  Without a maximum, notes play indefinitely.
+
+    for max in [0, 500] {
+        let (sender, receiver) = mpsc::channel();
+        let mut director = Director::new_seeded(InstrumentType::Cello, 1, receiver, 0);
+        let _ = sender.send(Message::SetMaxInstrumentDelay {max_delay: 0});
+        let _ = sender.send(Message::SetMaxNoteDuration {ms: max});
+        let _ = sender.send(Message::NoteOn {note_index: 48, velocity: 0.8});
+        for _ in 0..SAMPLE_RATE*4/10 {
+            director.generate();
+        }
+        assert_eq!(max, director.get_state().max_note_duration);
+        assert!(director.get_envelope(0, 0) > 0.5);
+        let _ = sender.send(Message::NoteOn {note_index: 50, velocity: 0.8});
+        for _ in 0..SAMPLE_RATE*4/10 {
+            director.generate();
+        }
+        assert!(director.get_envelope(0, 0) > 0.5);
+        for _ in 0..SAMPLE_RATE*2 {
+            director.generate();
+        }
+        if max == 0 {
+            assert!(director.get_envelope(0, 0) > 0.5);
+        }
+        else {
+            assert_eq!(0.0, director.get_envelope(0, 0));
+        }
+    }
+}
+
+#[test]
+fn test_generate_block() {
+    // Generating audio in blocks should give exactly the same result as generating it one
+    // sample at a time, including while settings change and instruments are being retired.
+
+    let (sender1, receiver1) = mpsc::channel();
+    let (sender2, receiver2) = mpsc::channel();
+    let mut director1 = Director::new_seeded(InstrumentType::Viola, 3, receiver1, 0);
+    let mut director2 = Director::new_seeded(InstrumentType::Viola, 3, receiver2, 0);
+    let events = vec![
+        vec![Message::NoteOn {note_index: 60, velocity: 0.8}],
+        vec![Message::SetVolume {volume: 0.5}, Message::SetStereoWidth {width: 0.8}],
+        vec![Message::NoteOn {note_index: 64, velocity: 1.0}],
+        vec![Message::Reinitialize {instrument_type: InstrumentType::Violin, instrument_count: 2}],
+        vec![Message::SetPolyphonic {polyphonic: true}, Message::NoteOn {note_index: 67, velocity: 0.7}, Message::NoteOn {note_index: 72, velocity: 0.7}],
+        vec![Message::AllNotesOff],
+        vec![]
+    ];
+    let block_sizes = [1, 37, 256, 1000];
+    let mut block_index = 0;
+    for messages in events {
+        for message in messages {
+            let _ = sender1.send(message.clone());
+            let _ = sender2.send(message);
+        }
+        let mut expected = vec![];
+        for _ in 0..SAMPLE_RATE/4 {
+            expected.push(director1.generate());
+        }
+        let mut i = 0;
+        while i < expected.len() {
+            let size = block_sizes[block_index%block_sizes.len()].min(expected.len()-i);
+            block_index += 1;
+            let mut left = vec![0.0; size];
+            let mut right = vec![0.0; size];
+            director2.generate_block(&mut left, &mut right);
+            for j in 0..size {
+                assert_eq!(expected[i+j], (left[j], right[j]));
+            }
+            i += size;
+        }
+    }
+}
+
+#[test]
+fn test_body_modes() {
+    // A body mode should boost the output near its frequency and leave distant frequencies
+    // nearly unchanged.  By default there are no modes, so the output is unaffected.
+
+    let compute_spectrum = |messages: Vec<Message>| {
+        let (_sender, receiver) = mpsc::channel();
+        let mut director = Director::new_seeded(InstrumentType::Viola, 1, receiver, 0);
+        let mut events: Vec<(i64, Message)> = messages.into_iter().map(|m| (0, m)).collect();
+        events.push((0, Message::NoteOn {note_index: 57, velocity: 0.8}));
+        let (left, _) = director.generate_with_events(&events, 24000+65536);
+        let mut windowed: Vec<f32> = left[24000..].iter().enumerate().map(|(i, x)| x*(1.0-(2.0*PI*i as f32/65536.0).cos())).collect();
+        let mut fft_planner = RealFftPlanner::<f32>::new();
+        let fft = fft_planner.plan_fft_forward(windowed.len());
+        let mut spectrum = fft.make_output_vec();
+        let _ = fft.process(&mut windowed, &mut spectrum);
+        (director.get_state(), spectrum)
+    };
+    let band_power = |spectrum: &Vec<realfft::num_complex::Complex<f32>>, low: f32, high: f32| {
+        let mut power = 0.0;
+        for (i, x) in spectrum.iter().enumerate() {
+            let freq = i as f32*SAMPLE_RATE as f32/65536.0;
+            if freq >= low && freq < high {
+                power += x.norm_sqr();
+            }
+        }
+        power
+    };
+    let (state1, spectrum1) = compute_spectrum(vec![]);
+    assert!(state1.body_modes.iter().all(|mode| mode.2 == 0.0));
+    let (state2, spectrum2) = compute_spectrum(vec![
+        Message::SetBodyMode {index: 1, freq: 880.0, q: 5.0, gain: 12.0},
+        Message::SetBodyMode {index: 7, freq: 300.0, q: 5.0, gain: 12.0}
+    ]);
+    assert_eq!((880.0, 5.0, 12.0), state2.body_modes[1]);
+    assert_eq!(state1.body_modes[0], state2.body_modes[0]);
+    let (_, spectrum3) = compute_spectrum(vec![Message::SetBodyMode {index: 1, freq: 880.0, q: 5.0, gain: 0.0}]);
+    assert_eq!(spectrum1, spectrum3);
+    let near = band_power(&spectrum2, 860.0, 900.0)/band_power(&spectrum1, 860.0, 900.0);
+    let far = band_power(&spectrum2, 200.0, 240.0)/band_power(&spectrum1, 200.0, 240.0);
+    assert!(near > 10.0 && near < 20.0);
+    assert!(far > 0.8 && far < 1.25);
+}
+
+#[test]
+fn test_wake_after_idle() {
+    // When a note starts after the Director has been idle, nothing left over from the previous
+    // note should be output.  Until the new note has produced its first period, the output should
+    // be exactly silent, just as it is for a Director that has never played anything.
+
+    for instrument_type in [InstrumentType::Violin, InstrumentType::Cello, InstrumentType::Bass] {
+        let (sender, receiver) = mpsc::channel();
+        let mut director = Director::new_seeded(instrument_type, 2, receiver, 0);
+        let note = instrument_type.lowest_note()+12;
+        let _ = sender.send(Message::SetMaxInstrumentDelay {max_delay: 0});
+        let _ = sender.send(Message::NoteOn {note_index: note, velocity: 1.0});
+        for _ in 0..SAMPLE_RATE {
+            director.generate();
+        }
+        let _ = sender.send(Message::NoteOff {note_index: note, velocity: 1.0});
+        for _ in 0..4*SAMPLE_RATE {
+            director.generate();
+        }
+        for _ in 0..1000 {
+            assert_eq!((0.0, 0.0), director.generate());
+        }
+        let _ = sender.send(Message::NoteOn {note_index: note+7, velocity: 1.0});
+        for _ in 0..50 {
+            assert_eq!((0.0, 0.0), director.generate());
+        }
+        let mut peak = 0.0;
+        for _ in 0..SAMPLE_RATE/2 {
+            let (left, right) = director.generate();
+            peak = f32::max(peak, f32::max(left.abs(), right.abs()));
+        }
+        assert!(peak > 0.01);
+    }
+}
+
+#[test]
+fn test_reverb_width() {
+    // Changing the reverb width should only change the side signal of the output.  The mid signal,
+    // which contains the dry signal's pan position, should be unaffected.  A wider reverb should
+    // produce a wider output.
+
+    let generate = |width: f32| {
+        let (_sender, receiver) = mpsc::channel();
+        let mut director = Director::new_seeded(InstrumentType::Viola, 4, receiver, 0);
+        let events = vec![
+            (0, Message::SetReverbWidth {width}),
+            (0, Message::NoteOn {note_index: 60, velocity: 0.8}),
+            (48000, Message::NoteOff {note_index: 60, velocity: 0.8})
+        ];
+        let (left, right) = director.generate_with_events(&events, 72000);
+        assert_eq!(width, director.get_state().reverb_width);
+        (left, right)
+    };
+    let side_power = |left: &Vec<f32>, right: &Vec<f32>| -> f32 {
+        left.iter().zip(right).map(|(l, r)| 0.25*(l-r)*(l-r)).sum()
+    };
+    let (left1, right1) = generate(1.0);
+    let (left2, right2) = generate(2.5);
+    let (left3, right3) = generate(0.0);
+    let mut mid_difference: f32 = 0.0;
+    let mut mid_power: f32 = 0.0;
+    for i in 0..left1.len() {
+        mid_difference = mid_difference.max(((left2[i]+right2[i])-(left1[i]+right1[i])).abs());
+        mid_power = mid_power.max((left1[i]+right1[i]).abs());
+    }
+    assert!(mid_difference < 1e-4*mid_power);
+    let side1 = side_power(&left1, &right1);
+    let side2 = side_power(&left2, &right2);
+    let side3 = side_power(&left3, &right3);
+    assert!(side2 > 1.2*side1);
+    assert!(side3 < side1);
+}
+
+#[test]
+fn test_process_input() {
+    // External input should pass through with the body resonance added.  Notes should not be heard,
+    // and silent input should give silent output.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Cello, 2, receiver, 0);
+    let _ = sender.send(Message::NoteOn {note_index: 48, velocity: 1.0});
+    for _ in 0..SAMPLE_RATE/2 {
+        assert_eq!((0.0, 0.0), director.process_input(0.0, 0.0));
+    }
+    let (left, right) = director.process_input(0.5, 0.0);
+    assert!(left > 0.4 && left < 0.6);
+    assert!(right.abs() < 0.1);
+    let mut resonance = 0.0;
+    for _ in 0..SAMPLE_RATE/10 {
+        let (left, right) = director.process_input(0.0, 0.0);
+        resonance += left*left + right*right;
+    }
+    assert!(resonance > 1e-4);
+
+    // Switching back to generating audio should play the note.
+
+    let mut power = 0.0;
+    for _ in 0..SAMPLE_RATE/2 {
+        let (left, right) = director.generate();
+        power += left*left + right*right;
+    }
+    assert!(power > 1.0);
+
+    // With a single instrument the input is processed in mono, and should come out centered.
+
+    let (_sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Cello, 1, receiver, 0);
+    let mut random = Random::with_seed(0);
+    let mut left_power = 0.0;
+    let mut right_power = 0.0;
+    for _ in 0..SAMPLE_RATE/2 {
+        let x = 0.1*random.get_normal();
+        let (left, right) = director.process_input(x, x);
+        left_power += left*left;
+        right_power += right*right;
+    }
+    assert!(left_power > 0.0);
+    assert!(left_power > 0.9*right_power && left_power < 1.1*right_power);
+}
+
+#[test]
+fn test_tilt_eq() {
+    // A positive tilt should make the output brighter and a negative one darker.  With no tilt
+    // the output should be unchanged.
+
+    let generate = |tilt: Option<f32>| {
+        let (_sender, receiver) = mpsc::channel();
+        let mut director = Director::new_seeded(InstrumentType::Cello, 1, receiver, 0);
+        let mut events = vec![(0, Message::NoteOn {note_index: 48, velocity: 0.8})];
+        if let Some(tilt) = tilt {
+            events.insert(0, (0, Message::SetTiltEq {tilt}));
+        }
+        let (left, _) = director.generate_with_events(&events, 48000);
+        if let Some(tilt) = tilt {
+            assert_eq!(tilt.clamp(-1.0, 1.0), director.get_state().tilt_eq);
+        }
+        left
+    };
+    let brightness = |x: &Vec<f32>| -> f32 {
+        let signal: f32 = x.iter().map(|v| v*v).sum();
+        let difference: f32 = x.windows(2).map(|w| (w[1]-w[0])*(w[1]-w[0])).sum();
+        difference/signal
+    };
+    let flat = generate(None);
+    assert_eq!(flat, generate(Some(0.0)));
+    let bright = generate(Some(1.0));
+    let dark = generate(Some(-2.0));
+    assert!(brightness(&bright) > 1.5*brightness(&flat));
+    assert!(brightness(&dark) < 0.8*brightness(&flat));
+}
+
+#[test]
+fn test_divisi() {
+    // In divisi mode, the instruments should be divided among the held notes.  With more notes
+    // than instruments, the lowest note should be silent.  Disabling it should restore all
+    // instruments to every note.
+
+    let sounding = |director: &Director, division: usize, instrument: usize| {
+        let (left, right) = director.get_pan_gain(division, instrument);
+        left+right > 0.01
+    };
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Violin, 6, receiver, 0);
+    let _ = sender.send(Message::SetDivisi {enabled: true});
+    for note in [67, 60, 64] {
+        let _ = sender.send(Message::NoteOn {note_index: note, velocity: 0.8});
+    }
+    for _ in 0..4800 {
+        director.generate();
+    }
+    assert!(director.get_state().divisi);
+
+    // Notes are assigned to the last idle division first, so divisions 1 to 3 are playing.
+
+    for instrument in 0..6 {
+        let divisions: Vec<usize> = (1..4).filter(|&d| sounding(&director, d, instrument)).collect();
+        assert_eq!(1, divisions.len());
+    }
+    for division in 1..4 {
+        assert_eq!(2, (0..6).filter(|&i| sounding(&director, division, i)).count());
+    }
+    let _ = sender.send(Message::SetDivisi {enabled: false});
+    for _ in 0..4800 {
+        director.generate();
+    }
+    for division in 1..4 {
+        assert!((0..6).all(|i| sounding(&director, division, i)));
+    }
+
+    // Two instruments playing three notes.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Violin, 2, receiver, 0);
+    let _ = sender.send(Message::SetDivisi {enabled: true});
+    for note in [67, 60, 64] {
+        let _ = sender.send(Message::NoteOn {note_index: note, velocity: 0.8});
+    }
+    for _ in 0..4800 {
+        director.generate();
+    }
+    assert!(!sounding(&director, 2, 0) && !sounding(&director, 2, 1));
+    assert!(sounding(&director, 3, 0) != sounding(&director, 3, 1));
+    assert!(sounding(&director, 1, 0) != sounding(&director, 1, 1));
+
+    // Releasing the highest note lets the lowest one be heard.
+
+    let _ = sender.send(Message::NoteOff {note_index: 67, velocity: 0.5});
+    for _ in 0..4800 {
+        director.generate();
+    }
+    assert!(sounding(&director, 2, 0) || sounding(&director, 2, 1));
+}
+
+#[test]
+fn test_string_crossing() {
+    // A bend that moves a note onto a different string should produce a burst of noise when string
+    // crossing is enabled.  Before the bend, and for bends that stay on one string, the output
+    // should be identical to when it is disabled.
+
+    let generate = |note: i32, crossing: bool| {
+        let (sender, receiver) = mpsc::channel();
+        let mut director = Director::new_seeded(InstrumentType::Violin, 1, receiver, 0);
+        let _ = sender.send(Message::SetStringCrossing {enabled: crossing});
+        let _ = sender.send(Message::NoteOn {note_index: note, velocity: 0.8});
+        let mut output = vec![];
+        for _ in 0..48000 {
+            output.push(director.generate().0);
+        }
+        assert_eq!(crossing, director.get_state().string_crossing);
+        let _ = sender.send(Message::SetPitchBend {semitones: 2.0});
+        for _ in 0..24000 {
+            output.push(director.generate().0);
+        }
+        output
+    };
+
+    // C#4 bent to D#4 crosses from the G string to the D string.
+
+    let without = generate(61, false);
+    let with = generate(61, true);
+    assert_eq!(without[..48000], with[..48000]);
+    assert_ne!(without[48000..], with[48000..]);
+
+    // A4 bent to B4 stays on the A string.
+
+    let without = generate(69, false);
+    let with = generate(69, true);
+    assert_eq!(without, with);
+}
+
+#[test]
+fn test_generate_interleaved() {
+    // Generating interleaved output should give exactly the same result as calling generate()
+    // once for each sample.
+
+    let (sender1, receiver1) = mpsc::channel();
+    let (sender2, receiver2) = mpsc::channel();
+    let mut director1 = Director::new_seeded(InstrumentType::Cello, 2, receiver1, 0);
+    let mut director2 = Director::new_seeded(InstrumentType::Cello, 2, receiver2, 0);
+    for message in [Message::NoteOn {note_index: 48, velocity: 0.8}, Message::SetStereoWidth {width: 0.8}] {
+        let _ = sender1.send(message.clone());
+        let _ = sender2.send(message);
+    }
+    for size in [2, 74, 512, 2000] {
+        let mut out = vec![0.0; size];
+        director2.generate_interleaved(&mut out);
+        for frame in out.chunks_exact(2) {
+            assert_eq!(director1.generate(), (frame[0], frame[1]));
+        }
+    }
+}
+
+#[test]
+fn test_separate_outputs() {
+    // Enabling separate outputs should not change the main output.  Each instrument should have its
+    // own signal, and they should be 0 when the mode is disabled.
+
+    let (sender1, receiver1) = mpsc::channel();
+    let (sender2, receiver2) = mpsc::channel();
+    let mut director1 = Director::new_seeded(InstrumentType::Viola, 3, receiver1, 0);
+    let mut director2 = Director::new_seeded(InstrumentType::Viola, 3, receiver2, 0);
+    let _ = sender2.send(Message::SetSeparateOutputs {enabled: true});
+    for sender in [&sender1, &sender2] {
+        let _ = sender.send(Message::NoteOn {note_index: 60, velocity: 0.8});
+    }
+    let mut energy = vec![0.0; 4];
+    let mut difference = 0.0;
+    for _ in 0..SAMPLE_RATE {
+        assert_eq!(director1.generate(), director2.generate());
+        for i in 0..4 {
+            energy[i] += director2.get_instrument_output(i).powi(2);
+            assert_eq!(0.0, director1.get_instrument_output(i));
+        }
+        difference += (director2.get_instrument_output(0)-director2.get_instrument_output(1)).powi(2);
+    }
+    assert!(director2.get_state().separate_outputs);
+    for i in 0..3 {
+        assert!(energy[i] > 0.0);
+    }
+    assert_eq!(0.0, energy[3]);
+    assert!(difference > 0.1*energy[0]);
+
+    // Generating blocks should give the same result as generate() while the mode is enabled.
+
+    let mut left = vec![0.0; 1000];
+    let mut right = vec![0.0; 1000];
+    director2.generate_block(&mut left, &mut right);
+    for i in 0..1000 {
+        assert_eq!(director1.generate(), (left[i], right[i]));
+    }
+    let _ = sender2.send(Message::SetSeparateOutputs {enabled: false});
+    for _ in 0..100 {
+        director2.generate();
+    }
+    assert!(!director2.get_state().separate_outputs);
+    for i in 0..3 {
+        assert_eq!(0.0, director2.get_instrument_output(i));
+    }
+}
+
+#[test]
+fn test_envelope_scale() {
+    // Scaling the attack and release should change how long they take by the same factor.  The
+    // state should reflect the scales, clamped to the allowed range.
+
+    let measure = |attack_scale: f32, release_scale: f32| {
+        let (sender, receiver) = mpsc::channel();
+        let mut director = Director::new(InstrumentType::Cello, 1, receiver);
+        let _ = sender.send(Message::SetAttackScale {scale: attack_scale});
+        let _ = sender.send(Message::SetReleaseScale {scale: release_scale});
+        let _ = sender.send(Message::NoteOn {note_index: 48, velocity: 0.5});
+        let mut attack_time = 0;
+        while director.get_envelope(0, 0) < 1.0 {
+            director.generate();
+            attack_time += 1;
+        }
+        let _ = sender.send(Message::NoteOff {note_index: 48, velocity: 0.0});
+        let mut release_time = 0;
+        while director.get_envelope(0, 0) > 0.0 {
+            director.generate();
+            release_time += 1;
+        }
+        let state = director.get_state();
+        (attack_time as f32, release_time as f32, state.attack_scale, state.release_scale)
+    };
+    let (attack1, release1, _, _) = measure(1.0, 1.0);
+    let (attack2, release2, attack_scale, release_scale) = measure(4.0, 10.0);
+    assert!((attack2/attack1-4.0).abs() < 0.1);
+    assert!((release2/release1-10.0).abs() < 0.2);
+    assert_eq!(4.0, attack_scale);
+    assert_eq!(10.0, release_scale);
+    let (_, _, attack_scale, release_scale) = measure(0.0, 100.0);
+    assert_eq!(0.25, attack_scale);
+    assert_eq!(20.0, release_scale);
+}
+
+#[test]
+fn test_set_active_instrument() {
+    // Switching the instrument type with SetActiveInstrument should give exactly the same result
+    // as Reinitialize, both the first time it is used and when switching back to a type.
+
+    let (sender1, receiver1) = mpsc::channel();
+    let (sender2, receiver2) = mpsc::channel();
+    let mut director1 = Director::new_seeded(InstrumentType::Violin, 2, receiver1, 0);
+    let mut director2 = Director::new_seeded(InstrumentType::Violin, 2, receiver2, 0);
+    for (instrument_type, note) in [(InstrumentType::Cello, 48), (InstrumentType::Violin, 67), (InstrumentType::Cello, 50)] {
+        let _ = sender1.send(Message::Reinitialize {instrument_type, instrument_count: 2});
+        let _ = sender2.send(Message::SetActiveInstrument {instrument_type});
+        for sender in [&sender1, &sender2] {
+            let _ = sender.send(Message::NoteOn {note_index: note, velocity: 0.8});
+        }
+        for _ in 0..10000 {
+            assert_eq!(director1.generate(), director2.generate());
+        }
+        assert_eq!(instrument_type, director2.instrument_type);
+        for sender in [&sender1, &sender2] {
+            let _ = sender.send(Message::AllNotesOff);
+        }
+        for _ in 0..20000 {
+            assert_eq!(director1.generate(), director2.generate());
+        }
+    }
+}
+
+#[test]
+fn test_set_active_instrument_reverbs_prebuilt() {
+    // The body Reverbs for every type should be built when the Director is created, so the first
+    // use of SetActiveInstrument does no more work on the audio thread than later ones.  Switching
+    // to Cello the first time should make the same allocations as switching to it again.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Violin, 1, receiver, 0);
+    let mut switch_allocations = vec![];
+    for instrument_type in [InstrumentType::Cello, InstrumentType::Violin, InstrumentType::Cello] {
+        let _ = sender.send(Message::SetActiveInstrument {instrument_type});
+        let allocations = allocation_count();
+        director.advance(1000);
+        switch_allocations.push(allocation_count()-allocations);
+        assert_eq!(instrument_type, director.instrument_type);
+    }
+    assert_eq!(switch_allocations[0], switch_allocations[2]);
+}
+
+#[test]
+fn test_articulation_loudness() {
+    // At fixed dynamics, every articulation should have roughly the same loudness as arco.  The
+    // loudness of a note is measured as the RMS of its loudest 100 ms, and averaged over notes
+    // across the range of every instrument type.
+
+    let articulations = [Articulation::Arco, Articulation::Marcato, Articulation::Glissando, Articulation::Spiccato,
+                         Articulation::Pizzicato, Articulation::ColLegno, Articulation::Tremolo];
+    let mut loudness = vec![0.0; articulations.len()];
+    for instrument_type in [InstrumentType::Violin, InstrumentType::Viola, InstrumentType::Cello, InstrumentType::Bass] {
+        for (i, articulation) in articulations.iter().enumerate() {
+            let notes: Vec<i32> = (instrument_type.lowest_note()+5..=instrument_type.highest_note()-10).step_by(10).collect();
+            for note in &notes {
+                let (sender, receiver) = mpsc::channel();
+                let mut director = Director::new_seeded(instrument_type, 1, receiver, 0);
+                let _ = sender.send(Message::SetArticulation {articulation: *articulation});
+                let _ = sender.send(Message::NoteOn {note_index: *note, velocity: 0.8});
+                let output: Vec<f32> = (0..SAMPLE_RATE).map(|_| director.generate().0).collect();
+                let window = SAMPLE_RATE as usize/10;
+                let max_power = (0..output.len()-window).step_by(480)
+                    .map(|start| output[start..start+window].iter().map(|x| x*x).sum::<f32>()/window as f32)
+                    .fold(0.0, f32::max);
+                loudness[i] += 10.0*max_power.log10()/(4*notes.len()) as f32;
+            }
+        }
+    }
+    for i in 1..articulations.len() {
+        assert!((loudness[i]-loudness[0]).abs() < 1.5, "{:?}: {} dB", articulations[i], loudness[i]-loudness[0]);
+    }
+}
+
+#[test]
+fn test_note_tuning() {
+    // Retuning a note should affect the next time it is played, and a sounding note should glide
+    // smoothly to the new tuning.  Transitions are updated every 100 steps, so the frequency
+    // changes in small increments rather than jumping.  Other notes are unaffected.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Violin, 1, receiver, 0);
+    let _ = sender.send(Message::SetMaxInstrumentDelay {max_delay: 0});
+    let _ = sender.send(Message::SetNoteTuning {note_index: 69, cents: -14.0});
+    let _ = sender.send(Message::NoteOn {note_index: 69, velocity: 0.8});
+    for _ in 0..1000 {
+        director.generate();
+    }
+    assert!((director.get_frequency(0, 0)-440.0*f32::powf(2.0, -14.0/1200.0)).abs() < 1e-3);
+    assert_eq!(-14.0, director.get_state().note_tuning[69]);
+    let _ = sender.send(Message::SetNoteTuning {note_index: 69, cents: 20.0});
+    let _ = sender.send(Message::SetNoteTuning {note_index: 71, cents: 50.0});
+    let mut previous = director.get_frequency(0, 0);
+    for _ in 0..4000 {
+        director.generate();
+        let freq = director.get_frequency(0, 0);
+        assert!(freq >= previous && freq-previous < 1.5);
+        previous = freq;
+    }
+    assert!((director.get_frequency(0, 0)-440.0*f32::powf(2.0, 20.0/1200.0)).abs() < 1e-3);
+    let _ = sender.send(Message::NoteOn {note_index: 67, velocity: 0.8});
+    director.generate();
+    assert!((director.get_frequency(0, 0)-440.0*f32::powf(2.0, -2.0/12.0)).abs() < 1e-3);
+
+    // Invalid notes are ignored, and the amount is clamped.
+
+    let _ = sender.send(Message::SetNoteTuning {note_index: 128, cents: 20.0});
+    let _ = sender.send(Message::SetNoteTuning {note_index: 60, cents: 5000.0});
+    for _ in 0..100 {
+        director.generate();
+    }
+    assert_eq!(1200.0, director.get_state().note_tuning[60]);
+}
+
+#[test]
+fn test_ensemble_tightness() {
+    // The default tightness corresponds to the default settings.  Tighter values reduce the delay
+    // between instruments, and looser ones also add variation between them.  The individual
+    // settings can still be changed afterward.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new(InstrumentType::Violin, 4, receiver);
+    let default = director.get_state();
+    let _ = sender.send(Message::SetEnsembleTightness {value: 0.5});
+    for _ in 0..100 {
+        director.generate();
+    }
+    let state = director.get_state();
+    assert_eq!(0.5, state.ensemble_tightness);
+    assert_eq!(default.max_instrument_delay, state.max_instrument_delay);
+    assert_eq!(default.pitch_drift, state.pitch_drift);
+    assert_eq!(default.attack_jitter, state.attack_jitter);
+    assert_eq!(default.vibrato_gradient, state.vibrato_gradient);
+    assert_eq!(default.unison_detune, state.unison_detune);
+    let _ = sender.send(Message::SetEnsembleTightness {value: 1.0});
+    for _ in 0..100 {
+        director.generate();
+    }
+    let tight = director.get_state();
+    assert_eq!(0, tight.max_instrument_delay);
+    assert_eq!(0.0, tight.pitch_drift);
+    let _ = sender.send(Message::SetEnsembleTightness {value: 0.0});
+    for _ in 0..100 {
+        director.generate();
+    }
+    let loose = director.get_state();
+    assert!(loose.max_instrument_delay > default.max_instrument_delay);
+    assert!(loose.pitch_drift > 0.0);
+    assert!(loose.attack_jitter > 0.0);
+    assert!(loose.vibrato_gradient > 0.0);
+    assert!(loose.unison_detune > 0.0);
+    let _ = sender.send(Message::SetPitchDrift {amount: 0.2});
+    for _ in 0..100 {
+        director.generate();
+    }
+    let state = director.get_state();
+    assert_eq!(0.2, state.pitch_drift);
+    assert_eq!(loose.max_instrument_delay, state.max_instrument_delay);
+    assert_eq!(0.0, state.ensemble_tightness);
+}
+
+#[test]
+fn test_transition_lifecycle() {
+    // Transitions are updated every 100 steps.  A Transition should reach its final value on the
+    // update at its end, and be removed on that same update, but not before.  An arco note played
+    // at full velocity has an attack lasting 1000 steps, and also changes the bow position over
+    // 10000 steps.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Violin, 1, receiver, 0);
+    let _ = sender.send(Message::SetMaxInstrumentDelay {max_delay: 0});
+    let _ = sender.send(Message::NoteOn {note_index: 69, velocity: 1.0});
+    director.advance(1);
+    assert_eq!(1, director.step);
+    assert_eq!(2, director.get_transition_count(0));
+    director.advance(899);
+    assert_eq!(2, director.get_transition_count(0));
+    assert!(director.get_envelope(0, 0) < 1.0);
+    director.advance(100);
+    assert_eq!(1000, director.step);
+    assert_eq!(1.0, director.get_envelope(0, 0));
+    assert_eq!(1, director.get_transition_count(0));
+    director.advance(8900);
+    assert_eq!(1, director.get_transition_count(0));
+    director.advance(100);
+    assert_eq!(0, director.get_transition_count(0));
+
+    // With several instruments, a Transition is kept until the most delayed instrument could have
+    // finished it, and by then all of them have.
+
+    let (sender, receiver) = mpsc::channel();
+    let mut director = Director::new_seeded(InstrumentType::Violin, 4, receiver, 0);
+    let _ = sender.send(Message::SetMaxInstrumentDelay {max_delay: 1000});
+    let _ = sender.send(Message::NoteOn {note_index: 69, velocity: 1.0});
+    director.advance(1900);
+    assert_eq!(2, director.get_transition_count(0));
+    director.advance(100);
+    assert_eq!(1, director.get_transition_count(0));
+    assert!((0..4).all(|i| director.get_envelope(0, i) == 1.0));
+}
+
+#[test]
+fn test_reverb_tail_across_count_change() {
+    // Changing the number of instruments without changing the type keeps the body reverb, so its
+    // tail continues to decay instead of stopping abruptly.  Changing the type starts a new body.
+
+    for (instrument_type, expect_tail) in [(InstrumentType::Cello, true), (InstrumentType::Viola, false)] {
+        let (sender, receiver) = mpsc::channel();
+        let mut director = Director::new_seeded(InstrumentType::Cello, 1, receiver, 0);
+        let _ = sender.send(Message::NoteOn {note_index: 48, velocity: 0.8});
+        director.advance(SAMPLE_RATE as usize/2);
+        let _ = sender.send(Message::Reinitialize {instrument_type, instrument_count: 3});
+        let energy = |director: &mut Director| {
+            (0..SAMPLE_RATE/100).map(|_| {
+                let (left, right) = director.generate();
+                left*left+right*right
+            }).sum::<f32>()
+        };
+        let initial = energy(&mut director);
+        let later = energy(&mut director);
+        if expect_tail {
+            assert!(initial > 0.1);
+            assert!(later < initial);
+        }
+        else {
+            assert!(initial < 0.01);
+        }
+    }
+}
+
+#[test]
+fn test_noise_gate() {
+    // Measure the bow noise by comparing the output with and without it.  With the noise gate
+    // enabled, there should be about as much noise during the attack as without it, but much less
+    // during a steady sustain.
+
+    let noise_energy = |gate: f32| {
+        let mut outputs = vec![];
+        for bow_noise in [0.0, 1.0] {
+            let (sender, receiver) = mpsc::channel();
+            let mut director = Director::new_seeded(InstrumentType::Violin, 1, receiver, 0);
+            let _ = sender.send(Message::SetNoiseGate {amount: gate});
+            let _ = sender.send(Message::SetBowNoise {bow_noise});
+            let _ = sender.send(Message::NoteOn {note_index: 67, velocity: 0.5});
+            outputs.push((0..2*SAMPLE_RATE).map(|_| director.generate().0).collect::<Vec<f32>>());
+            assert_eq!(gate, director.get_state().noise_gate);
+        }
+        let energy = |range: std::ops::Range<usize>| range.map(|i| (outputs[1][i]-outputs[0][i]).powi(2)).sum::<f32>();
+        (energy(2000..10000), energy(70000..90000))
+    };
+    let (attack_open, sustain_open) = noise_energy(0.0);
+    let (attack_gated, sustain_gated) = noise_energy(1.0);
+    assert!(attack_gated > 0.5*attack_open);
+    assert!(sustain_gated < 0.01*sustain_open);
+}
+
+#[test]
+fn test_excitation_variation() {
+    // With no variation, every period receives the same excitation, so the level of a sustained
+    // note should be much steadier than with full variation.  It still drifts slightly as the pitch
+    // drifts through the body resonances, so average over several notes.  The overall level should
+    // be similar.
+
+    let measure = |variation: f32| {
+        let mut total_level = 0.0;
+        let mut total_deviation = 0.0;
+        for seed in 0..4 {
+            let (sender, receiver) = mpsc::channel();
+            let mut director = Director::new_seeded(InstrumentType::Violin, 1, receiver, seed);
+            let _ = sender.send(Message::SetExcitationVariation {amount: variation});
+            let _ = sender.send(Message::SetVibrato {vibrato: 0.0});
+            let _ = sender.send(Message::SetBowNoise {bow_noise: 0.0});
+            let _ = sender.send(Message::NoteOn {note_index: 67, velocity: 0.5});
+            let output: Vec<f32> = (0..2*SAMPLE_RATE).map(|_| director.generate().0).collect();
+            assert_eq!(variation, director.get_state().excitation_variation);
+            let levels: Vec<f32> = output[48000..96000].chunks(2400).map(|c| c.iter().map(|x| x*x).sum::<f32>()).collect();
+            let mean = levels.iter().sum::<f32>()/levels.len() as f32;
+            let deviation = (levels.iter().map(|x| (x-mean).powi(2)).sum::<f32>()/levels.len() as f32).sqrt();
+            total_level += mean;
+            total_deviation += deviation/mean;
+        }
+        (total_level, total_deviation)
+    };
+    let (fixed_level, fixed_deviation) = measure(0.0);
+    let (random_level, random_deviation) = measure(1.0);
+    assert!(fixed_deviation < 0.5*random_deviation);
+    assert!((10.0*(fixed_level/random_level).log10()).abs() < 6.0);
+}
+
+#[test]
+fn test_sustain_damping() {
+    // Sustain damping should make a held note gradually darker.  Measure the brightness as the ratio
+    // of the power in the third difference of the signal, which emphasizes high frequencies, to the
+    // power in the signal.  At the start of the note it should be almost unchanged, but later it
+    // should be clearly lower.
+
+    let brightness = |amount: f32| {
+        let (sender, receiver) = mpsc::channel();
+        let mut director = Director::new_seeded(InstrumentType::Violin, 1, receiver, 0);
+        let _ = sender.send(Message::SetSustainDamping {amount});
+        let _ = sender.send(Message::SetBowNoise {bow_noise: 0.0});
+        let _ = sender.send(Message::SetVibrato {vibrato: 0.0});
+        let _ = sender.send(Message::NoteOn {note_index: 67, velocity: 0.5});
+        let output: Vec<f32> = (0..5*SAMPLE_RATE).map(|_| director.generate().0).collect();
+        assert_eq!(amount, director.get_state().sustain_damping);
+        let measure = |range: std::ops::Range<usize>| {
+            let power = range.clone().map(|i| output[i]*output[i]).sum::<f32>();
+            let difference = range.map(|i| (output[i]-3.0*output[i-1]+3.0*output[i-2]-output[i-3]).powi(2)).sum::<f32>();
+            difference/power
+        };
+        (measure(5000..15000), measure(200000..240000))
+    };
+    let (early_constant, late_constant) = brightness(0.0);
+    let (early_damped, late_damped) = brightness(1.0);
+    assert!(early_damped > 0.9*early_constant);
+    assert!(late_damped < 0.75*late_constant);
+}
@@ -0,0 +1,112 @@
+// Copyright 2025 by Peter Eastman
+//
+// This file is part of Viola Ex Machina.
+//
+// Viola Ex Machina is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Lesser General Public License as published by the Free Software Foundation, either
+// version 2.1 of the License, or (at your option) any later version.
+//
+// Viola Ex Machina is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License along with Viola Ex Machina.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use synth::dither::Ditherer;
+use std::f32::consts::PI;
+
+const SAMPLE_RATE: f32 = 48000.0;
+
+/// Process one second of a sine wave and return the input and output after the first half
+/// second, by which point the Ditherer has settled.
+fn process_sine(ditherer: &mut Ditherer, amplitude: f32) -> (Vec<f32>, Vec<f32>) {
+    let f = 2.0*PI*440.0/SAMPLE_RATE;
+    let mut input = vec![];
+    let mut output = vec![];
+    for i in 0..SAMPLE_RATE as usize {
+        let x = amplitude*(f*i as f32).sin();
+        let y = ditherer.process(x);
+        if i >= SAMPLE_RATE as usize/2 {
+            input.push(x);
+            output.push(y);
+        }
+    }
+    (input, output)
+}
+
+/// Compute the RMS of the noise that was added to a signal.
+fn noise_rms(input: &[f32], output: &[f32]) -> f32 {
+    let sum: f32 = input.iter().zip(output).map(|(x, y)| (y-x)*(y-x)).sum();
+    (sum/input.len() as f32).sqrt()
+}
+
+#[test]
+fn test_quiet_signal() {
+    // Dither should be added to every sample of a quiet signal, including near the zero crossings.
+
+    let mut ditherer = Ditherer::new(SAMPLE_RATE, 16);
+    let (input, output) = process_sine(&mut ditherer, 1e-4);
+    let changed = input.iter().zip(&output).filter(|(x, y)| x != y).count();
+    assert!(changed > input.len()*99/100);
+    for (x, y) in input.iter().zip(&output) {
+        if x.abs() < 1e-5 {
+            assert_ne!(x, y);
+        }
+    }
+}
+
+#[test]
+fn test_loud_signal() {
+    // A signal above the threshold should be unchanged, even where it crosses zero.
+
+    let mut ditherer = Ditherer::new(SAMPLE_RATE, 16);
+    let (input, output) = process_sine(&mut ditherer, 0.01);
+    assert_eq!(input, output);
+
+    // Between the thresholds for switching on and off, dither should stay in whatever state
+    // it was already in.
+
+    let (input, output) = process_sine(&mut ditherer, 1.5e-3);
+    assert_eq!(input, output);
+    process_sine(&mut ditherer, 1e-4);
+    let (input, output) = process_sine(&mut ditherer, 1.5e-3);
+    let changed = input.iter().zip(&output).filter(|(x, y)| x != y).count();
+    assert!(changed > input.len()*99/100);
+}
+
+#[test]
+fn test_silence() {
+    // Silence should remain exact zeros, including after a quiet signal ends.
+
+    let mut ditherer = Ditherer::new(SAMPLE_RATE, 16);
+    process_sine(&mut ditherer, 1e-4);
+    let mut tail = vec![];
+    for _ in 0..SAMPLE_RATE as usize {
+        tail.push(ditherer.process(0.0));
+    }
+    assert!(tail[..SAMPLE_RATE as usize/2].iter().any(|&y| y != 0.0));
+    assert!(tail[SAMPLE_RATE as usize/2..].iter().all(|&y| y == 0.0));
+
+    // The level should have decayed to exactly zero, not to a denormal.
+
+    assert_eq!(0.0, ditherer.get_level());
+}
+
+#[test]
+fn test_level() {
+    // The noise should be one least significant bit at the specified bit depth.  The difference of
+    // two uniform random numbers has a standard deviation of 1/sqrt(6).
+
+    for bits in [16, 24] {
+        let lsb = 2.0f32.powi(1-bits);
+        let mut ditherer = Ditherer::new(SAMPLE_RATE, bits as u32);
+        let (input, output) = process_sine(&mut ditherer, 1e-4);
+        let rms = noise_rms(&input, &output);
+        let expected = lsb/6.0f32.sqrt();
+        assert!(rms > 0.9*expected && rms < 1.1*expected);
+        for (x, y) in input.iter().zip(&output) {
+            assert!((y-x).abs() < lsb);
+        }
+    }
+}
@@ -13,7 +13,7 @@
 // You should have received a copy of the GNU Lesser General Public License along with Viola Ex Machina.
 // If not, see <https://www.gnu.org/licenses/>.
 
-use synth::filter::{Filter, LowpassFilter, HighpassFilter, BandpassFilter, ResonantFilter};
+use synth::filter::{Filter, LowpassFilter, HighpassFilter, HighShelfFilter, BandpassFilter, PeakingFilter, ResonantFilter, TiltFilter};
 use synth::SAMPLE_RATE;
 use std::f32::consts::PI;
 
@@ -44,6 +44,15 @@ fn test_highpass() {
     assert!(compute_response_amplitude(&mut filter, 4000.0) > 0.5);
 }
 
+#[test]
+fn test_high_shelf() {
+    let mut filter = HighShelfFilter::new(2000.0, 2.0);
+    let y1 = compute_response_amplitude(&mut filter, 100.0);
+    let y2 = compute_response_amplitude(&mut filter, 15000.0);
+    assert!(y1 > 0.95 && y1 < 1.1);
+    assert!(y2 > 1.8 && y2 < 2.05);
+}
+
 #[test]
 fn test_bandpass() {
     let mut filter = BandpassFilter::new(2000.0, 3000.0);
@@ -54,6 +63,32 @@ fn test_bandpass() {
     assert!(y2 > y3);
 }
 
+#[test]
+fn test_peaking() {
+    let mut filter = PeakingFilter::new(2000.0, 5.0, 4.0);
+    let y1 = compute_response_amplitude(&mut filter, 200.0);
+    let y2 = compute_response_amplitude(&mut filter, 2000.0);
+    let y3 = compute_response_amplitude(&mut filter, 15000.0);
+    assert!(y1 > 0.95 && y1 < 1.1);
+    assert!(y2 > 3.9 && y2 < 4.1);
+    assert!(y3 > 0.9 && y3 < 1.1);
+    let mut filter = PeakingFilter::new(2000.0, 5.0, 0.25);
+    let y = compute_response_amplitude(&mut filter, 2000.0);
+    assert!(y > 0.24 && y < 0.26);
+}
+
+#[test]
+fn test_tilt() {
+    let mut filter = TiltFilter::new(1000.0, 2.0);
+    let y1 = compute_response_amplitude(&mut filter, 50.0);
+    let y2 = compute_response_amplitude(&mut filter, 15000.0);
+    assert!(y1 > 0.48 && y1 < 0.55);
+    assert!(y2 > 1.9 && y2 < 2.05);
+    let mut filter = TiltFilter::new(1000.0, 1.0);
+    let y = compute_response_amplitude(&mut filter, 3000.0);
+    assert!(y > 0.99 && y < 1.01);
+}
+
 #[test]
 fn test_resonant() {
     let mut filter = ResonantFilter::new(2000.0, 1000.0);
@@ -0,0 +1,607 @@
+// Copyright 2026 by Peter Eastman
+//
+// This file is part of Viola Ex Machina.
+//
+// Viola Ex Machina is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Lesser General Public License as published by the Free Software Foundation, either
+// version 2.1 of the License, or (at your option) any later version.
+//
+// Viola Ex Machina is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License along with Viola Ex Machina.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use synth::instrument::{Instrument, fft_failed, random_excitation};
+use synth::random::Random;
+use synth::{Articulation, DebugTap, InstrumentType, VibratoShape, SAMPLE_RATE};
+use realfft::RealFftPlanner;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+/// An allocator that counts the allocations made by each thread, so tests can check that an
+/// operation does not allocate.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const {Cell::new(0)};
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get()+1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Get the number of allocations the current thread has made.
+fn allocation_count() -> usize {
+    ALLOCATIONS.with(|count| count.get())
+}
+
+#[test]
+fn test_excitation_mean() {
+    // The excitation should have zero mean and the same variance for any coherence.
+
+    let mut random = Random::new();
+    for coherence in [0.0, 0.5, 1.0] {
+        let mut sum_re = 0.0;
+        let mut sum_im = 0.0;
+        let mut sum_sq = 0.0;
+        let mut sum_cross = 0.0;
+        let n = 100000;
+        for _ in 0..n {
+            let x = random_excitation(&mut random, coherence);
+            sum_re += x.re;
+            sum_im += x.im;
+            sum_sq += x.norm_sqr();
+            sum_cross += x.re*x.im;
+        }
+        assert!((sum_re/n as f32).abs() < 0.01);
+        assert!((sum_im/n as f32).abs() < 0.01);
+        assert!((sum_sq/n as f32 - 2.0/3.0).abs() < 0.01);
+
+        // The correlation between the real and imaginary parts should match the coherence.
+
+        assert!((3.0*sum_cross/n as f32 - coherence).abs() < 0.02);
+    }
+}
+
+#[test]
+fn test_vibrato_shape() {
+    // All shapes should reach their extremes at the same phases, but differ in between.
+
+    for shape in [VibratoShape::Sine, VibratoShape::Cubed, VibratoShape::Triangle] {
+        assert!(shape.evaluate(0.0).abs() < 1e-5);
+        assert!((shape.evaluate(0.25)-1.0).abs() < 1e-5);
+        assert!((shape.evaluate(0.75)+1.0).abs() < 1e-5);
+        assert!((shape.evaluate(1.125)-shape.evaluate(0.125)).abs() < 1e-5);
+    }
+    assert!((VibratoShape::Sine.evaluate(0.125)-0.5f32.sqrt()).abs() < 1e-5);
+    assert!((VibratoShape::Cubed.evaluate(0.125)-0.5f32.powf(1.5)).abs() < 1e-5);
+    assert!((VibratoShape::Triangle.evaluate(0.125)-0.5).abs() < 1e-5);
+}
+
+#[test]
+fn test_vibrato_phase_reset() {
+    // With phase reset enabled, the vibrato should start from the beginning on each new note.
+    // Otherwise it continues from wherever it was.
+
+    let mut planner = RealFftPlanner::<f32>::new();
+    for reset in [false, true] {
+        let mut instrument = Instrument::new_seeded(InstrumentType::Violin, 0, 0);
+        instrument.set_vibrato_phase_reset(reset);
+        instrument.set_vibrato_amplitude(0.004);
+        instrument.note_on(60, Articulation::Arco);
+        for _ in 0..10000 {
+            instrument.generate(&mut planner);
+        }
+        let phase_before = instrument.get_vibrato_phase();
+        assert!(phase_before > 0.1);
+        instrument.note_on(64, Articulation::Arco);
+        for _ in 0..200 {
+            instrument.generate(&mut planner);
+        }
+        let phase_after = instrument.get_vibrato_phase();
+        if reset {
+            assert!(phase_after < 0.05);
+        }
+        else {
+            assert!(phase_after > phase_before);
+        }
+    }
+}
+
+#[test]
+fn test_retrigger_same_note() {
+    // Repeating a note should only create a tail when retriggering is enabled.
+
+    let mut planner = RealFftPlanner::<f32>::new();
+    for retrigger in [false, true] {
+        let mut instrument = Instrument::new_seeded(InstrumentType::Violin, 0, 0);
+        instrument.set_retrigger_same_note(retrigger);
+        instrument.set_volume(0.5);
+        instrument.note_on(60, Articulation::Arco);
+        for _ in 0..5000 {
+            instrument.generate(&mut planner);
+        }
+        assert_eq!(0, instrument.decaying_note_count());
+        instrument.note_on(60, Articulation::Arco);
+        for _ in 0..1000 {
+            instrument.generate(&mut planner);
+        }
+        assert_eq!(if retrigger {1} else {0}, instrument.decaying_note_count());
+    }
+}
+
+#[test]
+fn test_max_harmonic() {
+    // Limiting the highest excited harmonic should remove most of the high frequency content.
+
+    let mut planner = RealFftPlanner::<f32>::new();
+    let fft = planner.plan_fft_forward(16384);
+    let mut high_fraction = vec![];
+    for ratio in [1.0, 0.1] {
+        let mut instrument = Instrument::new_seeded(InstrumentType::Violin, 0, 0);
+        instrument.set_max_harmonic(ratio);
+        instrument.set_volume(0.5);
+        instrument.set_frequency(440.0*2.0f32.powf((60-69) as f32/12.0));
+        instrument.note_on(60, Articulation::Arco);
+        for _ in 0..8000 {
+            instrument.generate(&mut planner);
+        }
+        let mut input: Vec<f32> = (0..16384).map(|_| instrument.generate(&mut planner)).collect();
+        let mut spectrum = fft.make_output_vec();
+        fft.process(&mut input, &mut spectrum).unwrap();
+        let power: Vec<f32> = spectrum.iter().map(|c| c.norm_sqr()).collect();
+        let cutoff = 5000*16384/48000;
+        let total: f32 = power.iter().sum();
+        assert!(total > 0.0);
+        high_fraction.push(power[cutoff..].iter().sum::<f32>()/total);
+    }
+    assert!(high_fraction[1] < 0.1*high_fraction[0]);
+}
+
+#[test]
+fn test_debug_tap() {
+    // After the bow stops, the normal output keeps ringing but the excitation is silent.  The
+    // spectrum before filtering should differ from the normal output.
+
+    let mut planner = RealFftPlanner::<f32>::new();
+    let mut outputs = vec![];
+    for tap in [DebugTap::Output, DebugTap::PreFilter, DebugTap::Excitation] {
+        let mut instrument = Instrument::new_seeded(InstrumentType::Viola, 0, 0);
+        instrument.set_debug_tap(tap);
+        instrument.set_volume(0.5);
+        instrument.note_on(60, Articulation::Arco);
+        let playing: Vec<f32> = (0..5000).map(|_| instrument.generate(&mut planner)).collect();
+        assert!(playing.iter().any(|&x| x != 0.0));
+        instrument.set_volume(0.0);
+        for _ in 0..1000 {
+            instrument.generate(&mut planner);
+        }
+        let released: f32 = (0..100).map(|_| instrument.generate(&mut planner).abs()).sum();
+        if tap == DebugTap::Excitation {
+            assert!(released < 1e-6);
+        }
+        else {
+            assert!(released > 1e-4);
+        }
+        outputs.push(playing);
+    }
+    assert!(outputs[0] != outputs[1]);
+}
+
+/// Play a note at a fixed frequency and check the bookkeeping for every block: each block's size
+/// must be the whole part of the period plus the carried fraction, the carried fraction must stay
+/// between 0 and 1, and the buffers must be large enough.  The total length of all blocks must
+/// match the total of the periods to within one sample.
+fn check_period_bookkeeping(instrument_type: InstrumentType, frequency: f32) {
+    let mut planner = RealFftPlanner::<f32>::new();
+    let mut instrument = Instrument::new_seeded(instrument_type, 0, 0);
+    instrument.set_volume(0.5);
+    instrument.set_frequency(frequency);
+    instrument.note_on(60, Articulation::Arco);
+    let mut last_state = (instrument.get_period(), instrument.get_period_offset());
+    let mut blocks = 0;
+    let mut total_size = 0;
+    let mut total_period = 0.0;
+    let mut offset = last_state.1;
+    for _ in 0..96000 {
+        instrument.generate(&mut planner);
+        let state = (instrument.get_period(), instrument.get_period_offset());
+        if state == last_state {
+            continue;
+        }
+        let (period, new_offset) = state;
+        let (output_size, spectrum_size) = instrument.get_block_sizes();
+        let (output_length, spectrum_length) = instrument.get_buffer_lengths();
+        assert_eq!((period+offset).floor() as usize, output_size);
+        assert!(new_offset >= 0.0 && new_offset < 1.0);
+        assert_eq!(output_size/2+1, spectrum_size);
+        assert!(output_length >= output_size);
+        assert!(spectrum_length >= spectrum_size);
+        blocks += 1;
+        total_size += output_size;
+        total_period += period as f64;
+        offset = new_offset;
+        last_state = state;
+    }
+
+    // Random drift changes the period slightly from block to block, but on average it should be
+    // close to the ideal value.
+
+    let ideal = instrument.get_internal_sample_rate()/frequency;
+    assert!(blocks > 50);
+    assert!((total_size as f64-total_period).abs() <= 1.0);
+    assert!(((total_size as f64/blocks as f64)/ideal as f64-1.0).abs() < 0.01);
+}
+
+#[test]
+fn test_period_bookkeeping() {
+    check_period_bookkeeping(InstrumentType::Violin, 440.0);
+    check_period_bookkeeping(InstrumentType::Violin, 1234.5);
+    check_period_bookkeeping(InstrumentType::Cello, 65.4);
+    check_period_bookkeeping(InstrumentType::Bass, 41.2);
+}
+
+#[test]
+fn test_attack_noise() {
+    // Attack noise should add energy between the harmonics at the start of the note.  With an amount of zero,
+    // starting it should have no effect.
+
+    let mut planner = RealFftPlanner::<f32>::new();
+    let fft = planner.plan_fft_forward(2048);
+    let mut outputs = vec![];
+    let mut noise_energy = vec![];
+    for (amount, start) in [(0.0, false), (0.0, true), (1.0, true)] {
+        let mut instrument = Instrument::new_seeded(InstrumentType::Cello, 0, 0);
+        instrument.set_attack_noise(amount);
+        instrument.set_volume(0.5);
+        instrument.set_frequency(130.8);
+        instrument.note_on(48, Articulation::Arco);
+        if start {
+            instrument.start_attack_noise(1.0);
+        }
+        let output: Vec<f32> = (0..2048).map(|_| instrument.generate(&mut planner)).collect();
+        let mut input = output.clone();
+        let mut spectrum = fft.make_output_vec();
+        fft.process(&mut input, &mut spectrum).unwrap();
+        let harmonic_spacing = 130.8*2048.0/48000.0;
+        let noise: f32 = spectrum.iter().enumerate().filter(|(i, _)| {
+            let h = *i as f32/harmonic_spacing;
+            (h-h.round()).abs()*harmonic_spacing > 1.5
+        }).map(|(_, c)| c.norm_sqr()).sum();
+        noise_energy.push(noise);
+        outputs.push(output);
+    }
+    assert_eq!(outputs[0], outputs[1]);
+    assert!(noise_energy[2] > 2.0*noise_energy[0]);
+}
+
+#[test]
+fn test_sustain_level() {
+    // Without normalization, high cello notes are much louder than low ones.  Setting a sustain
+    // level should make the amplitude much more consistent across the range.
+
+    let mut planner = RealFftPlanner::<f32>::new();
+    let mut ratio = vec![];
+    for level in [0.0, 1.0] {
+        let mut rms = vec![];
+        for note in [36, 78] {
+            let mut instrument = Instrument::new_seeded(InstrumentType::Cello, 0, 0);
+            instrument.set_sustain_level(level);
+            instrument.set_frequency(440.0*2.0f32.powf((note-69) as f32/12.0));
+            instrument.note_on(note, Articulation::Arco);
+            let mut sum = 0.0;
+            for i in 0..96000 {
+                let x = instrument.generate(&mut planner);
+                if i >= 48000 {
+                    sum += x*x;
+                }
+            }
+            rms.push((sum/48000.0).sqrt());
+        }
+        ratio.push(rms[1]/rms[0]);
+    }
+    assert!(ratio[0] > 3.0);
+    assert!(ratio[1] < 0.5*ratio[0]);
+}
+
+#[test]
+fn test_extreme_notes() {
+    // Notes far outside the MIDI range should not cause overflows or invalid output.
+
+    let mut planner = RealFftPlanner::<f32>::new();
+    for note in [i32::MIN, -1, 128, i32::MAX] {
+        let mut instrument = Instrument::new_seeded(InstrumentType::Violin, 0, 0);
+        instrument.set_volume(0.5);
+        instrument.set_frequency(440.0);
+        instrument.note_on(note, Articulation::Arco);
+        for _ in 0..5000 {
+            assert!(instrument.generate(&mut planner).is_finite());
+        }
+    }
+}
+
+/// Measure the time constant (in samples) over which the tail of a note decays.
+fn measure_tail_time_constant(instrument_type: InstrumentType, rate: f32) -> f32 {
+    let mut planner = RealFftPlanner::<f32>::new();
+    let mut instrument = Instrument::new_seeded(instrument_type, 0, 0);
+    instrument.set_tail_decay(rate);
+    instrument.set_volume(0.5);
+    instrument.set_frequency(220.0);
+    instrument.note_on(57, Articulation::Arco);
+    for _ in 0..10000 {
+        instrument.generate(&mut planner);
+    }
+
+    // Start a silent note so only the tail of the first one is heard.
+
+    instrument.set_volume(0.0);
+    instrument.note_on(60, Articulation::Arco);
+    let window = 2400;
+    let mut energy = [0.0; 2];
+    for i in 0..3*window {
+        let x = instrument.generate(&mut planner);
+        if i < window {
+            energy[0] += x*x;
+        }
+        else if i >= 2*window {
+            energy[1] += x*x;
+        }
+    }
+    2.0*(2*window) as f32/(energy[0]/energy[1]).ln()
+}
+
+#[test]
+fn test_tail_decay() {
+    // The time constant for the decay of tails should be inversely proportional to the rate.
+
+    let default = measure_tail_time_constant(InstrumentType::Violin, 1.0);
+    let slow = measure_tail_time_constant(InstrumentType::Violin, 0.5);
+    let fast = measure_tail_time_constant(InstrumentType::Violin, 2.0);
+    assert!((slow/default-2.0).abs() < 0.4);
+    assert!((default/fast-2.0).abs() < 0.4);
+}
+
+#[test]
+fn test_pitch_drift() {
+    // Pitch drift should wander slowly, stay within a limited range, and average to zero.
+    // Without it, the tuning should not drift at all.
+
+    let mut planner = RealFftPlanner::<f32>::new();
+    for amount in [0.0, 1.0] {
+        let mut instrument = Instrument::new_seeded(InstrumentType::Cello, 0, 2);
+        instrument.set_pitch_drift(amount);
+        instrument.set_volume(0.0);
+        instrument.set_frequency(220.0);
+        let mut drift = vec![];
+        for _ in 0..60*SAMPLE_RATE/480 {
+            for _ in 0..480 {
+                instrument.generate(&mut planner);
+            }
+            drift.push(instrument.get_pitch_drift());
+        }
+        if amount == 0.0 {
+            assert!(drift.iter().all(|&d| d == 0.0));
+            continue;
+        }
+        let mean = drift.iter().sum::<f32>()/drift.len() as f32;
+        let rms = (drift.iter().map(|d| d*d).sum::<f32>()/drift.len() as f32).sqrt();
+        let max = drift.iter().fold(0.0, |max: f32, d| max.max(d.abs()));
+        assert!(rms > 1.0 && rms < 10.0);
+        assert!(max <= 15.0);
+        assert!(mean.abs() < 0.5*rms);
+
+        // Values a quarter second apart should be highly correlated.
+
+        let lag = 25;
+        let mut covariance = 0.0;
+        for i in 0..drift.len()-lag {
+            covariance += (drift[i]-mean)*(drift[i+lag]-mean);
+        }
+        let correlation = covariance/(drift.len()-lag) as f32/(rms*rms-mean*mean);
+        assert!(correlation > 0.8);
+    }
+}
+
+#[test]
+fn test_unison_voices() {
+    // With a single voice the output should be unchanged.  Extra voices should change the sound
+    // while keeping the level about the same, including across a change of note.
+
+    let mut planner = RealFftPlanner::<f32>::new();
+    let mut outputs = vec![];
+    for (count, detune) in [(0, 0.0), (1, 0.0), (3, 10.0)] {
+        let mut instrument = Instrument::new_seeded(InstrumentType::Violin, 0, 5);
+        if count > 0 {
+            instrument.set_unison_voices(count, detune);
+            assert_eq!((count, detune), instrument.get_unison_voices());
+        }
+        instrument.set_frequency(440.0);
+        let mut output = vec![];
+        for i in 0..SAMPLE_RATE {
+            if i == SAMPLE_RATE/2 {
+                instrument.note_on(71, Articulation::Arco);
+                instrument.set_frequency(493.9);
+            }
+            output.push(instrument.generate(&mut planner));
+        }
+        outputs.push(output);
+    }
+    assert_eq!(outputs[0], outputs[1]);
+    assert_ne!(outputs[0], outputs[2]);
+    let rms = |x: &[f32]| (x.iter().map(|v| v*v).sum::<f32>()/x.len() as f32).sqrt();
+    let start = SAMPLE_RATE as usize/10;
+    let ratio = rms(&outputs[2][start..])/rms(&outputs[0][start..]);
+    assert!(ratio > 0.7 && ratio < 1.4);
+}
+
+#[test]
+fn test_inharmonicity() {
+    // Find the frequency of an upper partial from the spectrum of a sustained note.  With
+    // inharmonicity it should be sharp of the exact harmonic.  Without it, the output should be
+    // unchanged.
+
+    let mut planner = RealFftPlanner::<f32>::new();
+    let frequency = 110.0;
+    let partial = 20;
+    let size = 2*SAMPLE_RATE as usize;
+    let mut outputs = vec![];
+    for amount in [None, Some(0.0), Some(1.0)] {
+        let mut instrument = Instrument::new_seeded(InstrumentType::Cello, 0, 3);
+        if let Some(amount) = amount {
+            instrument.set_inharmonicity(amount);
+        }
+        instrument.set_frequency(frequency);
+        for _ in 0..SAMPLE_RATE/2 {
+            instrument.generate(&mut planner);
+        }
+        let output: Vec<f32> = (0..size).map(|_| instrument.generate(&mut planner)).collect();
+        outputs.push(output);
+    }
+    assert_eq!(outputs[0], outputs[1]);
+    let mut shifts = vec![];
+    for output in &outputs[1..] {
+        let fft = planner.plan_fft_forward(size);
+        let mut input = output.clone();
+        let mut spectrum = fft.make_output_vec();
+        fft.process(&mut input, &mut spectrum).unwrap();
+        let bin_width = SAMPLE_RATE as f32/size as f32;
+        let center = partial as f32*frequency;
+        let start = ((center-0.5*frequency)/bin_width) as usize;
+        let end = ((center+0.5*frequency)/bin_width) as usize;
+        let peak = (start..end).max_by(|&a, &b| spectrum[a].norm().total_cmp(&spectrum[b].norm())).unwrap();
+        shifts.push(peak as f32*bin_width-center);
+    }
+    assert!(shifts[0].abs() < 0.05*frequency);
+    assert!(shifts[1] > 0.1*frequency);
+}
+
+#[test]
+fn test_odd_and_even_sizes() {
+    // The output size changes from one period to the next, so it is sometimes odd and sometimes
+    // even.  The inverse FFT has different requirements in the two cases, which matter once the
+    // phases have been rotated.  A period the FFT rejects would be output as silence, so the
+    // rotated output should have the same energy as an identical instrument without rotation,
+    // while still being different from it.
+
+    let mut planner = RealFftPlanner::<f32>::new();
+    for instrument_type in [InstrumentType::Violin, InstrumentType::Viola, InstrumentType::Cello, InstrumentType::Bass] {
+        for frequency in [100.7, 261.6, 443.3] {
+            let mut plain = Instrument::new_seeded(instrument_type, 0, 0);
+            let mut rotated = Instrument::new_seeded(instrument_type, 0, 0);
+            plain.set_frequency(frequency);
+            rotated.set_frequency(frequency);
+            let mut seen = [false, false];
+            let mut plain_energy = 0.0;
+            let mut rotated_energy = 0.0;
+            let mut difference_energy = 0.0;
+            for i in 0..SAMPLE_RATE {
+                if i == 1000 {
+                    rotated.set_phase_decorrelation(1.0);
+                }
+                if i == SAMPLE_RATE/2 {
+                    plain.note_on(64, Articulation::Arco);
+                    rotated.note_on(64, Articulation::Arco);
+                }
+                let expected: f32 = plain.generate(&mut planner);
+                let output: f32 = rotated.generate(&mut planner);
+                assert!(output.is_finite());
+                let (output_size, spectrum_size) = rotated.get_block_sizes();
+                assert_eq!(output_size/2+1, spectrum_size);
+                seen[output_size%2] = true;
+                if i >= SAMPLE_RATE/4 {
+                    plain_energy += expected*expected;
+                    rotated_energy += output*output;
+                    difference_energy += (output-expected)*(output-expected);
+                }
+            }
+            assert_eq!([true, true], seen);
+            assert!(difference_energy > 0.2*plain_energy);
+            assert!(rotated_energy > 0.8*plain_energy && rotated_energy < 1.25*plain_energy);
+        }
+    }
+
+    // None of the transforms should have failed.
+
+    assert!(!fft_failed());
+}
+
+#[test]
+fn test_extreme_frequency() {
+    // Frequencies far outside the playable range, such as those produced by extreme pitch bends,
+    // get clamped.  The buffers should never grow beyond what the lowest allowed frequency (8 Hz)
+    // needs at the highest internal sample rate.
+
+    let max_output_size = 2*SAMPLE_RATE as usize/8 + 1;
+    let mut planner = RealFftPlanner::<f32>::new();
+    for frequency in [0.0, -100.0, 1e-6, 1e9, f32::INFINITY, f32::NAN] {
+        let mut instrument = Instrument::new_seeded(InstrumentType::Violin, 0, 0);
+        instrument.set_volume(0.5);
+        instrument.set_frequency(frequency);
+        instrument.note_on(60, Articulation::Arco);
+        for _ in 0..20000 {
+            let output = instrument.generate(&mut planner);
+            assert!(output.is_finite());
+        }
+        let (output_length, spectrum_length) = instrument.get_buffer_lengths();
+        assert!(output_length <= max_output_size);
+        assert!(spectrum_length <= max_output_size/2+1);
+    }
+}
+
+#[test]
+fn test_set_instrument_type_tails() {
+    // Changing to a type with a different internal sample rate should discard the tails of previous
+    // notes, since they would play at the wrong pitch.  Changing to one with the same rate keeps them.
+
+    let mut planner = RealFftPlanner::<f32>::new();
+    let mut instrument = Instrument::new_seeded(InstrumentType::Viola, 0, 0);
+    instrument.set_volume(0.5);
+    instrument.note_on(60, Articulation::Arco);
+    for _ in 0..5000 {
+        instrument.generate(&mut planner);
+    }
+    instrument.note_on(62, Articulation::Arco);
+    for _ in 0..1000 {
+        instrument.generate(&mut planner);
+    }
+    assert_eq!(1, instrument.decaying_note_count());
+    instrument.set_instrument_type(InstrumentType::Cello);
+    assert_eq!(1, instrument.decaying_note_count());
+    instrument.set_instrument_type(InstrumentType::Violin);
+    assert_eq!(0, instrument.decaying_note_count());
+    assert_eq!(2.0*SAMPLE_RATE as f32, instrument.get_internal_sample_rate());
+}
+
+#[test]
+fn test_fixed_excitation_does_not_allocate() {
+    // With less than full variation, each note chooses a fixed excitation.  Changing the coherence
+    // in the middle of a note causes it to be regenerated.  That should not allocate on the audio
+    // thread.
+
+    let mut planner = RealFftPlanner::<f32>::new();
+    let mut instrument = Instrument::new_seeded(InstrumentType::Cello, 0, 0);
+    instrument.set_excitation_variation(0.5);
+    instrument.set_volume(0.5);
+    instrument.note_on(48, Articulation::Arco);
+    for _ in 0..5000 {
+        instrument.generate(&mut planner);
+    }
+    instrument.set_excitation_coherence(0.5);
+    let allocations = allocation_count();
+    for _ in 0..5000 {
+        instrument.generate(&mut planner);
+    }
+    assert_eq!(allocations, allocation_count());
+}
@@ -0,0 +1,54 @@
+// Copyright 2026 by Peter Eastman
+//
+// This file is part of Viola Ex Machina.
+//
+// Viola Ex Machina is free software: you can redistribute it and/or modify it under the terms
+// of the GNU Lesser General Public License as published by the Free Software Foundation, either
+// version 2.1 of the License, or (at your option) any later version.
+//
+// Viola Ex Machina is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License along with Viola Ex Machina.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use synth::director::{Director, Message};
+use synth::preset::Preset;
+use synth::InstrumentType;
+use std::sync::mpsc;
+
+#[test]
+fn test_default_preset() {
+    // A new Preset should match the settings of a new Director.
+
+    for (instrument_type, count) in [(InstrumentType::Violin, 1), (InstrumentType::Cello, 6)] {
+        let (_sender, receiver) = mpsc::channel();
+        let director = Director::new(instrument_type, count, receiver);
+        assert_eq!(Preset::new(instrument_type, count), Preset::from_state(&director.get_state()));
+    }
+}
+
+#[test]
+fn test_apply_builtin_presets() {
+    // Applying each built-in Preset should produce exactly its settings, while leaving settings
+    // that are not part of Presets unchanged.
+
+    let builtin = Preset::builtin();
+    for (i, (name, preset)) in builtin.iter().enumerate() {
+        assert!(builtin[..i].iter().all(|(other, _)| other != name));
+        let (sender, receiver) = mpsc::channel();
+        let mut director = Director::new_seeded(InstrumentType::Viola, 2, receiver, 0);
+        let _ = sender.send(Message::SetVolume {volume: 0.7});
+        let _ = sender.send(Message::NoteOn {note_index: 60, velocity: 0.8});
+        director.generate();
+        director.apply_preset(preset);
+        let state = director.get_state();
+        assert_eq!(*preset, Preset::from_state(&state));
+        assert_eq!(0.7, state.volume);
+        for _ in 0..1000 {
+            let (left, right) = director.generate();
+            assert!(left.is_finite() && right.is_finite());
+        }
+    }
+}
@@ -90,3 +90,20 @@ fn test_normal_distribution() {
     assert!(c3.abs() < 0.4);
     assert!(c4.abs() < 0.4);
 }
+
+#[test]
+fn test_state_round_trip() {
+    // Restoring a saved state should reproduce the same sequence, even when it is saved
+    // halfway through a pair of normally distributed values.
+
+    let mut rand = Random::with_seed(5);
+    rand.get_normal();
+    let state = rand.state();
+    assert!(state.next_normal.is_some());
+    let expected: Vec<f32> = (0..10).map(|i| if i%3 == 0 {rand.get_uniform()} else {rand.get_normal()}).collect();
+    let mut rand2 = Random::with_seed(17);
+    rand2.set_state(state);
+    let actual: Vec<f32> = (0..10).map(|i| if i%3 == 0 {rand2.get_uniform()} else {rand2.get_normal()}).collect();
+    assert_eq!(expected, actual);
+    assert_eq!(rand.state(), rand2.state());
+}
@@ -13,7 +13,7 @@
 // You should have received a copy of the GNU Lesser General Public License along with Viola Ex Machina.
 // If not, see <https://www.gnu.org/licenses/>.
 
-use synth::resampler::Resampler;
+use synth::resampler::{Resampler, SincResampler};
 use synth::SAMPLE_RATE;
 use std::f32::consts::PI;
 
@@ -52,3 +52,66 @@ fn test_downsample() {
 fn test_upsample() {
     test_for_output_rate(96000);
 }
+
+#[test]
+fn test_from_rate() {
+    // Converting input at another rate to the native rate should produce one second of output
+    // for each second of input.
+
+    for input_rate in [44100, 96000] {
+        let mut resampler = Resampler::from_rate(input_rate as f32);
+        let mut output = Vec::new();
+        for i in 0..input_rate {
+            while resampler.has_output() {
+                output.push(resampler.get_output());
+            }
+            resampler.add_input(i as f32/input_rate as f32);
+        }
+        assert!((output.len() as i32 - SAMPLE_RATE).abs() < 2);
+        for i in 0..output.len() {
+            let expected = i as f32/SAMPLE_RATE as f32;
+            assert!((output[i]-expected).abs() < 0.001);
+        }
+    }
+}
+
+fn test_sinc_for_output_rate(output_rate: usize) {
+    let input_rate = SAMPLE_RATE;
+    let frequency = 1000.0;
+    let mut resampler = SincResampler::new(output_rate as f32);
+    let mut output = Vec::new();
+
+    // Generate one second of a sine wave at the input rate.
+
+    for i in 0..input_rate {
+        while resampler.has_output() {
+            output.push(resampler.get_output());
+        }
+        resampler.add_input((2.0*PI*frequency*(i as f32)/input_rate as f32).sin());
+    }
+
+    // The filter introduces a fixed latency, so the number of output samples should be less than
+    // the output rate by about the reported latency.
+
+    assert!(output.len() < output_rate);
+    assert!((output_rate-output.len()).abs_diff(resampler.latency() as usize) <= 2);
+
+    // The output should be the same sine wave sampled at the output rate.  The first input
+    // sample corresponds to time 1.  Skip the beginning, where the filter is still filling.
+
+    for i in 100..output.len() {
+        let time = (i as f32*input_rate as f32/output_rate as f32 - 1.0)/input_rate as f32;
+        let expected = (2.0*PI*frequency*time).sin();
+        assert!((output[i]-expected).abs() < 0.01);
+    }
+}
+
+#[test]
+fn test_sinc_downsample() {
+    test_sinc_for_output_rate(44100);
+}
+
+#[test]
+fn test_sinc_upsample() {
+    test_sinc_for_output_rate(96000);
+}
@@ -40,13 +40,51 @@ fn test_reverb() {
         }
     }
 
-    // See if the reverb produces the correct sequence.
+    // See if the reverb produces the correct sequence, both with the default partitioning
+    // and with a variety of custom ones.  The last one is large enough that the whole IR
+    // is convolved directly.
 
     let mut fft_planner = RealFftPlanner::<f32>::new();
-    let mut reverb = Reverb::new(&ir, &mut fft_planner);
-    for i in 0..expected.len() {
-        let x = if i < input.len() {input[i]} else {0.0};
-        let output = reverb.process(x);
-        assert!((expected[i]-output).abs() < 1e-4);
+    let mut reverbs = vec![Reverb::new(&ir, &mut fft_planner)];
+    for (direct_width, max_block_width) in [(1, 2), (4, 16), (16, 32), (8, 100), (32, 64), (256, 512)] {
+        reverbs.push(Reverb::with_partitions(&ir, direct_width, max_block_width, &mut fft_planner));
+    }
+    for reverb in reverbs.iter_mut() {
+        for i in 0..expected.len() {
+            let x = if i < input.len() {input[i]} else {0.0};
+            let output = reverb.process(x);
+            assert!((expected[i]-output).abs() < 1e-4);
+        }
+    }
+}
+
+#[test]
+fn test_denormals_flushed() {
+    // Create an IR whose tail decays until it is far too small to represent as a normal number.
+
+    let mut random = Random::new();
+    let mut ir = vec![];
+    for i in 0..8192 {
+        ir.push(random.get_normal()*(-(i as f32)/40.0).exp());
+    }
+    assert!(ir.iter().any(|x| *x != 0.0 && !x.is_normal()));
+
+    // Send an impulse through it.  The output follows the IR down into the denormal range, but
+    // it should produce exact zeros instead of denormals.  Try it both with the default partitioning
+    // and with the whole IR convolved directly.
+
+    let mut fft_planner = RealFftPlanner::<f32>::new();
+    let reverbs = vec![Reverb::new(&ir, &mut fft_planner), Reverb::with_partitions(&ir, ir.len(), ir.len(), &mut fft_planner)];
+    for mut reverb in reverbs {
+        let mut nonzero = 0;
+        for i in 0..2*ir.len() {
+            let x = if i == 0 {1.0} else {0.0};
+            let output = reverb.process(x);
+            assert!(output == 0.0 || output.is_normal());
+            if output != 0.0 {
+                nonzero += 1;
+            }
+        }
+        assert!(nonzero > 1000);
     }
 }